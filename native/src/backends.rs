@@ -0,0 +1,202 @@
+use librespot::playback::{
+    audio_backend::{self, SinkBuilder, BACKENDS},
+    config::AudioFormat,
+};
+use rodio::cpal::{
+    self,
+    traits::{DeviceTrait, HostTrait},
+};
+
+use crate::{
+    capture_sink::CAPTURE_BACKEND,
+    config_reader::{ConfigReader, IssueKind},
+    error::{ErrorCode, NativeError},
+    pcm_sink::JS_BACKEND,
+};
+
+const RODIO_BACKEND: &str = "rodio";
+const SUBPROCESS_BACKEND: &str = "subprocess";
+
+/// Backend the sink is opened with. An empty name selects librespot's
+/// default backend, the device is passed on to it as is and only checked
+/// for rodio
+#[derive(Clone)]
+pub struct BackendConfig {
+    pub name: String,
+    pub device: Option<String>,
+    pub format: AudioFormat,
+}
+
+// Formats a backend can open its sink with, `None` if it takes any
+fn supported_formats(backend: &str) -> Option<&'static [AudioFormat]> {
+    match backend {
+        RODIO_BACKEND => Some(&[AudioFormat::F32, AudioFormat::S16]),
+        JS_BACKEND => Some(&[
+            AudioFormat::F64,
+            AudioFormat::F32,
+            AudioFormat::S32,
+            AudioFormat::S24,
+            AudioFormat::S16,
+        ]),
+        _ => None,
+    }
+}
+
+pub struct DeviceInfo {
+    pub name: String,
+    pub is_default: bool,
+}
+
+pub struct BackendInfo {
+    pub name: String,
+    pub is_default: bool,
+    /// Only listed for backends that open a sound card, the device of the
+    /// others is free-form, e.g. a file path for pipe
+    pub devices: Vec<DeviceInfo>,
+}
+
+fn rodio_devices() -> Result<Vec<DeviceInfo>, NativeError> {
+    let host = cpal::default_host();
+    let default_name = host.default_output_device().and_then(|d| d.name().ok());
+
+    let devices = host.output_devices().map_err(|err| {
+        NativeError::new(ErrorCode::Unavailable, "Failed to list output devices").with_cause(err)
+    })?;
+
+    Ok(devices
+        .filter_map(|device| device.name().ok())
+        .map(|name| DeviceInfo {
+            is_default: default_name.as_ref() == Some(&name),
+            name,
+        })
+        .collect())
+}
+
+/// Backends compiled into librespot, followed by the ones provided here
+pub fn list_backends() -> Result<Vec<BackendInfo>, NativeError> {
+    let mut backends = vec![];
+    for (i, (name, _)) in BACKENDS.iter().enumerate() {
+        let devices = if *name == RODIO_BACKEND {
+            rodio_devices()?
+        } else {
+            vec![]
+        };
+
+        backends.push(BackendInfo {
+            name: name.to_string(),
+            is_default: i == 0,
+            devices,
+        });
+    }
+
+    for name in [JS_BACKEND, CAPTURE_BACKEND] {
+        backends.push(BackendInfo {
+            name: name.to_string(),
+            is_default: false,
+            devices: vec![],
+        });
+    }
+
+    Ok(backends)
+}
+
+impl BackendConfig {
+    fn is_internal(&self) -> bool {
+        self.name == JS_BACKEND || self.name == CAPTURE_BACKEND
+    }
+
+    /// Checks the backend exists and can open the device with the format,
+    /// librespot panics on the player thread otherwise. Problems are reported
+    /// to `reader`, failing to list the devices to check against is an error
+    pub fn validate(&self, reader: &mut ConfigReader) -> Result<(), NativeError> {
+        let names: Vec<&str> = BACKENDS
+            .iter()
+            .map(|(name, _)| *name)
+            .chain([JS_BACKEND, CAPTURE_BACKEND])
+            .collect();
+        if !self.name.is_empty() && !names.contains(&self.name.as_str()) {
+            reader.issue(
+                "backend".to_string(),
+                IssueKind::UnknownValue,
+                format!("must be one of {}, got \"{}\"", names.join(", "), self.name),
+            );
+            return Ok(());
+        }
+
+        let name = match self.name.as_str() {
+            "" => BACKENDS.first().map(|(name, _)| *name).unwrap_or_default(),
+            name => name,
+        };
+
+        if let Some(formats) = supported_formats(name) {
+            if !formats.contains(&self.format) {
+                reader.issue(
+                    "format".to_string(),
+                    IssueKind::UnknownValue,
+                    format!("{:?} is not supported by the {} backend", self.format, name),
+                );
+            }
+        }
+
+        if self.is_internal() {
+            if self.device.is_some() {
+                reader.issue(
+                    "device".to_string(),
+                    IssueKind::UnknownValue,
+                    format!("can't be selected with the {} backend", name),
+                );
+            }
+            return Ok(());
+        }
+
+        match (name, &self.device) {
+            (SUBPROCESS_BACKEND, None) => reader.issue(
+                "device".to_string(),
+                IssueKind::Missing,
+                "is required by the subprocess backend, as the command to run",
+            ),
+            // Only rodio can list its devices, the others get the device as is
+            (RODIO_BACKEND, Some(device)) => {
+                if !rodio_devices()?.iter().any(|d| &d.name == device) {
+                    reader.issue(
+                        "device".to_string(),
+                        IssueKind::UnknownValue,
+                        format!("must be an output device, got \"{}\"", device),
+                    );
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Checks a backend applied at runtime keeps the output the player was
+    /// created with, the pcm stream and capture only exist from the start
+    pub fn validate_update(
+        &self,
+        has_pcm_output: bool,
+        has_capture: bool,
+    ) -> Result<(), NativeError> {
+        if (self.name == JS_BACKEND) != has_pcm_output
+            || (self.name == CAPTURE_BACKEND) != has_capture
+        {
+            return Err(NativeError::new(
+                ErrorCode::InvalidConfig,
+                "Cannot switch to or from this backend after the player was created",
+            )
+            .context("backend", &self.name));
+        }
+        Ok(())
+    }
+
+    /// Builder of the librespot sink, `None` for the backends whose sink is
+    /// built by the player itself
+    pub fn sink_builder(&self) -> Option<SinkBuilder> {
+        if self.is_internal() {
+            return None;
+        }
+
+        let name = Some(self.name.clone()).filter(|name| !name.is_empty());
+        audio_backend::find(name)
+    }
+}
@@ -0,0 +1,363 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+const INDEX_FILE: &str = "index.json";
+pub const DEFAULT_CANVAS_SIZE_LIMIT: u64 = 200 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct CacheEntry {
+    file_name: String,
+    url: String,
+    etag: String,
+    // Unix timestamps in seconds
+    expires_at: u64,
+    last_access: u64,
+    size: u64,
+}
+
+/// Canvas media downloaded to disk, keyed by entity uri. Entries stay valid
+/// for the TTL returned by the canvaz service, after which the etag decides
+/// whether the file on disk can be kept
+pub struct CanvasCache {
+    dir: PathBuf,
+    size_limit: u64,
+    index: Mutex<HashMap<String, CacheEntry>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn file_name_for(url: &str) -> String {
+    let hash = hex::encode(Sha1::digest(url.as_bytes()));
+    let extension = Path::new(url.split('?').next().unwrap_or(url))
+        .extension()
+        .and_then(|ext| ext.to_str());
+
+    match extension {
+        Some(ext) => format!("{}.{}", hash, ext),
+        None => hash,
+    }
+}
+
+impl CanvasCache {
+    pub fn new(dir: PathBuf, size_limit: u64) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+
+        let mut index: HashMap<String, CacheEntry> = fs::read(dir.join(INDEX_FILE))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+
+        // Files may have been removed behind our back
+        index.retain(|_, entry| dir.join(&entry.file_name).is_file());
+
+        Ok(Self {
+            dir,
+            size_limit,
+            index: Mutex::new(index),
+        })
+    }
+
+    pub fn size_limit(&self) -> u64 {
+        self.size_limit
+    }
+
+    fn touch(&self, index: &mut HashMap<String, CacheEntry>, entity_uri: &str) -> Option<PathBuf> {
+        let entry = index.get_mut(entity_uri)?;
+        let path = self.dir.join(&entry.file_name);
+        if !path.is_file() {
+            index.remove(entity_uri);
+            return None;
+        }
+
+        entry.last_access = now();
+        Some(path)
+    }
+
+    /// Cached file that is still within its TTL
+    pub fn get_fresh(&self, entity_uri: &str) -> Option<PathBuf> {
+        let mut index = self.index.lock().unwrap();
+        if index.get(entity_uri)?.expires_at <= now() {
+            return None;
+        }
+        self.touch(&mut index, entity_uri)
+    }
+
+    /// Cached file regardless of its TTL, used when the network is unavailable
+    pub fn get_stale(&self, entity_uri: &str) -> Option<PathBuf> {
+        let mut index = self.index.lock().unwrap();
+        self.touch(&mut index, entity_uri)
+    }
+
+    /// Extends the TTL of an expired entry if the canvas did not change since
+    /// it was downloaded
+    pub fn revalidate(&self, entity_uri: &str, url: &str, etag: &str, ttl: u64) -> Option<PathBuf> {
+        let mut index = self.index.lock().unwrap();
+        let entry = index.get_mut(entity_uri)?;
+        if entry.url != url || entry.etag != etag {
+            return None;
+        }
+
+        entry.expires_at = now() + ttl;
+        let path = self.touch(&mut index, entity_uri);
+        self.persist(&index);
+        path
+    }
+
+    pub fn insert(
+        &self,
+        entity_uri: &str,
+        url: &str,
+        etag: &str,
+        ttl: u64,
+        data: &[u8],
+    ) -> io::Result<PathBuf> {
+        let size = data.len() as u64;
+        if size > self.size_limit {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "Canvas of {} bytes exceeds the cache limit of {} bytes",
+                    size, self.size_limit
+                ),
+            ));
+        }
+
+        let file_name = file_name_for(url);
+        let path = self.dir.join(&file_name);
+        fs::write(&path, data)?;
+
+        let mut index = self.index.lock().unwrap();
+        let timestamp = now();
+        let previous = index.insert(
+            entity_uri.to_string(),
+            CacheEntry {
+                file_name: file_name.clone(),
+                url: url.to_string(),
+                etag: etag.to_string(),
+                expires_at: timestamp + ttl,
+                last_access: timestamp,
+                size,
+            },
+        );
+
+        if let Some(previous) = previous {
+            self.remove_file_if_unused(&index, &previous.file_name);
+        }
+
+        self.evict(&mut index, entity_uri);
+        self.persist(&index);
+        Ok(path)
+    }
+
+    /// Drops the entry of an entity that no longer has a canvas
+    pub fn remove(&self, entity_uri: &str) {
+        let mut index = self.index.lock().unwrap();
+        if let Some(entry) = index.remove(entity_uri) {
+            self.remove_file_if_unused(&index, &entry.file_name);
+            self.persist(&index);
+        }
+    }
+
+    // Several entities (e.g. tracks of an album) can share the same canvas file
+    fn remove_file_if_unused(&self, index: &HashMap<String, CacheEntry>, file_name: &str) {
+        if !index.values().any(|entry| entry.file_name == file_name) {
+            let _ = fs::remove_file(self.dir.join(file_name));
+        }
+    }
+
+    // Removes the least recently used files until the cache fits its limit,
+    // never evicting the entry that was just added
+    fn evict(&self, index: &mut HashMap<String, CacheEntry>, keep: &str) {
+        let mut sizes: HashMap<&str, (u64, u64)> = HashMap::new();
+        for entry in index.values() {
+            let file = sizes.entry(&entry.file_name).or_insert((entry.size, 0));
+            file.1 = file.1.max(entry.last_access);
+        }
+
+        let mut total: u64 = sizes.values().map(|(size, _)| size).sum();
+        if total <= self.size_limit {
+            return;
+        }
+
+        let keep_file = index.get(keep).map(|entry| entry.file_name.clone());
+        let mut files: Vec<(String, u64, u64)> = sizes
+            .into_iter()
+            .filter(|(file_name, _)| Some(*file_name) != keep_file.as_deref())
+            .map(|(file_name, (size, last_access))| (file_name.to_string(), size, last_access))
+            .collect();
+        files.sort_by_key(|(_, _, last_access)| *last_access);
+
+        for (file_name, size, _) in files {
+            if total <= self.size_limit {
+                break;
+            }
+
+            index.retain(|_, entry| entry.file_name != file_name);
+            let _ = fs::remove_file(self.dir.join(&file_name));
+            total -= size;
+        }
+    }
+
+    fn persist(&self, index: &HashMap<String, CacheEntry>) {
+        let res = serde_json::to_vec(index)
+            .map_err(io::Error::from)
+            .and_then(|data| {
+                let tmp = self.dir.join(format!("{}.tmp", INDEX_FILE));
+                fs::write(&tmp, data)?;
+                fs::rename(tmp, self.dir.join(INDEX_FILE))
+            });
+
+        if let Err(err) = res {
+            warn!("Failed to write canvas cache index: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "canvas-cache-test-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn set_last_access(cache: &CanvasCache, entity_uri: &str, last_access: u64) {
+        let mut index = cache.index.lock().unwrap();
+        index.get_mut(entity_uri).unwrap().last_access = last_access;
+    }
+
+    #[test]
+    fn evicts_least_recently_used_files_first() {
+        let dir = TempDir::new("lru");
+        let cache = CanvasCache::new(dir.0.clone(), 10).unwrap();
+        let a = cache
+            .insert("a", "https://c/a.mp4", "", 60, &[0; 4])
+            .unwrap();
+        let b = cache
+            .insert("b", "https://c/b.mp4", "", 60, &[0; 4])
+            .unwrap();
+        set_last_access(&cache, "a", 2);
+        set_last_access(&cache, "b", 1);
+
+        cache
+            .insert("c", "https://c/c.mp4", "", 60, &[0; 4])
+            .unwrap();
+
+        assert!(cache.get_fresh("a").is_some());
+        assert!(cache.get_fresh("b").is_none());
+        assert!(a.is_file());
+        assert!(!b.is_file());
+    }
+
+    #[test]
+    fn new_entry_is_kept_even_if_least_recently_used() {
+        let dir = TempDir::new("keep");
+        let cache = CanvasCache::new(dir.0.clone(), 10).unwrap();
+        cache
+            .insert("a", "https://c/a.mp4", "", 60, &[0; 4])
+            .unwrap();
+        set_last_access(&cache, "a", u64::MAX);
+
+        cache
+            .insert("b", "https://c/b.mp4", "", 60, &[0; 8])
+            .unwrap();
+
+        assert!(cache.get_fresh("a").is_none());
+        assert!(cache.get_fresh("b").is_some());
+    }
+
+    #[test]
+    fn shared_files_are_counted_and_evicted_together() {
+        let dir = TempDir::new("shared");
+        let cache = CanvasCache::new(dir.0.clone(), 10).unwrap();
+        cache
+            .insert("a1", "https://c/a.mp4", "", 60, &[0; 4])
+            .unwrap();
+        cache
+            .insert("a2", "https://c/a.mp4", "", 60, &[0; 4])
+            .unwrap();
+        cache
+            .insert("b", "https://c/b.mp4", "", 60, &[0; 4])
+            .unwrap();
+        assert!(cache.get_fresh("a1").is_some());
+
+        set_last_access(&cache, "a1", 1);
+        set_last_access(&cache, "a2", 1);
+        set_last_access(&cache, "b", 2);
+        cache
+            .insert("c", "https://c/c.mp4", "", 60, &[0; 4])
+            .unwrap();
+
+        assert!(cache.get_fresh("a1").is_none());
+        assert!(cache.get_fresh("a2").is_none());
+        assert!(cache.get_fresh("b").is_some());
+    }
+
+    #[test]
+    fn rejects_canvases_larger_than_the_limit() {
+        let dir = TempDir::new("limit");
+        let cache = CanvasCache::new(dir.0.clone(), 10).unwrap();
+
+        assert!(cache
+            .insert("a", "https://c/a.mp4", "", 60, &[0; 11])
+            .is_err());
+        assert!(cache.get_stale("a").is_none());
+    }
+
+    #[test]
+    fn expired_entries_are_revalidated_by_etag() {
+        let dir = TempDir::new("etag");
+        let cache = CanvasCache::new(dir.0.clone(), 10).unwrap();
+        cache
+            .insert("a", "https://c/a.mp4", "v1", 0, &[0; 4])
+            .unwrap();
+
+        assert!(cache.get_fresh("a").is_none());
+        assert!(cache.get_stale("a").is_some());
+        assert!(cache.revalidate("a", "https://c/a.mp4", "v2", 60).is_none());
+        assert!(cache.revalidate("a", "https://c/a.mp4", "v1", 60).is_some());
+        assert!(cache.get_fresh("a").is_some());
+    }
+
+    #[test]
+    fn index_is_loaded_from_disk() {
+        let dir = TempDir::new("index");
+        CanvasCache::new(dir.0.clone(), 10)
+            .unwrap()
+            .insert("a", "https://c/a.mp4", "", 60, &[0; 4])
+            .unwrap();
+
+        let cache = CanvasCache::new(dir.0.clone(), 10).unwrap();
+        assert!(cache.get_fresh("a").is_some());
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use librespot::{
+    core::SpotifyId,
+    playback::{
+        audio_backend::{Sink, SinkError, SinkResult},
+        convert::Converter,
+        decoder::AudioPacket,
+        player::{PlayerEvent, PlayerEventChannel},
+        NUM_CHANNELS, SAMPLE_RATE,
+    },
+};
+
+pub const CAPTURE_BACKEND: &str = "capture";
+
+/// Audio captured for a track from the position it was loaded or seeked to,
+/// until the next track or seek. Timestamps are milliseconds since the
+/// capture was created or last cleared
+#[derive(Clone)]
+pub struct CapturedSegment {
+    pub track_id: Option<SpotifyId>,
+    pub start_position_ms: u32,
+    /// Interleaved samples, divide by the channel count for frames
+    pub samples: u64,
+    pub peak: f64,
+    pub first_write_ms: Option<u64>,
+    pub last_write_ms: Option<u64>,
+    pub starts: u32,
+    pub stops: u32,
+}
+
+impl CapturedSegment {
+    fn new(track_id: Option<SpotifyId>, start_position_ms: u32) -> Self {
+        Self {
+            track_id,
+            start_position_ms,
+            samples: 0,
+            peak: 0.0,
+            first_write_ms: None,
+            last_write_ms: None,
+            starts: 0,
+            stops: 0,
+        }
+    }
+
+    /// Duration of the captured audio, independent of how long it took to play
+    pub fn duration_ms(&self) -> u64 {
+        self.samples * 1000 / (SAMPLE_RATE as u64 * NUM_CHANNELS as u64)
+    }
+}
+
+struct CaptureState {
+    epoch: Instant,
+    segments: Vec<CapturedSegment>,
+    // Set by player events, the segment is opened by the next sink call so
+    // audio still buffered for the previous position isn't attributed to it
+    pending: Option<(Option<SpotifyId>, u32)>,
+}
+
+impl CaptureState {
+    fn elapsed_ms(&self) -> u64 {
+        self.epoch.elapsed().as_millis() as u64
+    }
+
+    fn current(&mut self) -> &mut CapturedSegment {
+        if let Some((track_id, position_ms)) = self.pending.take() {
+            self.segments
+                .push(CapturedSegment::new(track_id, position_ms));
+        }
+        if self.segments.is_empty() {
+            self.segments.push(CapturedSegment::new(None, 0));
+        }
+        self.segments.last_mut().unwrap()
+    }
+}
+
+/// Records what a player would have played, without an audio device
+pub struct Capture {
+    state: Mutex<CaptureState>,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(CaptureState {
+                epoch: Instant::now(),
+                segments: vec![],
+                pending: None,
+            }),
+        }
+    }
+
+    pub fn segments(&self) -> Vec<CapturedSegment> {
+        self.state.lock().unwrap().segments.clone()
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.epoch = Instant::now();
+        state.segments.clear();
+    }
+
+    fn observe(&self, event: &PlayerEvent) {
+        let mut state = self.state.lock().unwrap();
+        let current_track = state
+            .pending
+            .map(|(track_id, _)| track_id)
+            .or_else(|| state.segments.last().map(|segment| segment.track_id));
+
+        match *event {
+            PlayerEvent::Loading {
+                track_id,
+                position_ms,
+                ..
+            }
+            | PlayerEvent::Seeked {
+                track_id,
+                position_ms,
+                ..
+            } => state.pending = Some((Some(track_id), position_ms)),
+            // Gapless transitions don't load the next track
+            PlayerEvent::Playing {
+                track_id,
+                position_ms,
+                ..
+            } if current_track != Some(Some(track_id)) => {
+                state.pending = Some((Some(track_id), position_ms))
+            }
+            _ => {}
+        }
+    }
+
+    /// Follows the events of the player the capture sink belongs to. The
+    /// thread ends once the player is dropped
+    pub fn follow(self: &Arc<Self>, mut event_channel: PlayerEventChannel) {
+        let capture = self.clone();
+        thread::spawn(move || {
+            while let Some(event) = event_channel.blocking_recv() {
+                capture.observe(&event);
+            }
+        });
+    }
+}
+
+/// Sink of the capture backend. Writes are paced to real time so the player
+/// behaves as it would with a device
+pub struct CaptureSink {
+    capture: Arc<Capture>,
+    started_at: Instant,
+    written: Duration,
+}
+
+impl CaptureSink {
+    pub fn new(capture: Arc<Capture>) -> Self {
+        Self {
+            capture,
+            started_at: Instant::now(),
+            written: Duration::ZERO,
+        }
+    }
+}
+
+impl Sink for CaptureSink {
+    fn start(&mut self) -> SinkResult<()> {
+        self.started_at = Instant::now();
+        self.written = Duration::ZERO;
+
+        self.capture.state.lock().unwrap().current().starts += 1;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> SinkResult<()> {
+        self.capture.state.lock().unwrap().current().stops += 1;
+        Ok(())
+    }
+
+    fn write(&mut self, packet: AudioPacket, _: &mut Converter) -> SinkResult<()> {
+        let samples = packet
+            .samples()
+            .map_err(|err| SinkError::OnWrite(err.to_string()))?;
+
+        {
+            let mut state = self.capture.state.lock().unwrap();
+            let now_ms = state.elapsed_ms();
+            let segment = state.current();
+
+            segment.samples += samples.len() as u64;
+            segment.peak = samples
+                .iter()
+                .fold(segment.peak, |peak, sample| peak.max(sample.abs()));
+            segment.first_write_ms.get_or_insert(now_ms);
+            segment.last_write_ms = Some(now_ms);
+        }
+
+        self.written += Duration::from_secs_f64(
+            samples.len() as f64 / (SAMPLE_RATE as f64 * NUM_CHANNELS as f64),
+        );
+        if let Some(ahead) = self.written.checked_sub(self.started_at.elapsed()) {
+            thread::sleep(ahead);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,431 @@
+use std::{ops::RangeInclusive, str::FromStr};
+
+use log::warn;
+use neon::{
+    prelude::{FunctionContext, Handle, Object},
+    result::NeonResult,
+    types::{
+        JsArray, JsBoolean, JsNull, JsNumber, JsObject, JsString, JsUndefined, JsValue, Value,
+    },
+};
+
+use crate::error::{ErrorCode, NativeError};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IssueKind {
+    Missing,
+    WrongType,
+    UnknownValue,
+    OutOfRange,
+}
+
+impl IssueKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueKind::Missing => "missing",
+            IssueKind::WrongType => "wrong_type",
+            IssueKind::UnknownValue => "unknown_value",
+            IssueKind::OutOfRange => "out_of_range",
+        }
+    }
+}
+
+/// A config value that can't be used, exposed to JS in `error.issues`
+#[derive(Debug)]
+pub struct ConfigIssue {
+    /// Dotted path of the value, e.g. `connectConfig.initialVolume`
+    pub path: String,
+    pub kind: IssueKind,
+    pub message: String,
+}
+
+pub fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+// Left out keys read as undefined, null is treated the same
+fn is_missing(cx: &mut FunctionContext, value: Handle<JsValue>) -> bool {
+    value.is_a::<JsUndefined, _>(cx) || value.is_a::<JsNull, _>(cx)
+}
+
+/// Reads config values, falling back to their default when left out.
+/// Problems are collected instead of thrown so they can all be reported in
+/// a single error by `check`
+#[derive(Default)]
+pub struct ConfigReader {
+    issues: Vec<ConfigIssue>,
+    /// Dotted paths of the keys no value is read from
+    unknown_keys: Vec<String>,
+}
+
+impl ConfigReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn issue(&mut self, path: String, kind: IssueKind, message: impl Into<String>) {
+        // Sections read by several functions report their problems once
+        if self
+            .issues
+            .iter()
+            .any(|issue| issue.path == path && issue.kind == kind)
+        {
+            return;
+        }
+
+        self.issues.push(ConfigIssue {
+            path,
+            kind,
+            message: message.into(),
+        });
+    }
+
+    // Reports the key as missing when it is left out
+    fn require(
+        &mut self,
+        cx: &mut FunctionContext,
+        obj: Handle<JsObject>,
+        path: &str,
+        key: &str,
+    ) -> NeonResult<bool> {
+        let value: Handle<JsValue> = obj.get_value(cx, key)?;
+        if is_missing(cx, value) {
+            self.issue(join_path(path, key), IssueKind::Missing, "is required");
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    // `None` if the key is left out or null, or holds a value of another type
+    fn typed<'a, V: Value>(
+        &mut self,
+        cx: &mut FunctionContext<'a>,
+        obj: Handle<JsObject>,
+        path: &str,
+        key: &str,
+        expected: &str,
+    ) -> NeonResult<Option<Handle<'a, V>>> {
+        let value: Handle<JsValue> = obj.get_value(cx, key)?;
+        if is_missing(cx, value) {
+            return Ok(None);
+        }
+        if !value.is_a::<V, _>(cx) {
+            self.issue(
+                join_path(path, key),
+                IssueKind::WrongType,
+                format!("must be {}", expected),
+            );
+            return Ok(None);
+        }
+        Ok(Some(value.downcast_or_throw::<V, _>(cx)?))
+    }
+
+    pub fn object<'a>(
+        &mut self,
+        cx: &mut FunctionContext<'a>,
+        obj: Handle<JsObject>,
+        path: &str,
+        key: &str,
+    ) -> NeonResult<Option<Handle<'a, JsObject>>> {
+        self.typed::<JsObject>(cx, obj, path, key, "an object")
+    }
+
+    /// Like `object`, reporting the key as missing when it is left out
+    pub fn required_object<'a>(
+        &mut self,
+        cx: &mut FunctionContext<'a>,
+        obj: Handle<JsObject>,
+        path: &str,
+        key: &str,
+    ) -> NeonResult<Option<Handle<'a, JsObject>>> {
+        if !self.require(cx, obj, path, key)? {
+            return Ok(None);
+        }
+        self.object(cx, obj, path, key)
+    }
+
+    pub fn array<'a>(
+        &mut self,
+        cx: &mut FunctionContext<'a>,
+        obj: Handle<JsObject>,
+        path: &str,
+        key: &str,
+    ) -> NeonResult<Option<Handle<'a, JsArray>>> {
+        self.typed::<JsArray>(cx, obj, path, key, "an array")
+    }
+
+    pub fn optional_bool(
+        &mut self,
+        cx: &mut FunctionContext,
+        obj: Handle<JsObject>,
+        path: &str,
+        key: &str,
+    ) -> NeonResult<Option<bool>> {
+        Ok(self
+            .typed::<JsBoolean>(cx, obj, path, key, "a boolean")?
+            .map(|v| v.value(cx)))
+    }
+
+    pub fn bool(
+        &mut self,
+        cx: &mut FunctionContext,
+        obj: Handle<JsObject>,
+        path: &str,
+        key: &str,
+        default: bool,
+    ) -> NeonResult<bool> {
+        Ok(self.optional_bool(cx, obj, path, key)?.unwrap_or(default))
+    }
+
+    pub fn optional_string(
+        &mut self,
+        cx: &mut FunctionContext,
+        obj: Handle<JsObject>,
+        path: &str,
+        key: &str,
+    ) -> NeonResult<Option<String>> {
+        Ok(self
+            .typed::<JsString>(cx, obj, path, key, "a string")?
+            .map(|v| v.value(cx)))
+    }
+
+    /// Like `optional_string`, reporting the key as missing when it is left out
+    pub fn required_string(
+        &mut self,
+        cx: &mut FunctionContext,
+        obj: Handle<JsObject>,
+        path: &str,
+        key: &str,
+    ) -> NeonResult<Option<String>> {
+        if !self.require(cx, obj, path, key)? {
+            return Ok(None);
+        }
+        self.optional_string(cx, obj, path, key)
+    }
+
+    pub fn string(
+        &mut self,
+        cx: &mut FunctionContext,
+        obj: Handle<JsObject>,
+        path: &str,
+        key: &str,
+        default: &str,
+    ) -> NeonResult<String> {
+        Ok(self
+            .optional_string(cx, obj, path, key)?
+            .unwrap_or_else(|| default.to_string()))
+    }
+
+    pub fn optional_number(
+        &mut self,
+        cx: &mut FunctionContext,
+        obj: Handle<JsObject>,
+        path: &str,
+        key: &str,
+        range: RangeInclusive<f64>,
+    ) -> NeonResult<Option<f64>> {
+        let value = match self.typed::<JsNumber>(cx, obj, path, key, "a number")? {
+            Some(value) => value.value(cx),
+            None => return Ok(None),
+        };
+
+        if !range.contains(&value) {
+            self.issue(
+                join_path(path, key),
+                IssueKind::OutOfRange,
+                out_of_range_message(&range),
+            );
+            return Ok(None);
+        }
+        Ok(Some(value))
+    }
+
+    pub fn number(
+        &mut self,
+        cx: &mut FunctionContext,
+        obj: Handle<JsObject>,
+        path: &str,
+        key: &str,
+        default: f64,
+        range: RangeInclusive<f64>,
+    ) -> NeonResult<f64> {
+        Ok(self
+            .optional_number(cx, obj, path, key, range)?
+            .unwrap_or(default))
+    }
+
+    /// One of the `expected` names, parsed with `T::from_str`
+    pub fn choice<T: FromStr>(
+        &mut self,
+        cx: &mut FunctionContext,
+        obj: Handle<JsObject>,
+        path: &str,
+        key: &str,
+        default: T,
+        expected: &[&str],
+    ) -> NeonResult<T> {
+        let value = match self.optional_string(cx, obj, path, key)? {
+            Some(value) => value,
+            None => return Ok(default),
+        };
+
+        match T::from_str(&value)
+            .ok()
+            .filter(|_| expected.contains(&value.as_str()))
+        {
+            Some(parsed) => Ok(parsed),
+            None => {
+                self.issue(
+                    join_path(path, key),
+                    IssueKind::UnknownValue,
+                    format!("must be one of {}, got \"{}\"", expected.join(", "), value),
+                );
+                Ok(default)
+            }
+        }
+    }
+
+    /// Logs and collects the keys of `obj` that aren't read from it, most
+    /// likely typos. They don't make the config invalid
+    pub fn warn_unknown_keys(
+        &mut self,
+        cx: &mut FunctionContext,
+        obj: Handle<JsObject>,
+        path: &str,
+        known: &[&str],
+    ) -> NeonResult<()> {
+        let keys = obj.get_own_property_names(cx)?.to_vec(cx)?;
+        for key in keys {
+            if !key.is_a::<JsString, _>(cx) {
+                continue;
+            }
+
+            let key = key.downcast_or_throw::<JsString, _>(cx)?.value(cx);
+            if !known.contains(&key.as_str()) {
+                self.unknown_key(join_path(path, &key));
+            }
+        }
+        Ok(())
+    }
+
+    fn unknown_key(&mut self, path: String) {
+        if self.unknown_keys.contains(&path) {
+            return;
+        }
+
+        warn!("Ignoring unknown config key {}", path);
+        self.unknown_keys.push(path);
+    }
+
+    /// Unknown keys found so far, reported to JS with a ConfigWarning event
+    pub fn take_unknown_keys(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.unknown_keys)
+    }
+
+    /// Throws the problems found so far as one INVALID_CONFIG error
+    pub fn check(&mut self, cx: &mut FunctionContext) -> NeonResult<()> {
+        if self.issues.is_empty() {
+            return Ok(());
+        }
+
+        let issues = std::mem::take(&mut self.issues);
+        let summary = issues
+            .iter()
+            .map(|issue| format!("{} {}", issue.path, issue.message))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        NativeError::new(
+            ErrorCode::InvalidConfig,
+            format!("Invalid config: {}", summary),
+        )
+        .with_issues(issues)
+        .throw(cx)
+    }
+}
+
+fn out_of_range_message(range: &RangeInclusive<f64>) -> String {
+    match (*range.start() > f64::MIN, *range.end() < f64::MAX) {
+        (true, true) => format!("must be between {} and {}", range.start(), range.end()),
+        (true, false) => format!("must be at least {}", range.start()),
+        (false, true) => format!("must be at most {}", range.end()),
+        (false, false) => "must be a finite number".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_joined_with_dots() {
+        assert_eq!(join_path("", "bitrate"), "bitrate");
+        assert_eq!(
+            join_path("connectConfig", "initialVolume"),
+            "connectConfig.initialVolume"
+        );
+    }
+
+    #[test]
+    fn issues_are_collected_once_per_path_and_kind() {
+        let mut reader = ConfigReader::new();
+        reader.issue("auth".to_string(), IssueKind::Missing, "is required");
+        reader.issue("auth".to_string(), IssueKind::Missing, "is required");
+        reader.issue(
+            "auth".to_string(),
+            IssueKind::WrongType,
+            "must be an object",
+        );
+        reader.issue(
+            "bitrate".to_string(),
+            IssueKind::UnknownValue,
+            "must be one of 96",
+        );
+
+        let issues: Vec<(&str, IssueKind)> = reader
+            .issues
+            .iter()
+            .map(|issue| (issue.path.as_str(), issue.kind))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                ("auth", IssueKind::Missing),
+                ("auth", IssueKind::WrongType),
+                ("bitrate", IssueKind::UnknownValue),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_collected_once_and_taken() {
+        let mut reader = ConfigReader::new();
+        reader.unknown_key("connectConfig.intialVolume".to_string());
+        reader.unknown_key("bitrte".to_string());
+        reader.unknown_key("connectConfig.intialVolume".to_string());
+
+        assert_eq!(
+            reader.take_unknown_keys(),
+            vec!["connectConfig.intialVolume", "bitrte"]
+        );
+        assert!(reader.take_unknown_keys().is_empty());
+        assert!(reader.issues.is_empty());
+    }
+
+    #[test]
+    fn out_of_range_message_names_the_bounds() {
+        assert_eq!(
+            out_of_range_message(&(0.0..=65535.0)),
+            "must be between 0 and 65535"
+        );
+        assert_eq!(
+            out_of_range_message(&(1.0..=f64::MAX)),
+            "must be at least 1"
+        );
+        assert_eq!(out_of_range_message(&(f64::MIN..=0.0)), "must be at most 0");
+    }
+}
@@ -0,0 +1,53 @@
+use librespot::playback::config::PlayerConfig;
+use neon::{
+    prelude::{Channel, Context, Handle},
+    types::{Deferred, JsUndefined},
+};
+
+use crate::{
+    backends::BackendConfig, error::NativeError, events::EventListeners,
+    utils::create_js_obj_from_config_changed,
+};
+
+/// Player settings applied at runtime. The player is rebuilt with them while
+/// the playing track, position and volume carry over
+pub struct ConfigUpdate {
+    pub player_config: PlayerConfig,
+    pub backend: BackendConfig,
+    deferred: Deferred,
+}
+
+impl ConfigUpdate {
+    pub fn new(player_config: PlayerConfig, backend: BackendConfig, deferred: Deferred) -> Self {
+        Self {
+            player_config,
+            backend,
+            deferred,
+        }
+    }
+
+    pub fn into_deferred(self) -> Deferred {
+        self.deferred
+    }
+
+    /// Emits ConfigChanged and resolves the update
+    pub fn applied(self, channel: &Channel, listeners: &EventListeners) {
+        let Self {
+            player_config,
+            backend,
+            deferred,
+        } = self;
+
+        listeners.emit(channel, move |cx| {
+            create_js_obj_from_config_changed(cx, player_config, backend)
+        });
+        deferred.settle_with(channel, move |mut cx| Ok(cx.undefined()));
+    }
+
+    pub fn failed(self, channel: &Channel, err: NativeError) {
+        self.deferred.settle_with(channel, move |mut cx| {
+            err.prefixed("Failed to apply config")
+                .throw::<_, Handle<JsUndefined>>(&mut cx)
+        });
+    }
+}
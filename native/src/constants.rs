@@ -1 +0,0 @@
-pub const GLOBAL_JS_CALLBACK_METHOD: &str = "_watch_player_events_global";
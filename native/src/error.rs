@@ -0,0 +1,351 @@
+use std::{error, fmt, io};
+
+use librespot::{
+    core::{
+        error::ErrorKind, http_client::HttpClientError, session::SessionError,
+        spotify_id::SpotifyIdError, Error,
+    },
+    protocol::keyexchange::{APLoginFailed, ErrorCode as LoginErrorCode},
+};
+use neon::{
+    prelude::{Context, Object},
+    result::{JsResult, NeonResult},
+    types::JsError,
+};
+
+use crate::config_reader::ConfigIssue;
+
+/// Stable error codes exposed to JS as `error.code`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ErrorCode {
+    AuthFailed,
+    PremiumRequired,
+    InvalidUri,
+    InvalidArgument,
+    InvalidConfig,
+    NotFound,
+    Network,
+    Unavailable,
+    RateLimited,
+    ParseError,
+    IoError,
+    PlayerClosed,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::AuthFailed => "AUTH_FAILED",
+            ErrorCode::PremiumRequired => "PREMIUM_REQUIRED",
+            ErrorCode::InvalidUri => "INVALID_URI",
+            ErrorCode::InvalidArgument => "INVALID_ARGUMENT",
+            ErrorCode::InvalidConfig => "INVALID_CONFIG",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::Network => "NETWORK",
+            ErrorCode::Unavailable => "UNAVAILABLE",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::ParseError => "PARSE_ERROR",
+            ErrorCode::IoError => "IO_ERROR",
+            ErrorCode::PlayerClosed => "PLAYER_CLOSED",
+            ErrorCode::Internal => "INTERNAL",
+        }
+    }
+
+    fn from_status(status: u16) -> Self {
+        match status {
+            401 | 403 => ErrorCode::AuthFailed,
+            404 => ErrorCode::NotFound,
+            429 => ErrorCode::RateLimited,
+            500..=599 => ErrorCode::Unavailable,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NativeError {
+    code: ErrorCode,
+    message: String,
+    cause: Option<String>,
+    context: Vec<(&'static str, String)>,
+    issues: Vec<ConfigIssue>,
+}
+
+impl NativeError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            cause: None,
+            context: vec![],
+            issues: vec![],
+        }
+    }
+
+    pub fn player_closed() -> Self {
+        Self::new(ErrorCode::PlayerClosed, "Player is closed")
+    }
+
+    fn from_err(err: impl Into<NativeError>) -> Self {
+        err.into()
+    }
+
+    /// Prepends what was being done when the error occurred to the message
+    pub fn prefixed(mut self, prefix: &str) -> Self {
+        self.message = format!("{}: {}", prefix, self.message);
+        self
+    }
+
+    pub fn with_cause(mut self, cause: impl fmt::Display) -> Self {
+        self.cause = Some(cause.to_string());
+        self
+    }
+
+    pub fn context(mut self, key: &'static str, value: impl ToString) -> Self {
+        self.context.push((key, value.to_string()));
+        self
+    }
+
+    pub fn with_issues(mut self, issues: Vec<ConfigIssue>) -> Self {
+        self.issues = issues;
+        self
+    }
+
+    pub fn to_js<'a, C: Context<'a>>(&self, cx: &mut C) -> JsResult<'a, JsError> {
+        let error = cx.error(&self.message)?;
+
+        let code = cx.string(self.code.as_str());
+        error.set(cx, "code", code)?;
+
+        if let Some(cause) = &self.cause {
+            let cause = cx.error(cause)?;
+            error.set(cx, "cause", cause)?;
+        }
+
+        let context = cx.empty_object();
+        for (key, value) in &self.context {
+            let value = cx.string(value);
+            context.set(cx, *key, value)?;
+        }
+        error.set(cx, "context", context)?;
+
+        if !self.issues.is_empty() {
+            let issues = cx.empty_array();
+            for (i, issue) in self.issues.iter().enumerate() {
+                let obj = cx.empty_object();
+                let path = cx.string(&issue.path);
+                obj.set(cx, "path", path)?;
+                let kind = cx.string(issue.kind.as_str());
+                obj.set(cx, "kind", kind)?;
+                let message = cx.string(&issue.message);
+                obj.set(cx, "message", message)?;
+                issues.set(cx, i as u32, obj)?;
+            }
+            error.set(cx, "issues", issues)?;
+        }
+
+        Ok(error)
+    }
+
+    pub fn throw<'a, C: Context<'a>, T>(&self, cx: &mut C) -> NeonResult<T> {
+        let error = self.to_js(cx)?;
+        cx.throw(error)
+    }
+}
+
+impl fmt::Display for NativeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code.as_str())
+    }
+}
+
+// librespot's authentication error can't be named, its type is taken from the
+// SessionError variant wrapping it
+fn downcast_login_error<'a, T: error::Error + 'static>(
+    err: &'a (dyn error::Error + Send + Sync + 'static),
+    _variant: fn(T) -> SessionError,
+) -> Option<&'a T> {
+    err.downcast_ref::<T>()
+}
+
+// Its variants can't be matched either, so it is compared against the error
+// librespot builds for the login failure with `code`
+fn is_login_failure<T: From<APLoginFailed> + fmt::Debug>(err: &T, code: LoginErrorCode) -> bool {
+    let mut failure = APLoginFailed::new();
+    failure.set_error_code(code);
+    format!("{:?}", err) == format!("{:?}", T::from(failure))
+}
+
+impl From<Error> for NativeError {
+    fn from(err: Error) -> Self {
+        let premium_required =
+            downcast_login_error(err.error.as_ref(), SessionError::AuthenticationError)
+                .map_or(false, |login_err| {
+                    is_login_failure(login_err, LoginErrorCode::PremiumAccountRequired)
+                });
+
+        let code = if let Some(HttpClientError::StatusCode(status)) =
+            err.error.downcast_ref::<HttpClientError>()
+        {
+            ErrorCode::from_status(status.as_u16())
+        } else if err.error.is::<SpotifyIdError>() {
+            ErrorCode::InvalidUri
+        } else if err.error.is::<protobuf::Error>() {
+            ErrorCode::ParseError
+        } else if premium_required {
+            ErrorCode::PremiumRequired
+        } else {
+            match err.kind {
+                ErrorKind::Unauthenticated | ErrorKind::PermissionDenied => ErrorCode::AuthFailed,
+                ErrorKind::InvalidArgument | ErrorKind::OutOfRange | ErrorKind::AlreadyExists => {
+                    ErrorCode::InvalidArgument
+                }
+                ErrorKind::NotFound => ErrorCode::NotFound,
+                ErrorKind::ResourceExhausted => ErrorCode::RateLimited,
+                ErrorKind::Unavailable => ErrorCode::Unavailable,
+                ErrorKind::Cancelled | ErrorKind::Aborted | ErrorKind::DeadlineExceeded => {
+                    ErrorCode::Network
+                }
+                ErrorKind::DataLoss => ErrorCode::ParseError,
+                _ => ErrorCode::Internal,
+            }
+        };
+
+        NativeError::new(code, err.to_string())
+            .with_cause(&err.error)
+            .context("kind", format!("{:?}", err.kind))
+    }
+}
+
+impl From<reqwest::Error> for NativeError {
+    fn from(err: reqwest::Error) -> Self {
+        let code = match err.status() {
+            Some(status) => ErrorCode::from_status(status.as_u16()),
+            None if err.is_decode() => ErrorCode::ParseError,
+            None => ErrorCode::Network,
+        };
+
+        let mut native_error = NativeError::new(code, err.to_string());
+        if let Some(url) = err.url() {
+            native_error = native_error.context("url", url);
+        }
+        native_error
+    }
+}
+
+impl From<protobuf::Error> for NativeError {
+    fn from(err: protobuf::Error) -> Self {
+        NativeError::new(ErrorCode::ParseError, err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for NativeError {
+    fn from(err: serde_json::Error) -> Self {
+        NativeError::new(ErrorCode::ParseError, err.to_string())
+    }
+}
+
+impl From<io::Error> for NativeError {
+    fn from(err: io::Error) -> Self {
+        NativeError::new(ErrorCode::IoError, err.to_string())
+            .context("kind", format!("{:?}", err.kind()))
+    }
+}
+
+pub trait ResultExt<T> {
+    fn context(self, key: &'static str, value: impl ToString) -> Result<T, NativeError>;
+
+    fn prefixed(self, prefix: &str) -> Result<T, NativeError>;
+
+    /// Throws the error as a JS Error carrying its code, cause and context
+    fn or_throw<'a, C: Context<'a>>(self, cx: &mut C) -> NeonResult<T>;
+}
+
+impl<T, E: Into<NativeError>> ResultExt<T> for Result<T, E> {
+    fn context(self, key: &'static str, value: impl ToString) -> Result<T, NativeError> {
+        self.map_err(|err| NativeError::from_err(err).context(key, value))
+    }
+
+    fn prefixed(self, prefix: &str) -> Result<T, NativeError> {
+        self.map_err(|err| NativeError::from_err(err).prefixed(prefix))
+    }
+
+    fn or_throw<'a, C: Context<'a>>(self, cx: &mut C) -> NeonResult<T> {
+        match self {
+            Ok(value) => Ok(value),
+            Err(err) => NativeError::from_err(err).throw(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use librespot::core::SpotifyId;
+
+    use super::*;
+
+    fn login_failure<T: From<APLoginFailed>>(
+        _variant: fn(T) -> SessionError,
+        code: LoginErrorCode,
+    ) -> T {
+        let mut failure = APLoginFailed::new();
+        failure.set_error_code(code);
+        T::from(failure)
+    }
+
+    fn code_of(err: impl Into<NativeError>) -> ErrorCode {
+        err.into().code
+    }
+
+    #[test]
+    fn premium_login_failure_is_premium_required() {
+        let err = login_failure(
+            SessionError::AuthenticationError,
+            LoginErrorCode::PremiumAccountRequired,
+        );
+        assert_eq!(
+            code_of(Error::permission_denied(err)),
+            ErrorCode::PremiumRequired
+        );
+    }
+
+    #[test]
+    fn other_login_failures_are_auth_failed() {
+        let err = login_failure(
+            SessionError::AuthenticationError,
+            LoginErrorCode::BadCredentials,
+        );
+        assert_eq!(
+            code_of(Error::permission_denied(err)),
+            ErrorCode::AuthFailed
+        );
+    }
+
+    #[test]
+    fn invalid_spotify_id_is_invalid_uri() {
+        let err = SpotifyId::from_uri("spotify:track:invalid").unwrap_err();
+        assert_eq!(code_of(err), ErrorCode::InvalidUri);
+    }
+
+    #[test]
+    fn error_kinds_map_to_codes() {
+        assert_eq!(code_of(Error::not_found("gone")), ErrorCode::NotFound);
+        assert_eq!(
+            code_of(Error::resource_exhausted("slow down")),
+            ErrorCode::RateLimited
+        );
+        assert_eq!(
+            code_of(Error::deadline_exceeded("timeout")),
+            ErrorCode::Network
+        );
+        assert_eq!(code_of(Error::internal("oops")), ErrorCode::Internal);
+    }
+
+    #[test]
+    fn io_errors_keep_their_kind() {
+        let err: NativeError = io::Error::from(io::ErrorKind::NotFound).into();
+        assert_eq!(err.code, ErrorCode::IoError);
+        assert_eq!(err.context, vec![("kind", "NotFound".to_string())]);
+    }
+}
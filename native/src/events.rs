@@ -0,0 +1,93 @@
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc, Mutex,
+};
+
+use neon::{
+    handle::Root,
+    prelude::{Channel, Context, Handle, Object, TaskContext},
+    result::JsResult,
+    types::{JsFunction, JsObject, JsUndefined},
+};
+
+static NEXT_INSTANCE_ID: AtomicU32 = AtomicU32::new(1);
+
+struct Listeners {
+    instance_id: u32,
+    next_listener_id: u32,
+    callbacks: Vec<(u32, Root<JsFunction>)>,
+}
+
+/// JS callbacks subscribed to the events of a single player instance
+#[derive(Clone)]
+pub struct EventListeners(Arc<Mutex<Listeners>>);
+
+impl EventListeners {
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(Listeners {
+            instance_id: NEXT_INSTANCE_ID.fetch_add(1, Ordering::Relaxed),
+            next_listener_id: 0,
+            callbacks: vec![],
+        })))
+    }
+
+    pub fn instance_id(&self) -> u32 {
+        self.0.lock().unwrap().instance_id
+    }
+
+    pub fn add(&self, callback: Root<JsFunction>) -> u32 {
+        let mut listeners = self.0.lock().unwrap();
+        let id = listeners.next_listener_id;
+        listeners.next_listener_id += 1;
+        listeners.callbacks.push((id, callback));
+        id
+    }
+
+    pub fn remove(&self, id: u32) -> Option<Root<JsFunction>> {
+        let mut listeners = self.0.lock().unwrap();
+        let index = listeners.callbacks.iter().position(|(i, _)| *i == id)?;
+        Some(listeners.callbacks.remove(index).1)
+    }
+
+    /// Releases every callback once the player is closed. They may reference
+    /// the JS object wrapping the player, which could never be collected
+    /// while they are rooted
+    pub fn clear<'a, C: Context<'a>>(&self, cx: &mut C) {
+        let callbacks = std::mem::take(&mut self.0.lock().unwrap().callbacks);
+        for (_, callback) in callbacks {
+            callback.drop(cx);
+        }
+    }
+
+    pub fn emit<F>(&self, channel: &Channel, build: F)
+    where
+        F: for<'a> FnOnce(TaskContext<'a>) -> (Handle<'a, JsObject>, TaskContext<'a>)
+            + Send
+            + 'static,
+    {
+        let listeners = self.0.clone();
+        channel.send(move |cx| {
+            let (obj, mut cx) = build(cx);
+
+            // Listeners may subscribe or unsubscribe from inside a callback,
+            // so the lock can't be held while calling into JS
+            let (instance_id, callbacks) = {
+                let listeners = listeners.lock().unwrap();
+                let callbacks: Vec<Handle<JsFunction>> = listeners
+                    .callbacks
+                    .iter()
+                    .map(|(_, callback)| callback.to_inner(&mut cx))
+                    .collect();
+                (listeners.instance_id, callbacks)
+            };
+
+            let instance_id = cx.number(instance_id);
+            obj.set(&mut cx, "instance_id", instance_id)?;
+
+            for callback in callbacks {
+                let _: JsResult<JsUndefined> = callback.call_with(&cx).arg(obj).apply(&mut cx);
+            }
+            Ok(())
+        });
+    }
+}
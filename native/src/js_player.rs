@@ -1,65 +1,138 @@
 use std::{
     sync::{
         mpsc::{self, Receiver},
-        Arc,
+        Arc, Mutex, Weak,
     },
-    thread,
+    thread::{self, JoinHandle},
+    time::Duration,
 };
 
 use librespot::{
-    core::Error,
-    core::{cache::Cache, Session},
+    core::{cache::Cache, config::SessionConfig, Session},
     discovery::Credentials,
     playback::{
         config::PlayerConfig,
         mixer::Mixer,
-        player::{Player, PlayerEventChannel},
+        player::{Player, PlayerEvent, PlayerEventChannel},
     },
 };
+use log::warn;
 use neon::{
-    prelude::{Channel, Context, Handle, Object},
-    result::JsResult,
-    types::{Deferred, Finalize, JsFunction, JsUndefined},
+    prelude::{Channel, Context},
+    types::{Deferred, Finalize},
+};
+use tokio::{
+    runtime::Builder,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedSender},
+        oneshot,
+    },
 };
-use tokio::runtime::Builder;
 
 use crate::{
-    constants::GLOBAL_JS_CALLBACK_METHOD,
-    player::{create_session, new_player},
-    utils::create_js_obj_from_event,
+    backends::BackendConfig,
+    canvas_cache::CanvasCache,
+    capture_sink::Capture,
+    config_update::ConfigUpdate,
+    error::NativeError,
+    events::EventListeners,
+    pcm_sink::PcmOutput,
+    player::{connect_session, new_player, MixerSettings, SharedMixer},
+    position::{start_position_updates, PositionTracker, SharedPosition},
+    queue::{PlayQueue, QueueState},
+    reconnect::{reconnect, wait_for_disconnect, ReconnectConfig, ReconnectOutcome, Resume},
+    token_cache::{start_token_refresh, TokenCache},
+    utils::{create_js_event_from_queue, create_js_obj_from_credentials, create_js_obj_from_event},
 };
 
-impl Finalize for JsPlayerWrapper {}
+impl Finalize for JsPlayerWrapper {
+    fn finalize<'a, C: Context<'a>>(self, cx: &mut C) {
+        self.close_pcm_output();
+        self.listeners.clear(cx);
+        // Fails if the player was already closed, nothing left to clean up then
+        let _ = self.tx.send(Message::Close(None));
+    }
+}
 
 pub struct JsPlayerWrapper {
     tx: mpsc::Sender<Message>,
     device_id: String,
+    listeners: EventListeners,
+    position: SharedPosition,
+    canvas_cache: Option<Arc<CanvasCache>>,
+    token_cache: Arc<TokenCache>,
+    pcm_output: Option<Arc<PcmOutput>>,
+    capture: Option<Arc<Capture>>,
+    mixer: SharedMixer,
+    update_tx: UnboundedSender<ConfigUpdate>,
 }
 
-pub type Callback =
-    Box<dyn (FnOnce(&mut Arc<Player>, &mut Arc<dyn Mixer>, Session, &Channel, Deferred)) + Send>;
+pub type Callback = Box<
+    dyn (FnOnce(
+            &mut Arc<Player>,
+            &mut Arc<dyn Mixer>,
+            &Arc<Mutex<PlayQueue>>,
+            Session,
+            &Channel,
+            Deferred,
+        )) + Send,
+>;
 
 pub enum Message {
     Callback(Deferred, Callback),
-    Close,
+    Reattach(Reattach),
+    Close(Option<Deferred>),
 }
 
+/// Player created by the supervisor after reconnecting or applying a config
+/// update, replacing the current one
+pub struct Reattach {
+    player: Arc<Player>,
+    mixer: Arc<dyn Mixer>,
+    session: Session,
+    resume: Resume,
+}
+
+// Sent by the command thread once it has released the player, along with the
+// close promise to resolve after the remaining threads have joined
+type Shutdown = (Option<Deferred>, Channel);
+
 impl JsPlayerWrapper {
     pub fn new<'a, C>(
         cx: &mut C,
+        listeners: EventListeners,
+        position_interval: Duration,
+        reconnect_config: ReconnectConfig,
+        canvas_cache: Option<Arc<CanvasCache>>,
+        token_cache: Arc<TokenCache>,
         credentials: Credentials,
+        session_config: SessionConfig,
         player_config: PlayerConfig,
         cache_config: Cache,
-        backend: String,
-        volume_ctrl: String,
-    ) -> Result<Self, Error>
+        backend: BackendConfig,
+        pcm_output: Option<Arc<PcmOutput>>,
+        capture: Option<Arc<Capture>>,
+        mixer_settings: MixerSettings,
+    ) -> Result<Self, NativeError>
     where
         C: Context<'a>,
     {
         let (tx, rx) = mpsc::channel::<Message>();
+        // The supervisor holds its own sender, the wrapper is closed on finalize
+        let supervisor_tx = tx.clone();
 
-        let (player_creation_tx, player_creation_rx) = mpsc::channel::<Result<String, Error>>();
-        let (close_tx, close_rx) = mpsc::channel::<()>();
+        let (player_creation_tx, player_creation_rx) =
+            mpsc::channel::<Result<String, NativeError>>();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<Shutdown>();
+        let (update_tx, update_rx) = unbounded_channel::<ConfigUpdate>();
+        let thread_listeners = listeners.clone();
+        let position = Arc::new(Mutex::new(PositionTracker::new()));
+        let thread_position = position.clone();
+        let thread_token_cache = token_cache.clone();
+        let thread_pcm_output = pcm_output.clone();
+        let thread_capture = capture.clone();
+        let mixer: SharedMixer = Arc::new(Mutex::new(None));
+        let thread_mixer = mixer.clone();
 
         let mut commands_channel = cx.channel();
         commands_channel.unref(cx);
@@ -67,6 +140,9 @@ impl JsPlayerWrapper {
         let mut event_callback_channel = cx.channel();
         event_callback_channel.unref(cx);
 
+        let mut position_channel = cx.channel();
+        position_channel.unref(cx);
+
         thread::spawn(move || {
             let runtime = Builder::new_multi_thread()
                 .enable_io()
@@ -74,47 +150,241 @@ impl JsPlayerWrapper {
                 .build()
                 .unwrap();
 
-            runtime.block_on(async {
-                let session = create_session(cache_config).clone();
-                let conn_res = session.connect(credentials, false).await;
-                if conn_res.is_err() {
-                    player_creation_tx
-                        .send(Err(conn_res.err().unwrap()))
-                        .unwrap();
-                    return;
+            let threads = runtime.block_on(async {
+                let conn_res = connect_session(
+                    session_config.clone(),
+                    cache_config.clone(),
+                    credentials.clone(),
+                )
+                .await;
+                let session = match conn_res {
+                    Ok(session) => session,
+                    Err(err) => {
+                        player_creation_tx.send(Err(err.into())).unwrap();
+                        return None;
+                    }
+                };
+
+                // Only available when the cache has a credentials_location
+                if let Some(credentials) = session.cache().and_then(|cache| cache.credentials()) {
+                    thread_listeners.emit(&event_callback_channel, move |cx| {
+                        create_js_obj_from_credentials(cx, "CredentialsStored", credentials)
+                    });
                 }
 
                 let device_id = session.device_id().to_string();
 
-                let (player, mixer) =
-                    new_player(backend, session.clone(), player_config.clone(), volume_ctrl);
+                let res = new_player(
+                    backend.clone(),
+                    session.clone(),
+                    player_config.clone(),
+                    mixer_settings.clone(),
+                    thread_pcm_output.clone(),
+                    thread_capture.clone(),
+                );
+                let (player, mixer) = match res {
+                    Ok(created) => created,
+                    Err(err) => {
+                        session.shutdown();
+                        player_creation_tx.send(Err(err)).unwrap();
+                        return None;
+                    }
+                };
 
-                let events_channel = player.get_player_event_channel();
+                *thread_mixer.lock().unwrap() = Some(mixer.clone());
+                let queue = Arc::new(Mutex::new(PlayQueue::new()));
 
-                JsPlayerWrapper::start_player_event_thread(
-                    event_callback_channel,
-                    events_channel,
-                    close_rx,
+                let mut event_threads = vec![JsPlayerWrapper::start_player_event_thread(
+                    event_callback_channel.clone(),
+                    player.get_player_event_channel(),
+                    Arc::downgrade(&player),
+                    queue.clone(),
+                    thread_position.clone(),
+                    thread_listeners.clone(),
+                )];
+                start_position_updates(
+                    position_interval,
+                    thread_position.clone(),
+                    position_channel,
+                    thread_listeners.clone(),
+                );
+                start_token_refresh(
+                    thread_token_cache.clone(),
+                    session.clone(),
+                    event_callback_channel.clone(),
+                    thread_listeners.clone(),
                 );
-                JsPlayerWrapper::listen_commands(
+                let command_thread = JsPlayerWrapper::listen_commands(
                     rx,
                     player,
                     mixer,
+                    thread_mixer.clone(),
+                    queue.clone(),
                     session.clone(),
-                    close_tx,
+                    shutdown_tx,
                     commands_channel,
                 );
 
                 // Panic thread if send fails
                 player_creation_tx.send(Ok(device_id)).unwrap();
 
-                loop {}
-            })
+                let mut shutdown_rx = shutdown_rx;
+                let mut update_rx = update_rx;
+                let mut session = session;
+                let mut player_config = player_config;
+                let mut backend = backend;
+                let shutdown = loop {
+                    let update = tokio::select! {
+                        shutdown = &mut shutdown_rx => break shutdown.ok(),
+                        _ = wait_for_disconnect(&session), if reconnect_config.enabled => None,
+                        Some(update) = update_rx.recv() => Some(update),
+                    };
+
+                    let resume = {
+                        let position = thread_position.lock().unwrap();
+                        Resume {
+                            track_id: position.track_id(),
+                            position_ms: position.position_ms(),
+                            playing: position.is_playing(),
+                        }
+                    };
+
+                    // A config update rebuilds the player on the current session
+                    let (next_player_config, next_backend) = match &update {
+                        Some(update) => (update.player_config.clone(), update.backend.clone()),
+                        None => (player_config.clone(), backend.clone()),
+                    };
+                    let new_session = match &update {
+                        Some(_) => None,
+                        None => {
+                            let outcome = reconnect(
+                                &reconnect_config,
+                                &mut shutdown_rx,
+                                &event_callback_channel,
+                                &thread_listeners,
+                                || {
+                                    connect_session(
+                                        session_config.clone(),
+                                        cache_config.clone(),
+                                        credentials.clone(),
+                                    )
+                                },
+                            )
+                            .await;
+
+                            match outcome {
+                                ReconnectOutcome::Connected(new_session) => Some(new_session),
+                                ReconnectOutcome::Failed => break shutdown_rx.await.ok(),
+                                ReconnectOutcome::Shutdown(shutdown) => break shutdown,
+                            }
+                        }
+                    };
+
+                    let res = new_player(
+                        next_backend.clone(),
+                        new_session.clone().unwrap_or_else(|| session.clone()),
+                        next_player_config.clone(),
+                        mixer_settings.clone(),
+                        thread_pcm_output.clone(),
+                        thread_capture.clone(),
+                    );
+                    let ((player, mixer), update) = match (res, update) {
+                        (Ok(created), update) => {
+                            player_config = next_player_config;
+                            backend = next_backend;
+                            (created, update)
+                        }
+                        // The current player keeps playing with the previous settings
+                        (Err(err), Some(update)) => {
+                            update.failed(&event_callback_channel, err);
+                            continue;
+                        }
+                        (Err(err), None) => {
+                            warn!("Failed to re-create the player after reconnecting: {}", err);
+                            if let Some(new_session) = new_session {
+                                new_session.shutdown();
+                            }
+                            break shutdown_rx.await.ok();
+                        }
+                    };
+                    event_threads.push(JsPlayerWrapper::start_player_event_thread(
+                        event_callback_channel.clone(),
+                        player.get_player_event_channel(),
+                        Arc::downgrade(&player),
+                        queue.clone(),
+                        thread_position.clone(),
+                        thread_listeners.clone(),
+                    ));
+
+                    // Fails if the player is being closed, the shutdown follows
+                    let _ = supervisor_tx.send(Message::Reattach(Reattach {
+                        player,
+                        mixer,
+                        session: new_session.clone().unwrap_or_else(|| session.clone()),
+                        resume,
+                    }));
+
+                    if let Some(new_session) = new_session {
+                        session.shutdown();
+                        session = new_session;
+                        start_token_refresh(
+                            thread_token_cache.clone(),
+                            session.clone(),
+                            event_callback_channel.clone(),
+                            thread_listeners.clone(),
+                        );
+                    }
+
+                    if let Some(update) = update {
+                        update.applied(&event_callback_channel, &thread_listeners);
+                    }
+                };
+                session.shutdown();
+
+                Some((shutdown, command_thread, event_threads))
+            });
+
+            if let Some((shutdown, command_thread, event_threads)) = threads {
+                let _ = command_thread.join();
+                for event_thread in event_threads {
+                    let _ = event_thread.join();
+                }
+
+                // Wait for the session tasks to wind down before reporting the player closed
+                drop(runtime);
+
+                if let Some((deferred, channel)) = shutdown {
+                    let listeners = thread_listeners;
+                    match deferred {
+                        Some(deferred) => deferred.settle_with(&channel, move |mut cx| {
+                            listeners.clear(&mut cx);
+                            Ok(cx.undefined())
+                        }),
+                        None => channel.send(move |mut cx| {
+                            listeners.clear(&mut cx);
+                            Ok(())
+                        }),
+                    };
+                }
+            }
         });
 
         let res = player_creation_rx.recv();
         match res.unwrap() {
-            Ok(device_id) => return Ok(Self { tx, device_id }),
+            Ok(device_id) => {
+                return Ok(Self {
+                    tx,
+                    device_id,
+                    listeners,
+                    position,
+                    canvas_cache,
+                    token_cache,
+                    pcm_output,
+                    capture,
+                    mixer,
+                    update_tx,
+                })
+            }
             Err(e) => Err(e),
         }
     }
@@ -122,83 +392,203 @@ impl JsPlayerWrapper {
     pub fn start_player_event_thread(
         channel: Channel,
         mut event_channel: PlayerEventChannel,
-        close_rx: mpsc::Receiver<()>,
-    ) {
-        thread::spawn(move || loop {
-            let close_message = close_rx.try_recv();
-            match close_message {
-                Ok(_) => break,
-                Err(_) => {}
+        player: Weak<Player>,
+        queue: Arc<Mutex<PlayQueue>>,
+        position: SharedPosition,
+        listeners: EventListeners,
+    ) -> JoinHandle<()> {
+        // Only a weak reference is held so the player can be dropped on close,
+        // which closes the event channel and ends this thread
+        thread::spawn(move || {
+            while let Some(message) = event_channel.blocking_recv() {
+                position.lock().unwrap().update(&message);
+                if let Some(player) = player.upgrade() {
+                    JsPlayerWrapper::advance_queue(&channel, &listeners, &player, &queue, &message);
+                }
+                listeners.emit(&channel, move |cx| create_js_obj_from_event(cx, message));
             }
+        })
+    }
 
-            let message = event_channel.blocking_recv();
-            if message.is_some() {
-                channel.send(move |mut cx| {
-                    let callback: Handle<JsFunction> =
-                        cx.global().get(&mut cx, GLOBAL_JS_CALLBACK_METHOD).unwrap();
-                    let (obj, mut cx) = create_js_obj_from_event(cx, message.unwrap());
-                    let _: JsResult<JsUndefined> =
-                        callback.call_with(&mut cx).arg(obj).apply(&mut cx);
-                    Ok(())
-                });
+    fn advance_queue(
+        channel: &Channel,
+        listeners: &EventListeners,
+        player: &Player,
+        queue: &Mutex<PlayQueue>,
+        event: &PlayerEvent,
+    ) {
+        let mut queue = queue.lock().unwrap();
+        match *event {
+            PlayerEvent::PlayRequestIdChanged { play_request_id } => {
+                queue.request_started(play_request_id);
             }
-        });
+            PlayerEvent::TimeToPreloadNextTrack {
+                play_request_id, ..
+            } if queue.is_current_request(play_request_id) => {
+                if let Some(next) = queue.peek_next() {
+                    player.preload(next);
+                }
+            }
+            PlayerEvent::EndOfTrack {
+                play_request_id, ..
+            } if queue.is_current_request(play_request_id) => {
+                // Loading the preloaded track makes the transition gapless
+                if queue.next(false).is_some() {
+                    queue.load_current(player, true, 0);
+                    JsPlayerWrapper::emit_queue_changed(channel, listeners, queue.state());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn emit_queue_changed(channel: &Channel, listeners: &EventListeners, state: QueueState) {
+        listeners.emit(channel, move |cx| create_js_event_from_queue(cx, state));
     }
 
     pub fn listen_commands(
         rx: Receiver<Message>,
         mut player: Arc<Player>,
         mut mixer: Arc<dyn Mixer>,
-        session: Session,
-        close_tx: mpsc::Sender<()>,
+        shared_mixer: SharedMixer,
+        queue: Arc<Mutex<PlayQueue>>,
+        mut session: Session,
+        shutdown_tx: oneshot::Sender<Shutdown>,
         callback_channel: Channel,
-    ) {
+    ) -> JoinHandle<()> {
         thread::spawn(move || {
+            let mut close_deferred = None;
+
+            // Runs until Close, sent by close() or by finalize. The supervisor
+            // holds a sender too, so the channel never closes before that
             while let Ok(message) = rx.recv() {
                 match message {
                     Message::Callback(deferred, f) => {
                         f(
                             &mut player,
                             &mut mixer,
+                            &queue,
                             session.clone(),
                             &callback_channel,
                             deferred,
                         );
                     }
 
-                    Message::Close => {
-                        close_tx.send(()).unwrap();
-                        player.stop();
+                    Message::Reattach(reattach) => {
+                        reattach.mixer.set_volume(mixer.volume());
+                        player = reattach.player;
+                        mixer = reattach.mixer;
+                        *shared_mixer.lock().unwrap() = Some(mixer.clone());
+                        session = reattach.session;
+
+                        let mut queue = queue.lock().unwrap();
+                        if queue.current().is_some() {
+                            let Resume {
+                                position_ms,
+                                playing,
+                                ..
+                            } = reattach.resume;
+                            queue.load_current(&player, playing, position_ms);
+                        }
+                    }
+
+                    Message::Close(deferred) => {
+                        close_deferred = deferred;
                         break;
                     }
                 }
             }
-        });
+
+            player.stop();
+            shared_mixer.lock().unwrap().take();
+
+            // Dropping the last reference joins the player thread, which releases the sink
+            drop(player);
+            drop(mixer);
+
+            let _ = shutdown_tx.send((close_deferred, callback_channel));
+        })
     }
 
-    pub fn close(&self) -> Result<(), mpsc::SendError<Message>> {
-        self.tx.send(Message::Close)
+    // A player blocked on a paused pcm_callback would never release the sink
+    fn close_pcm_output(&self) {
+        if let Some(pcm_output) = &self.pcm_output {
+            pcm_output.close();
+        }
+    }
+
+    pub fn close(&self, deferred: Deferred) -> Result<(), Deferred> {
+        self.close_pcm_output();
+        self.tx
+            .send(Message::Close(Some(deferred)))
+            .map_err(|err| match err.0 {
+                Message::Close(Some(deferred)) => deferred,
+                _ => unreachable!(),
+            })
+    }
+
+    /// Rebuilds the player with the new settings, keeping what it was playing
+    pub fn update_config(&self, update: ConfigUpdate) -> Result<(), Deferred> {
+        self.update_tx
+            .send(update)
+            .map_err(|err| err.0.into_deferred())
     }
 
     pub fn send(
         &self,
         deferred: Deferred,
-        callback: impl (FnOnce(&mut Arc<Player>, &mut Arc<dyn Mixer>, Session, &Channel, Deferred))
-            + Send
+        callback: impl (FnOnce(
+                &mut Arc<Player>,
+                &mut Arc<dyn Mixer>,
+                &Arc<Mutex<PlayQueue>>,
+                Session,
+                &Channel,
+                Deferred,
+            )) + Send
             + 'static,
-    ) {
-        let res = self
-            .tx
-            .send(Message::Callback(deferred, Box::new(callback)));
-        if res.is_err() {
-            panic!(
-                "Failed to send command to player {}",
-                res.err().unwrap().to_string()
-            )
-        }
+    ) -> Result<(), Deferred> {
+        self.tx
+            .send(Message::Callback(deferred, Box::new(callback)))
+            .map_err(|err| match err.0 {
+                Message::Callback(deferred, _) => deferred,
+                _ => unreachable!(),
+            })
     }
 
     pub fn get_device_id(&self) -> String {
         self.device_id.clone()
     }
+
+    pub fn listeners(&self) -> EventListeners {
+        self.listeners.clone()
+    }
+
+    pub fn position(&self) -> SharedPosition {
+        self.position.clone()
+    }
+
+    pub fn canvas_cache(&self) -> Option<Arc<CanvasCache>> {
+        self.canvas_cache.clone()
+    }
+
+    pub fn token_cache(&self) -> Arc<TokenCache> {
+        self.token_cache.clone()
+    }
+
+    pub fn pcm_output(&self) -> Option<Arc<PcmOutput>> {
+        self.pcm_output.clone()
+    }
+
+    pub fn capture(&self) -> Option<Arc<Capture>> {
+        self.capture.clone()
+    }
+
+    /// Volume read back from the mixer, `None` once the player is closed
+    pub fn volume(&self) -> Option<u16> {
+        self.mixer
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|mixer| mixer.volume())
+    }
 }
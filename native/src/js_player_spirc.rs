@@ -1,59 +1,146 @@
 use std::{
-    sync::mpsc::{self, Receiver},
-    thread,
+    sync::{
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
 };
 
 use librespot::{
-    connect::{config::ConnectConfig, spirc::Spirc},
+    connect::{
+        config::ConnectConfig,
+        spirc::{Spirc, SpircLoadCommand},
+    },
     core::Error,
-    core::{cache::Cache, Session},
+    core::{cache::Cache, config::SessionConfig, Session},
     discovery::Credentials,
     playback::{config::PlayerConfig, player::PlayerEventChannel},
+    protocol::spirc::TrackRef,
 };
+use log::{info, warn};
 use neon::{
-    prelude::{Channel, Context, Handle, Object},
-    result::JsResult,
-    types::{Deferred, Finalize, JsFunction, JsUndefined},
+    prelude::{Channel, Context},
+    types::{Deferred, Finalize},
+};
+use tokio::{
+    runtime::Builder,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedSender},
+        oneshot,
+    },
 };
-use tokio::runtime::Builder;
 
 use crate::{
-    constants::GLOBAL_JS_CALLBACK_METHOD,
-    player::{create_session, new_player},
-    utils::create_js_obj_from_event,
+    backends::BackendConfig,
+    canvas_cache::CanvasCache,
+    capture_sink::Capture,
+    config_update::ConfigUpdate,
+    error::NativeError,
+    events::EventListeners,
+    pcm_sink::PcmOutput,
+    player::{
+        create_session, new_player, LoadedContext, MixerSettings, SharedContext, SharedMixer,
+    },
+    position::{start_position_updates, PositionTracker, SharedPosition},
+    reconnect::{reconnect, ReconnectConfig, ReconnectOutcome, Resume},
+    token_cache::{start_token_refresh, TokenCache},
+    utils::{create_js_obj_from_credentials, create_js_obj_from_event},
 };
 
-impl Finalize for JsPlayerSpircWrapper {}
+impl Finalize for JsPlayerSpircWrapper {
+    fn finalize<'a, C: Context<'a>>(self, cx: &mut C) {
+        self.close_pcm_output();
+        self.listeners.clear(cx);
+        // Fails if the player was already closed, nothing left to clean up then
+        let _ = self.tx.send(Message::Close(None));
+    }
+}
 
 pub struct JsPlayerSpircWrapper {
     tx: mpsc::Sender<Message>,
     device_id: String,
+    listeners: EventListeners,
+    position: SharedPosition,
+    canvas_cache: Option<Arc<CanvasCache>>,
+    token_cache: Arc<TokenCache>,
+    pcm_output: Option<Arc<PcmOutput>>,
+    capture: Option<Arc<Capture>>,
+    mixer: SharedMixer,
+    context: SharedContext,
+    update_tx: UnboundedSender<ConfigUpdate>,
 }
 
 pub type Callback = Box<dyn (FnOnce(&mut Spirc, Session, &Channel, Deferred)) + Send>;
 
 pub enum Message {
     Callback(Deferred, Callback),
-    Close,
+    /// Shuts down the current spirc ahead of its replacement
+    Detach(oneshot::Sender<()>),
+    Reattach(Reattach),
+    Close(Option<Deferred>),
 }
 
+/// Spirc created by the supervisor after reconnecting or applying a config
+/// update, replacing the current one along with its session
+pub struct Reattach {
+    spirc: Spirc,
+    session: Session,
+    resume: Resume,
+    context: Option<LoadedContext>,
+    volume: Option<u16>,
+}
+
+// What woke up the supervisor
+enum Wake {
+    Shutdown(Option<Shutdown>),
+    TaskEnded,
+    Update(ConfigUpdate),
+}
+
+// Sent by the command thread once spirc is shut down, along with the close
+// promise to resolve after the remaining threads have joined
+type Shutdown = (Option<Deferred>, Channel);
+
 impl JsPlayerSpircWrapper {
     pub fn new<'a, C>(
         cx: &mut C,
+        listeners: EventListeners,
+        position_interval: Duration,
+        reconnect_config: ReconnectConfig,
+        canvas_cache: Option<Arc<CanvasCache>>,
+        token_cache: Arc<TokenCache>,
         credentials: Credentials,
+        session_config: SessionConfig,
         player_config: PlayerConfig,
         connect_config: ConnectConfig,
         cache_config: Cache,
-        backend: String,
-        volume_ctrl: String,
-    ) -> Result<Self, Error>
+        backend: BackendConfig,
+        pcm_output: Option<Arc<PcmOutput>>,
+        capture: Option<Arc<Capture>>,
+        mixer_settings: MixerSettings,
+    ) -> Result<Self, NativeError>
     where
         C: Context<'a>,
     {
         let (tx, rx) = mpsc::channel::<Message>();
+        // The supervisor holds its own sender, the wrapper is closed on finalize
+        let supervisor_tx = tx.clone();
 
-        let (player_creation_tx, player_creation_rx) = mpsc::channel::<Result<String, Error>>();
-        let (close_tx, close_rx) = mpsc::channel::<()>();
+        let (player_creation_tx, player_creation_rx) =
+            mpsc::channel::<Result<String, NativeError>>();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<Shutdown>();
+        let (update_tx, update_rx) = unbounded_channel::<ConfigUpdate>();
+        let thread_listeners = listeners.clone();
+        let position = Arc::new(Mutex::new(PositionTracker::new()));
+        let thread_position = position.clone();
+        let thread_token_cache = token_cache.clone();
+        let thread_pcm_output = pcm_output.clone();
+        let thread_capture = capture.clone();
+        let mixer: SharedMixer = Arc::new(Mutex::new(None));
+        let thread_mixer = mixer.clone();
+        let context: SharedContext = Arc::new(Mutex::new(None));
+        let thread_context = context.clone();
 
         let mut commands_channel = cx.channel();
         commands_channel.unref(cx);
@@ -61,6 +148,9 @@ impl JsPlayerSpircWrapper {
         let mut event_callback_channel = cx.channel();
         event_callback_channel.unref(cx);
 
+        let mut position_channel = cx.channel();
+        position_channel.unref(cx);
+
         thread::spawn(move || {
             let runtime = Builder::new_multi_thread()
                 .enable_io()
@@ -68,16 +158,30 @@ impl JsPlayerSpircWrapper {
                 .build()
                 .unwrap();
 
-            runtime.block_on(async {
-                println!("Creating session");
-                let session = create_session(cache_config).clone();
+            let threads = runtime.block_on(async {
+                info!("Creating session");
+                let session = create_session(session_config.clone(), cache_config.clone());
 
                 let device_id = session.device_id().to_string();
 
-                let (player, mixer) =
-                    new_player(backend, session.clone(), player_config.clone(), volume_ctrl);
+                let res = new_player(
+                    backend.clone(),
+                    session.clone(),
+                    player_config.clone(),
+                    mixer_settings.clone(),
+                    thread_pcm_output.clone(),
+                    thread_capture.clone(),
+                );
+                let (player, mixer) = match res {
+                    Ok(created) => created,
+                    Err(err) => {
+                        player_creation_tx.send(Err(err)).unwrap();
+                        return None;
+                    }
+                };
 
                 let events_channel = player.get_player_event_channel();
+                *thread_mixer.lock().unwrap() = Some(mixer.clone());
 
                 let res = Spirc::new(
                     connect_config.clone(),
@@ -90,33 +194,280 @@ impl JsPlayerSpircWrapper {
 
                 match res {
                     Ok((spirc, spirc_task)) => {
-                        spirc.activate().unwrap();
-                        JsPlayerSpircWrapper::start_player_event_thread(
-                            event_callback_channel,
-                            events_channel,
-                            close_rx,
+                        if let Err(err) = spirc.activate() {
+                            let _ = spirc.shutdown();
+                            spirc_task.await;
+                            thread_mixer.lock().unwrap().take();
+                            session.shutdown();
+                            player_creation_tx.send(Err(err.into())).unwrap();
+                            return None;
+                        }
+
+                        // Only available when the cache has a credentials_location
+                        if let Some(credentials) =
+                            session.cache().and_then(|cache| cache.credentials())
+                        {
+                            thread_listeners.emit(&event_callback_channel, move |cx| {
+                                create_js_obj_from_credentials(cx, "CredentialsStored", credentials)
+                            });
+                        }
+
+                        let mut event_threads =
+                            vec![JsPlayerSpircWrapper::start_player_event_thread(
+                                event_callback_channel.clone(),
+                                events_channel,
+                                thread_position.clone(),
+                                thread_listeners.clone(),
+                            )];
+                        start_position_updates(
+                            position_interval,
+                            thread_position.clone(),
+                            position_channel,
+                            thread_listeners.clone(),
+                        );
+                        start_token_refresh(
+                            thread_token_cache.clone(),
+                            session.clone(),
+                            event_callback_channel.clone(),
+                            thread_listeners.clone(),
                         );
-                        JsPlayerSpircWrapper::listen_commands(
+                        let command_thread = JsPlayerSpircWrapper::listen_commands(
                             rx,
                             spirc,
                             session.clone(),
-                            close_tx,
+                            shutdown_tx,
                             commands_channel,
                         );
 
                         // Panic thread if send fails
                         player_creation_tx.send(Ok(device_id)).unwrap();
 
-                        spirc_task.await;
+                        // The task owns the player, which is dropped along with it
+                        let mut spirc_task = Box::pin(spirc_task);
+                        let mut shutdown_rx = shutdown_rx;
+                        let mut update_rx = update_rx;
+                        let mut session = session;
+                        let mut player_config = player_config;
+                        let mut backend = backend;
+                        let shutdown = loop {
+                            let wake = tokio::select! {
+                                shutdown = &mut shutdown_rx => Wake::Shutdown(shutdown.ok()),
+                                _ = &mut spirc_task => Wake::TaskEnded,
+                                Some(update) = update_rx.recv() => Wake::Update(update),
+                            };
+
+                            let update = match wake {
+                                Wake::Shutdown(shutdown) => {
+                                    spirc_task.await;
+                                    break shutdown;
+                                }
+                                // The task also ends once spirc is shut down on close
+                                Wake::TaskEnded => {
+                                    if !reconnect_config.enabled || !session.is_invalid() {
+                                        break shutdown_rx.await.ok();
+                                    }
+                                    None
+                                }
+                                Wake::Update(update) => Some(update),
+                            };
+
+                            let resume = {
+                                let position = thread_position.lock().unwrap();
+                                Resume {
+                                    track_id: position.track_id(),
+                                    position_ms: position.position_ms(),
+                                    playing: position.is_playing(),
+                                }
+                            };
+                            let context = thread_context.lock().unwrap().clone();
+                            let volume = thread_mixer
+                                .lock()
+                                .unwrap()
+                                .as_ref()
+                                .map(|mixer| mixer.volume());
+
+                            // Spirc owns its player and connects the session it is given,
+                            // which a session only does once. So a config update brings up
+                            // a new session under the same device id, like reconnecting does
+                            let connect =
+                                |player_config: &PlayerConfig, backend: &BackendConfig| {
+                                    let session = create_session(
+                                        session_config.clone(),
+                                        cache_config.clone(),
+                                    );
+                                    let player = new_player(
+                                        backend.clone(),
+                                        session.clone(),
+                                        player_config.clone(),
+                                        mixer_settings.clone(),
+                                        thread_pcm_output.clone(),
+                                        thread_capture.clone(),
+                                    );
+                                    let connect_config = connect_config.clone();
+                                    let credentials = credentials.clone();
+                                    async move {
+                                        let (player, mixer) = player?;
+                                        let events_channel = player.get_player_event_channel();
+                                        let (spirc, spirc_task) = Spirc::new(
+                                            connect_config,
+                                            session.clone(),
+                                            credentials,
+                                            player,
+                                            mixer.clone(),
+                                        )
+                                        .await?;
+                                        Ok::<_, NativeError>((
+                                            session,
+                                            spirc,
+                                            spirc_task,
+                                            events_channel,
+                                            mixer,
+                                        ))
+                                    }
+                                };
+
+                            let connected = match update {
+                                Some(update) => {
+                                    // Two spircs under one device id would both answer for it,
+                                    // so the current one is shut down first. Its task owns the
+                                    // player, which has to release the sink before the next
+                                    // one opens it. Fails if the player is being closed
+                                    let (detached_tx, detached_rx) = oneshot::channel();
+                                    if supervisor_tx.send(Message::Detach(detached_tx)).is_ok() {
+                                        let _ = detached_rx.await;
+                                    }
+                                    (&mut spirc_task).await;
+
+                                    match connect(&update.player_config, &update.backend).await {
+                                        Ok(connected) => {
+                                            player_config = update.player_config.clone();
+                                            backend = update.backend.clone();
+                                            Some((connected, Some(update)))
+                                        }
+                                        Err(err) => {
+                                            update.failed(&event_callback_channel, err);
+                                            None
+                                        }
+                                    }
+                                }
+                                None => None,
+                            };
+
+                            // Reconnects after the session was lost, or with the previous
+                            // settings once a config update failed to apply
+                            let connected = match connected {
+                                Some(connected) => connected,
+                                None => {
+                                    let outcome = reconnect(
+                                        &reconnect_config,
+                                        &mut shutdown_rx,
+                                        &event_callback_channel,
+                                        &thread_listeners,
+                                        || connect(&player_config, &backend),
+                                    )
+                                    .await;
+
+                                    match outcome {
+                                        ReconnectOutcome::Connected(connected) => (connected, None),
+                                        ReconnectOutcome::Failed => break shutdown_rx.await.ok(),
+                                        ReconnectOutcome::Shutdown(shutdown) => break shutdown,
+                                    }
+                                }
+                            };
+
+                            let (
+                                (new_session, spirc, new_spirc_task, events_channel, mixer),
+                                update,
+                            ) = connected;
+                            *thread_mixer.lock().unwrap() = Some(mixer);
+                            event_threads.push(JsPlayerSpircWrapper::start_player_event_thread(
+                                event_callback_channel.clone(),
+                                events_channel,
+                                thread_position.clone(),
+                                thread_listeners.clone(),
+                            ));
+
+                            let reattach = Message::Reattach(Reattach {
+                                spirc,
+                                session: new_session.clone(),
+                                resume,
+                                context,
+                                volume,
+                            });
+                            // Fails if the player is being closed, the shutdown follows
+                            if let Err(mpsc::SendError(Message::Reattach(reattach))) =
+                                supervisor_tx.send(reattach)
+                            {
+                                let _ = reattach.spirc.shutdown();
+                            }
+                            session.shutdown();
+                            session = new_session;
+                            start_token_refresh(
+                                thread_token_cache.clone(),
+                                session.clone(),
+                                event_callback_channel.clone(),
+                                thread_listeners.clone(),
+                            );
+                            spirc_task = Box::pin(new_spirc_task);
+
+                            if let Some(update) = update {
+                                update.applied(&event_callback_channel, &thread_listeners);
+                            }
+                        };
+                        thread_mixer.lock().unwrap().take();
+                        session.shutdown();
+
+                        Some((shutdown, command_thread, event_threads))
+                    }
+                    Err(e) => {
+                        player_creation_tx.send(Err(e.into())).unwrap();
+                        None
                     }
-                    Err(e) => player_creation_tx.send(Err(e)).unwrap(),
                 }
-            })
+            });
+
+            if let Some((shutdown, command_thread, event_threads)) = threads {
+                let _ = command_thread.join();
+                for event_thread in event_threads {
+                    let _ = event_thread.join();
+                }
+
+                // Wait for the session tasks to wind down before reporting the player closed
+                drop(runtime);
+
+                if let Some((deferred, channel)) = shutdown {
+                    let listeners = thread_listeners;
+                    match deferred {
+                        Some(deferred) => deferred.settle_with(&channel, move |mut cx| {
+                            listeners.clear(&mut cx);
+                            Ok(cx.undefined())
+                        }),
+                        None => channel.send(move |mut cx| {
+                            listeners.clear(&mut cx);
+                            Ok(())
+                        }),
+                    };
+                }
+            }
         });
 
         let res = player_creation_rx.recv();
         match res.unwrap() {
-            Ok(device_id) => return Ok(Self { tx, device_id }),
+            Ok(device_id) => {
+                return Ok(Self {
+                    tx,
+                    device_id,
+                    listeners,
+                    position,
+                    canvas_cache,
+                    token_cache,
+                    pcm_output,
+                    capture,
+                    mixer,
+                    context,
+                    update_tx,
+                })
+            }
             Err(e) => Err(e),
         }
     }
@@ -124,74 +475,204 @@ impl JsPlayerSpircWrapper {
     pub fn start_player_event_thread(
         channel: Channel,
         mut event_channel: PlayerEventChannel,
-        close_rx: mpsc::Receiver<()>,
-    ) {
-        thread::spawn(move || loop {
-            let close_message = close_rx.try_recv();
-            match close_message {
-                Ok(_) => break,
-                Err(_) => {}
-            }
-
-            let message = event_channel.blocking_recv();
-            if message.is_some() {
-                channel.send(move |mut cx| {
-                    let callback: Handle<JsFunction> =
-                        cx.global().get(&mut cx, GLOBAL_JS_CALLBACK_METHOD).unwrap();
-                    let (obj, mut cx) = create_js_obj_from_event(cx, message.unwrap());
-                    let _: JsResult<JsUndefined> =
-                        callback.call_with(&mut cx).arg(obj).apply(&mut cx);
-                    Ok(())
-                });
+        position: SharedPosition,
+        listeners: EventListeners,
+    ) -> JoinHandle<()> {
+        // Ends once the spirc task drops the player and closes the event channel
+        thread::spawn(move || {
+            while let Some(message) = event_channel.blocking_recv() {
+                position.lock().unwrap().update(&message);
+                listeners.emit(&channel, move |cx| create_js_obj_from_event(cx, message));
             }
-        });
+        })
     }
 
     pub fn listen_commands(
         rx: Receiver<Message>,
         mut spirc: Spirc,
-        session: Session,
-        close_tx: mpsc::Sender<()>,
+        mut session: Session,
+        shutdown_tx: oneshot::Sender<Shutdown>,
         callback_channel: Channel,
-    ) {
+    ) -> JoinHandle<()> {
         thread::spawn(move || {
+            let mut close_deferred = None;
+
+            // Runs until Close, sent by close() or by finalize. The supervisor
+            // holds a sender too, so the channel never closes before that
             while let Ok(message) = rx.recv() {
                 match message {
                     Message::Callback(deferred, f) => {
                         f(&mut spirc, session.clone(), &callback_channel, deferred);
                     }
 
-                    Message::Close => {
-                        close_tx.send(()).unwrap();
-                        spirc.shutdown().unwrap();
+                    Message::Detach(detached_tx) => {
+                        // Fails if the spirc task already ended on its own
+                        let _ = spirc.shutdown();
+                        let _ = detached_tx.send(());
+                    }
+
+                    // The replaced spirc was detached, or its task ended when the
+                    // session was lost
+                    Message::Reattach(reattach) => {
+                        spirc = reattach.spirc;
+                        session = reattach.session;
+                        let res = JsPlayerSpircWrapper::resume(
+                            &spirc,
+                            reattach.resume,
+                            reattach.context,
+                            reattach.volume,
+                        );
+                        if let Err(err) = res {
+                            warn!("Failed to resume playback on the new spirc: {}", err);
+                        }
+                    }
+
+                    Message::Close(deferred) => {
+                        close_deferred = deferred;
                         break;
                     }
                 }
             }
-        });
+
+            // Fails if the spirc task already ended on its own
+            let _ = spirc.shutdown();
+
+            let _ = shutdown_tx.send((close_deferred, callback_channel));
+        })
+    }
+
+    // The connect state lives on the old device session, so the context is
+    // loaded again at the current track instead of transferring the previous
+    // playback. Only the track is loaded if it was started from elsewhere,
+    // e.g. another Connect client
+    fn resume(
+        spirc: &Spirc,
+        resume: Resume,
+        context: Option<LoadedContext>,
+        volume: Option<u16>,
+    ) -> Result<(), Error> {
+        spirc.activate()?;
+        if let Some(volume) = volume {
+            spirc.set_volume(volume)?;
+        }
+
+        let track_id = match resume.track_id {
+            Some(track_id) => track_id,
+            None => return Ok(()),
+        };
+
+        let command =
+            match context.and_then(|context| context.load_command(track_id, resume.playing)) {
+                Some(command) => command,
+                None => {
+                    let mut track_ref = TrackRef::new();
+                    track_ref.set_gid(Vec::from(track_id.to_raw()));
+
+                    SpircLoadCommand {
+                        context_uri: track_id.to_uri()?,
+                        start_playing: resume.playing,
+                        shuffle: false,
+                        repeat: false,
+                        playing_track_index: 0,
+                        tracks: vec![track_ref],
+                    }
+                }
+            };
+
+        spirc.load(command)?;
+        spirc.set_position_ms(resume.position_ms)
+    }
+
+    // A player blocked on a paused pcm_callback would never release the sink
+    fn close_pcm_output(&self) {
+        if let Some(pcm_output) = &self.pcm_output {
+            pcm_output.close();
+        }
+    }
+
+    pub fn close(&self, deferred: Deferred) -> Result<(), Deferred> {
+        self.close_pcm_output();
+        self.tx
+            .send(Message::Close(Some(deferred)))
+            .map_err(|err| match err.0 {
+                Message::Close(Some(deferred)) => deferred,
+                _ => unreachable!(),
+            })
     }
 
-    pub fn close(&self) -> Result<(), mpsc::SendError<Message>> {
-        self.tx.send(Message::Close)
+    /// Brings up a new spirc with the settings, resuming what it was playing
+    pub fn update_config(&self, update: ConfigUpdate) -> Result<(), Deferred> {
+        self.update_tx
+            .send(update)
+            .map_err(|err| err.0.into_deferred())
     }
 
     pub fn send(
         &self,
         deferred: Deferred,
         callback: impl (FnOnce(&mut Spirc, Session, &Channel, Deferred)) + Send + 'static,
-    ) {
-        let res = self
-            .tx
-            .send(Message::Callback(deferred, Box::new(callback)));
-        if res.is_err() {
-            panic!(
-                "Failed to send command to player {}",
-                res.err().unwrap().to_string()
-            )
-        }
+    ) -> Result<(), Deferred> {
+        send_command(&self.tx, deferred, callback)
+    }
+
+    /// Sender for commands issued from other threads once they are ready,
+    /// see `send_command`
+    pub fn commands(&self) -> mpsc::Sender<Message> {
+        self.tx.clone()
     }
 
     pub fn get_device_id(&self) -> String {
         self.device_id.clone()
     }
+
+    pub fn listeners(&self) -> EventListeners {
+        self.listeners.clone()
+    }
+
+    pub fn position(&self) -> SharedPosition {
+        self.position.clone()
+    }
+
+    pub fn context(&self) -> SharedContext {
+        self.context.clone()
+    }
+
+    pub fn canvas_cache(&self) -> Option<Arc<CanvasCache>> {
+        self.canvas_cache.clone()
+    }
+
+    pub fn token_cache(&self) -> Arc<TokenCache> {
+        self.token_cache.clone()
+    }
+
+    pub fn pcm_output(&self) -> Option<Arc<PcmOutput>> {
+        self.pcm_output.clone()
+    }
+
+    pub fn capture(&self) -> Option<Arc<Capture>> {
+        self.capture.clone()
+    }
+
+    /// Volume read back from the mixer, `None` once the player is closed
+    pub fn volume(&self) -> Option<u16> {
+        self.mixer
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|mixer| mixer.volume())
+    }
+}
+
+/// Runs the callback on the command thread, handing the deferred back if the
+/// player is closed
+pub fn send_command(
+    tx: &mpsc::Sender<Message>,
+    deferred: Deferred,
+    callback: impl (FnOnce(&mut Spirc, Session, &Channel, Deferred)) + Send + 'static,
+) -> Result<(), Deferred> {
+    tx.send(Message::Callback(deferred, Box::new(callback)))
+        .map_err(|err| match err.0 {
+            Message::Callback(deferred, _) => deferred,
+            _ => unreachable!(),
+        })
 }
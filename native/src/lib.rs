@@ -1,12 +1,21 @@
-use std::sync::Arc;
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
 
-use constants::GLOBAL_JS_CALLBACK_METHOD;
+use canvas_cache::CanvasCache;
+use capture_sink::{Capture, CAPTURE_BACKEND};
+use config_reader::ConfigReader;
+use config_update::ConfigUpdate;
+use error::{ErrorCode, NativeError, ResultExt};
+use events::EventListeners;
 use futures::executor::block_on;
 use js_player::JsPlayerWrapper;
-use js_player_spirc::JsPlayerSpircWrapper;
+use js_player_spirc::{send_command, JsPlayerSpircWrapper, Message as SpircMessage};
 use librespot::{
     connect::spirc::{Spirc, SpircLoadCommand},
     core::{Session, SpotifyId},
+    discovery::Credentials,
     playback::{mixer::Mixer, player::Player},
     protocol::spirc::TrackRef,
 };
@@ -15,21 +24,45 @@ use neon::{
     prelude::{Channel, Context, FunctionContext, Handle, ModuleContext, Object},
     result::{JsResult, NeonResult},
     types::{
-        Deferred, JsBoolean, JsBox, JsFunction, JsNumber, JsObject, JsPromise, JsString,
+        Deferred, JsArray, JsBoolean, JsBox, JsFunction, JsNumber, JsObject, JsPromise, JsString,
         JsUndefined, JsValue, Value,
     },
 };
+use oauth::Login;
+use player::{ContextLoadOptions, SharedContext};
+use reqwest::Url;
 
+use queue::{PlayQueue, RepeatMode};
+use token_cache::TokenCache;
 use utils::{
-    create_js_obj_from_canvas, get_cache_config_from_obj, get_connect_config_from_obj,
-    get_credentials_from_obj, get_player_config_from_obj, get_volume_ctrl_from_obj, token_to_obj,
+    create_js_arr_from_backends, create_js_obj_from_authorization_url, create_js_obj_from_canvas,
+    create_js_obj_from_canvas_batch, create_js_obj_from_capture, create_js_obj_from_config_warning,
+    create_js_obj_from_credentials, create_js_obj_from_lyrics, create_js_obj_from_metadata,
+    create_js_obj_from_queue, get_backend_from_obj, get_cache_settings_from_obj, get_config_reader,
+    get_connect_config_from_obj, get_context_options_from_obj, get_discovery_port_from_obj,
+    get_login_from_obj, get_mixer_from_obj, get_pcm_output_from_obj, get_player_config_from_obj,
+    get_position_interval_from_obj, get_reconnect_config_from_obj, get_session_config_from_obj,
+    get_spotify_ids_from_arr, get_strings_from_arr, token_to_obj,
 };
 
+mod backends;
+mod canvas_cache;
 mod canvaz;
-mod constants;
+mod capture_sink;
+mod config_reader;
+mod config_update;
+mod error;
+mod events;
 mod js_player;
 mod js_player_spirc;
+mod lyrics;
+mod oauth;
+mod pcm_sink;
 mod player;
+mod position;
+mod queue;
+mod reconnect;
+mod token_cache;
 mod utils;
 use env_logger;
 
@@ -43,15 +76,20 @@ fn send_to_spirc(
 
     let (deferred, promise) = cx.promise();
     match player_wrapper {
-        Ok(p) => p.send(deferred, callback),
+        Ok(p) => {
+            if let Err(deferred) = p.send(deferred, callback) {
+                let error = NativeError::player_closed().to_js(&mut cx).unwrap();
+                deferred.reject(&mut cx, error)
+            }
+        }
 
         Err(e) => {
-            let error = cx
-                .error(format!(
-                    "Failed to get spirc from \"this\": {}",
-                    e.to_string()
-                ))
-                .unwrap();
+            let error = NativeError::new(
+                ErrorCode::Internal,
+                format!("Failed to get spirc from \"this\": {}", e),
+            )
+            .to_js(&mut cx)
+            .unwrap();
 
             deferred.reject(&mut cx, error)
         }
@@ -62,8 +100,14 @@ fn send_to_spirc(
 
 fn send_to_player(
     mut cx: FunctionContext,
-    callback: impl (FnOnce(&mut Arc<Player>, &mut Arc<dyn Mixer>, Session, &Channel, Deferred))
-        + Send
+    callback: impl (FnOnce(
+            &mut Arc<Player>,
+            &mut Arc<dyn Mixer>,
+            &Arc<Mutex<PlayQueue>>,
+            Session,
+            &Channel,
+            Deferred,
+        )) + Send
         + 'static,
 ) -> Handle<JsPromise> {
     let player_wrapper = cx
@@ -72,15 +116,20 @@ fn send_to_player(
 
     let (deferred, promise) = cx.promise();
     match player_wrapper {
-        Ok(p) => p.send(deferred, callback),
+        Ok(p) => {
+            if let Err(deferred) = p.send(deferred, callback) {
+                let error = NativeError::player_closed().to_js(&mut cx).unwrap();
+                deferred.reject(&mut cx, error)
+            }
+        }
 
         Err(e) => {
-            let error = cx
-                .error(format!(
-                    "Failed to get player from \"this\": {}",
-                    e.to_string()
-                ))
-                .unwrap();
+            let error = NativeError::new(
+                ErrorCode::Internal,
+                format!("Failed to get player from \"this\": {}", e),
+            )
+            .to_js(&mut cx)
+            .unwrap();
 
             deferred.reject(&mut cx, error)
         }
@@ -89,6 +138,83 @@ fn send_to_player(
     return promise;
 }
 
+fn send_to_queue(
+    mut cx: FunctionContext,
+    callback: impl (FnOnce(&Arc<Player>, &mut PlayQueue) -> Result<(), NativeError>) + Send + 'static,
+) -> JsResult<JsPromise> {
+    let listeners = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerWrapper>, _>(&mut cx)?
+        .listeners();
+
+    Ok(send_to_player(
+        cx,
+        move |player, _, queue, _, channel, deferred| {
+            let mut queue = queue.lock().unwrap();
+            let res = callback(player, &mut queue);
+            if res.is_ok() {
+                JsPlayerWrapper::emit_queue_changed(channel, &listeners, queue.state());
+            }
+
+            deferred.settle_with(channel, move |mut cx| {
+                res.or_throw(&mut cx)?;
+                Ok(cx.undefined())
+            });
+        },
+    ))
+}
+
+// Unknown keys don't make the config invalid, so they are reported with an
+// event instead of an error
+fn emit_config_warning(channel: &Channel, listeners: &EventListeners, reader: &mut ConfigReader) {
+    let unknown_keys = reader.take_unknown_keys();
+    if unknown_keys.is_empty() {
+        return;
+    }
+
+    listeners.emit(channel, move |cx| {
+        create_js_obj_from_config_warning(cx, unknown_keys)
+    });
+}
+
+// The OAuth flow waits for the user to finish logging in through the browser,
+// so it runs on its own thread before the player is created
+fn login_then(
+    login: Login,
+    proxy: Option<Url>,
+    channel: Channel,
+    deferred: Deferred,
+    listeners: EventListeners,
+    create: impl FnOnce(Channel, Deferred, EventListeners, Credentials) + Send + 'static,
+) {
+    let oauth_config = match login {
+        Login::Credentials(credentials) => {
+            return create(channel, deferred, listeners, credentials)
+        }
+        Login::OAuth(oauth_config) => oauth_config,
+    };
+
+    thread::spawn(move || {
+        let url_listeners = listeners.clone();
+        let url_channel = channel.clone();
+        let res = oauth::authorize(oauth_config, proxy, move |url| {
+            url_listeners.emit(&url_channel, move |cx| {
+                create_js_obj_from_authorization_url(cx, url)
+            });
+        });
+
+        match res {
+            Ok(credentials) => create(channel, deferred, listeners, credentials),
+            Err(err) => {
+                deferred.settle_with(&channel, move |mut cx| {
+                    err.prefixed("OAuth login failed")
+                        .throw::<_, Handle<JsUndefined>>(&mut cx)
+                });
+            }
+        }
+    });
+}
+
 fn create_player_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let config = cx.argument::<JsObject>(0)?;
 
@@ -97,34 +223,147 @@ fn create_player_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let (deferred, promise) = cx.promise();
     let channel = cx.channel();
 
-    let credentials = get_credentials_from_obj(&mut cx, config)?;
-    let player_config = get_player_config_from_obj(&mut cx, config)?;
-    let connect_config = get_connect_config_from_obj(&mut cx, config)?;
-    let cache_config = get_cache_config_from_obj(&mut cx, config)?;
-    let volume_ctrl = get_volume_ctrl_from_obj(&mut cx, config)?;
-
-    let backend = config
-        .get::<JsString, _, _>(&mut cx, "backend")?
-        .value(&mut cx);
-
-    let global = cx.global();
-    global
-        .set(&mut cx, GLOBAL_JS_CALLBACK_METHOD, callback)
-        .unwrap();
-
-    deferred.settle_with(&channel, move |mut cx| {
-        let js_player = JsPlayerSpircWrapper::new(
-            &mut cx,
-            credentials,
-            player_config,
-            connect_config,
-            cache_config,
-            backend,
-            volume_ctrl,
+    let mut reader = get_config_reader(&mut cx, config)?;
+    let player_config = get_player_config_from_obj(&mut cx, config, &mut reader)?;
+    let connect_config = get_connect_config_from_obj(&mut cx, config, &mut reader)?;
+    let session_config = get_session_config_from_obj(&mut cx, config, &mut reader)?;
+    let cache_settings = get_cache_settings_from_obj(&mut cx, config, &mut reader)?;
+    let login = get_login_from_obj(&mut cx, config, &mut reader)?;
+    let mixer_settings = get_mixer_from_obj(&mut cx, config, &mut reader)?;
+    let position_interval = get_position_interval_from_obj(&mut cx, config, &mut reader)?;
+    let reconnect_config = get_reconnect_config_from_obj(&mut cx, config, &mut reader)?;
+    let backend = get_backend_from_obj(&mut cx, config, &mut reader)?;
+    reader.check(&mut cx)?;
+
+    let cache_config = cache_settings.cache().or_throw(&mut cx)?;
+    let canvas_cache = cache_settings.canvas_cache().or_throw(&mut cx)?;
+    let token_cache = cache_settings.token_cache();
+    let login = login.resolve(&cache_config).or_throw(&mut cx)?;
+    let pcm_output = get_pcm_output_from_obj(&mut cx, config, &backend.name)?;
+    let capture = (backend.name == CAPTURE_BACKEND).then(|| Arc::new(Capture::new()));
+
+    let listeners = EventListeners::new();
+    listeners.add(callback.root(&mut cx));
+    emit_config_warning(&channel, &listeners, &mut reader);
+
+    login_then(
+        login,
+        session_config.proxy.clone(),
+        channel,
+        deferred,
+        listeners,
+        move |channel, deferred, listeners, credentials| {
+            deferred.settle_with(&channel, move |mut cx| {
+                let js_player = JsPlayerSpircWrapper::new(
+                    &mut cx,
+                    listeners,
+                    position_interval,
+                    reconnect_config,
+                    canvas_cache,
+                    token_cache,
+                    credentials,
+                    session_config,
+                    player_config,
+                    connect_config,
+                    cache_config,
+                    backend,
+                    pcm_output,
+                    capture,
+                    mixer_settings,
+                );
+                match js_player {
+                    Ok(_) => Ok(cx.boxed(js_player.unwrap())),
+                    Err(e) => e.prefixed("Failed to create player").throw(&mut cx),
+                }
+            });
+        },
+    );
+
+    return Ok(promise);
+}
+
+fn create_player_spirc_discovery(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let config = cx.argument::<JsObject>(0)?;
+
+    let callback = cx.argument::<JsFunction>(1)?;
+
+    let (deferred, promise) = cx.promise();
+    let channel = cx.channel();
+
+    let mut reader = get_config_reader(&mut cx, config)?;
+    let player_config = get_player_config_from_obj(&mut cx, config, &mut reader)?;
+    let connect_config = get_connect_config_from_obj(&mut cx, config, &mut reader)?;
+    let session_config = get_session_config_from_obj(&mut cx, config, &mut reader)?;
+    let cache_settings = get_cache_settings_from_obj(&mut cx, config, &mut reader)?;
+    let mixer_settings = get_mixer_from_obj(&mut cx, config, &mut reader)?;
+    let position_interval = get_position_interval_from_obj(&mut cx, config, &mut reader)?;
+    let reconnect_config = get_reconnect_config_from_obj(&mut cx, config, &mut reader)?;
+    let discovery_port = get_discovery_port_from_obj(&mut cx, config, &mut reader)?;
+    let backend = get_backend_from_obj(&mut cx, config, &mut reader)?;
+    reader.check(&mut cx)?;
+
+    let cache_config = cache_settings.cache().or_throw(&mut cx)?;
+    let canvas_cache = cache_settings.canvas_cache().or_throw(&mut cx)?;
+    let token_cache = cache_settings.token_cache();
+    let pcm_output = get_pcm_output_from_obj(&mut cx, config, &backend.name)?;
+    let capture = (backend.name == CAPTURE_BACKEND).then(|| Arc::new(Capture::new()));
+
+    let listeners = EventListeners::new();
+    listeners.add(callback.root(&mut cx));
+    emit_config_warning(&channel, &listeners, &mut reader);
+
+    thread::spawn(move || {
+        // The session advertised over zeroconf and the one spirc connects with
+        // must share a device id, otherwise the handed over credentials are rejected
+        let res = player::start_discovery(
+            session_config.device_id.clone(),
+            session_config.client_id.clone(),
+            connect_config.clone(),
+            discovery_port,
         );
-        match js_player {
-            Ok(_) => Ok(cx.boxed(js_player.unwrap())),
-            Err(e) => cx.throw_error(format!("Failed to create player: {}", e.to_string())),
+
+        match res {
+            Ok(credentials) => {
+                let discovered_credentials = credentials.clone();
+                listeners.emit(&channel, move |cx| {
+                    create_js_obj_from_credentials(
+                        cx,
+                        "CredentialsDiscovered",
+                        discovered_credentials,
+                    )
+                });
+
+                deferred.settle_with(&channel, move |mut cx| {
+                    let js_player = JsPlayerSpircWrapper::new(
+                        &mut cx,
+                        listeners,
+                        position_interval,
+                        reconnect_config,
+                        canvas_cache,
+                        token_cache,
+                        credentials,
+                        session_config,
+                        player_config,
+                        connect_config,
+                        cache_config,
+                        backend,
+                        pcm_output,
+                        capture,
+                        mixer_settings,
+                    );
+                    match js_player {
+                        Ok(_) => Ok(cx.boxed(js_player.unwrap())),
+                        Err(e) => e.prefixed("Failed to create player").throw(&mut cx),
+                    }
+                });
+            }
+            Err(e) => {
+                deferred.settle_with(&channel, move |mut cx| {
+                    NativeError::from(e)
+                        .prefixed("Failed to discover credentials")
+                        .throw::<_, Handle<JsUndefined>>(&mut cx)
+                });
+            }
         }
     });
 
@@ -135,7 +374,7 @@ fn play_spirc(cx: FunctionContext) -> JsResult<JsPromise> {
     let promise = send_to_spirc(cx, move |player, _, channel, deferred| {
         let res = player.play();
         deferred.settle_with(channel, move |mut cx| {
-            res.or_else(|err| cx.throw_error(err.to_string()))?;
+            res.or_throw(&mut cx)?;
             Ok(cx.undefined())
         });
     });
@@ -147,7 +386,7 @@ fn pause_spirc(cx: FunctionContext) -> JsResult<JsPromise> {
     let promise = send_to_spirc(cx, move |player, _, channel, deferred| {
         let res = player.pause();
         deferred.settle_with(channel, move |mut cx| {
-            res.or_else(|err| cx.throw_error(err.to_string()))?;
+            res.or_throw(&mut cx)?;
             Ok(cx.undefined())
         });
     });
@@ -158,10 +397,17 @@ fn pause_spirc(cx: FunctionContext) -> JsResult<JsPromise> {
 fn seek_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let pos_ms = cx.argument::<JsNumber>(0)?.value(&mut cx);
 
+    if let Ok(p) = cx
+        .this()
+        .downcast::<JsBox<JsPlayerSpircWrapper>, _>(&mut cx)
+    {
+        p.position().lock().unwrap().seek(pos_ms as u32);
+    }
+
     let promise = send_to_spirc(cx, move |player, _, channel, deferred| {
         let res = player.set_position_ms(pos_ms as u32);
         deferred.settle_with(channel, move |mut cx| {
-            res.or_else(|err| cx.throw_error(err.to_string()))?;
+            res.or_throw(&mut cx)?;
             Ok(cx.undefined())
         });
     });
@@ -174,7 +420,114 @@ fn set_volume_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let promise = send_to_spirc(cx, move |player, _, channel, deferred| {
         let res = player.set_volume(volume as u16);
         deferred.settle_with(channel, move |mut cx| {
-            res.or_else(|err| cx.throw_error(err.to_string()))?;
+            res.or_throw(&mut cx)?;
+            Ok(cx.undefined())
+        });
+    });
+
+    Ok(promise)
+}
+
+fn play_pause_spirc(cx: FunctionContext) -> JsResult<JsPromise> {
+    let promise = send_to_spirc(cx, move |player, _, channel, deferred| {
+        let res = player.play_pause();
+        deferred.settle_with(channel, move |mut cx| {
+            res.or_throw(&mut cx)?;
+            Ok(cx.undefined())
+        });
+    });
+
+    Ok(promise)
+}
+
+fn next_spirc(cx: FunctionContext) -> JsResult<JsPromise> {
+    let promise = send_to_spirc(cx, move |player, _, channel, deferred| {
+        let res = player.next();
+        deferred.settle_with(channel, move |mut cx| {
+            res.or_throw(&mut cx)?;
+            Ok(cx.undefined())
+        });
+    });
+
+    Ok(promise)
+}
+
+fn prev_spirc(cx: FunctionContext) -> JsResult<JsPromise> {
+    let promise = send_to_spirc(cx, move |player, _, channel, deferred| {
+        let res = player.prev();
+        deferred.settle_with(channel, move |mut cx| {
+            res.or_throw(&mut cx)?;
+            Ok(cx.undefined())
+        });
+    });
+
+    Ok(promise)
+}
+
+fn volume_up_spirc(cx: FunctionContext) -> JsResult<JsPromise> {
+    let promise = send_to_spirc(cx, move |player, _, channel, deferred| {
+        let res = player.volume_up();
+        deferred.settle_with(channel, move |mut cx| {
+            res.or_throw(&mut cx)?;
+            Ok(cx.undefined())
+        });
+    });
+
+    Ok(promise)
+}
+
+fn volume_down_spirc(cx: FunctionContext) -> JsResult<JsPromise> {
+    let promise = send_to_spirc(cx, move |player, _, channel, deferred| {
+        let res = player.volume_down();
+        deferred.settle_with(channel, move |mut cx| {
+            res.or_throw(&mut cx)?;
+            Ok(cx.undefined())
+        });
+    });
+
+    Ok(promise)
+}
+
+fn activate_spirc(cx: FunctionContext) -> JsResult<JsPromise> {
+    let promise = send_to_spirc(cx, move |player, _, channel, deferred| {
+        let res = player.activate();
+        deferred.settle_with(channel, move |mut cx| {
+            res.or_throw(&mut cx)?;
+            Ok(cx.undefined())
+        });
+    });
+
+    Ok(promise)
+}
+
+fn disconnect_spirc(cx: FunctionContext) -> JsResult<JsPromise> {
+    let promise = send_to_spirc(cx, move |player, _, channel, deferred| {
+        let res = player.disconnect();
+        deferred.settle_with(channel, move |mut cx| {
+            res.or_throw(&mut cx)?;
+            Ok(cx.undefined())
+        });
+    });
+
+    Ok(promise)
+}
+
+fn shuffle_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let shuffle = cx.argument::<JsBoolean>(0)?.value(&mut cx);
+    let context = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerSpircWrapper>, _>(&mut cx)?
+        .context();
+
+    let promise = send_to_spirc(cx, move |player, _, channel, deferred| {
+        let res = player.shuffle(shuffle);
+        if res.is_ok() {
+            if let Some(context) = context.lock().unwrap().as_mut() {
+                context.shuffle = shuffle;
+            }
+        }
+        deferred.settle_with(channel, move |mut cx| {
+            res.or_throw(&mut cx)?;
             Ok(cx.undefined())
         });
     });
@@ -182,12 +535,169 @@ fn set_volume_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
     Ok(promise)
 }
 
+fn repeat_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let repeat = cx.argument::<JsBoolean>(0)?.value(&mut cx);
+    let context = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerSpircWrapper>, _>(&mut cx)?
+        .context();
+
+    let promise = send_to_spirc(cx, move |player, _, channel, deferred| {
+        let res = player.repeat(repeat);
+        if res.is_ok() {
+            if let Some(context) = context.lock().unwrap().as_mut() {
+                context.repeat = repeat;
+            }
+        }
+        deferred.settle_with(channel, move |mut cx| {
+            res.or_throw(&mut cx)?;
+            Ok(cx.undefined())
+        });
+    });
+
+    Ok(promise)
+}
+
+// Downloads can take a while, so they run on their own thread instead of
+// blocking the player's command thread
+fn settle_canvas_file(
+    channel: &Channel,
+    deferred: Deferred,
+    session: Session,
+    canvas_cache: Option<Arc<CanvasCache>>,
+    entity_uri: String,
+) {
+    let channel = channel.clone();
+    thread::spawn(move || {
+        let res = match canvas_cache {
+            Some(canvas_cache) => {
+                player::get_canvas_file(session, &canvas_cache, entity_uri.clone())
+            }
+            None => Err(NativeError::new(
+                ErrorCode::InvalidConfig,
+                "Canvas cache is not configured",
+            )),
+        };
+
+        deferred.settle_with(&channel, move |mut cx| match res {
+            Ok(Some(path)) => Ok(cx.string(path.to_string_lossy()).upcast::<JsValue>()),
+            Ok(None) => Ok(cx.undefined().upcast()),
+            Err(err) => err.context("uri", &entity_uri).throw(&mut cx),
+        });
+    });
+}
+
+// The tracks of the context are resolved on their own thread as well, only
+// the resolved load is sent back to the command thread
+fn settle_context_load(
+    channel: &Channel,
+    deferred: Deferred,
+    session: Session,
+    commands: mpsc::Sender<SpircMessage>,
+    context: SharedContext,
+    context_uri: String,
+    options: ContextLoadOptions,
+) {
+    let channel = channel.clone();
+    thread::spawn(move || {
+        let position_ms = options.position_ms;
+        let command = match player::context_load_command(session, context_uri, options) {
+            Ok(command) => command,
+            Err(err) => {
+                deferred.settle_with(&channel, move |mut cx| {
+                    NativeError::from(err).throw::<_, Handle<JsUndefined>>(&mut cx)
+                });
+                return;
+            }
+        };
+
+        let res = send_command(&commands, deferred, move |player, _, channel, deferred| {
+            let res = player::load_context(player, &context, command, position_ms);
+            deferred.settle_with(channel, move |mut cx| {
+                res.or_throw(&mut cx)?;
+                Ok(cx.undefined())
+            });
+        });
+
+        if let Err(deferred) = res {
+            deferred.settle_with(&channel, move |mut cx| {
+                NativeError::player_closed().throw::<_, Handle<JsUndefined>>(&mut cx)
+            });
+        }
+    });
+}
+
+fn settle_canvases(
+    channel: &Channel,
+    deferred: Deferred,
+    session: Session,
+    entity_uris: Vec<String>,
+) {
+    let channel = channel.clone();
+    thread::spawn(move || {
+        let res = player::get_canvases(session, entity_uris);
+
+        deferred.settle_with(&channel, move |mut cx| {
+            let batch = res.or_throw(&mut cx)?;
+            let (parsed_obj, _) = create_js_obj_from_canvas_batch(cx, batch);
+            Ok(parsed_obj)
+        });
+    });
+}
+
+fn settle_metadata(channel: &Channel, deferred: Deferred, session: Session, uri: String) {
+    let channel = channel.clone();
+    thread::spawn(move || {
+        let res = player::get_metadata(session, uri);
+
+        deferred.settle_with(&channel, move |mut cx| {
+            let item = res.or_throw(&mut cx)?;
+            let (parsed_obj, _) = create_js_obj_from_metadata(cx, item);
+            Ok(parsed_obj)
+        });
+    });
+}
+
+fn settle_token(
+    channel: &Channel,
+    deferred: Deferred,
+    session: Session,
+    cache: Arc<TokenCache>,
+    scopes: Vec<String>,
+) {
+    let channel = channel.clone();
+    thread::spawn(move || {
+        let res = block_on(token_cache::get_token(&session, &cache, &scopes));
+
+        deferred.settle_with(&channel, move |mut cx| {
+            let token = res.context("scopes", scopes.join(",")).or_throw(&mut cx)?;
+            let (obj, _) = token_to_obj(cx, token);
+            Ok(obj)
+        });
+    });
+}
+
+fn capture_to_js(mut cx: FunctionContext, capture: Option<Arc<Capture>>) -> JsResult<JsObject> {
+    let capture = match capture {
+        Some(capture) => capture,
+        None => {
+            return NativeError::new(
+                ErrorCode::Unavailable,
+                "Captured audio is only available with the capture backend",
+            )
+            .throw(&mut cx)
+        }
+    };
+
+    let (obj, _) = create_js_obj_from_capture(cx, capture.segments());
+    Ok(obj)
+}
+
 fn get_canvas_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let track_uri = cx.argument::<JsString>(0)?.value(&mut cx);
     let promise = send_to_spirc(cx, move |_, session, channel, deferred| {
         deferred.settle_with(channel, move |mut cx| {
-            let d = player::get_canvas(&mut cx, track_uri, session.clone())
-                .or_else(|err| cx.throw(err))?;
+            let d = player::get_canvas(track_uri, session).or_throw(&mut cx)?;
 
             let (parsed_obj, _) = create_js_obj_from_canvas(cx, d);
             Ok(parsed_obj)
@@ -197,13 +707,56 @@ fn get_canvas_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
     Ok(promise)
 }
 
+fn get_canvas_file_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let entity_uri = cx.argument::<JsString>(0)?.value(&mut cx);
+    let canvas_cache = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerSpircWrapper>, _>(&mut cx)?
+        .canvas_cache();
+
+    let promise = send_to_spirc(cx, move |_, session, channel, deferred| {
+        settle_canvas_file(channel, deferred, session, canvas_cache, entity_uri);
+    });
+
+    Ok(promise)
+}
+
+fn get_canvases_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let uris = cx.argument::<JsArray>(0)?;
+    let entity_uris: Vec<String> = get_spotify_ids_from_arr(&mut cx, uris)?
+        .iter()
+        .map(|id| id.to_string())
+        .collect();
+
+    let promise = send_to_spirc(cx, move |_, session, channel, deferred| {
+        settle_canvases(channel, deferred, session, entity_uris);
+    });
+
+    Ok(promise)
+}
+
+fn get_metadata_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let uri = cx.argument::<JsString>(0)?.value(&mut cx);
+    let promise = send_to_spirc(cx, move |_, session, channel, deferred| {
+        settle_metadata(channel, deferred, session, uri);
+    });
+
+    Ok(promise)
+}
+
 fn get_lyrics_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let track_uri = cx.argument::<JsString>(0)?.value(&mut cx);
     let promise = send_to_spirc(cx, move |_, session, channel, deferred| {
         deferred.settle_with(channel, move |mut cx| {
-            let resp =
-                player::get_lyrics(&mut cx, track_uri, session).or_else(|err| cx.throw(err))?;
-            Ok(cx.string(resp))
+            let resp = player::get_lyrics(track_uri, session).or_throw(&mut cx)?;
+
+            match resp {
+                Some(lyrics) => {
+                    let (parsed_obj, _) = create_js_obj_from_lyrics(cx, lyrics);
+                    Ok(parsed_obj.upcast::<JsValue>())
+                }
+                None => Ok(cx.undefined().upcast()),
+            }
         });
     });
 
@@ -212,10 +765,9 @@ fn get_lyrics_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
 
 fn get_canvas(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let track_uri = cx.argument::<JsString>(0)?.value(&mut cx);
-    let promise = send_to_player(cx, move |_, _, session, channel, deferred| {
+    let promise = send_to_player(cx, move |_, _, _, session, channel, deferred| {
         deferred.settle_with(channel, move |mut cx| {
-            let d = player::get_canvas(&mut cx, track_uri, session.clone())
-                .or_else(|err| cx.throw(err))?;
+            let d = player::get_canvas(track_uri, session).or_throw(&mut cx)?;
 
             let (parsed_obj, _) = create_js_obj_from_canvas(cx, d);
             Ok(parsed_obj)
@@ -225,26 +777,132 @@ fn get_canvas(mut cx: FunctionContext) -> JsResult<JsPromise> {
     Ok(promise)
 }
 
+fn get_canvas_file(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let entity_uri = cx.argument::<JsString>(0)?.value(&mut cx);
+    let canvas_cache = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerWrapper>, _>(&mut cx)?
+        .canvas_cache();
+
+    let promise = send_to_player(cx, move |_, _, _, session, channel, deferred| {
+        settle_canvas_file(channel, deferred, session, canvas_cache, entity_uri);
+    });
+
+    Ok(promise)
+}
+
+fn get_canvases(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let uris = cx.argument::<JsArray>(0)?;
+    let entity_uris: Vec<String> = get_spotify_ids_from_arr(&mut cx, uris)?
+        .iter()
+        .map(|id| id.to_string())
+        .collect();
+
+    let promise = send_to_player(cx, move |_, _, _, session, channel, deferred| {
+        settle_canvases(channel, deferred, session, entity_uris);
+    });
+
+    Ok(promise)
+}
+
+fn get_metadata(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let uri = cx.argument::<JsString>(0)?.value(&mut cx);
+    let promise = send_to_player(cx, move |_, _, _, session, channel, deferred| {
+        settle_metadata(channel, deferred, session, uri);
+    });
+
+    Ok(promise)
+}
+
 fn get_lyrics(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let track_uri = cx.argument::<JsString>(0)?.value(&mut cx);
-    let promise = send_to_player(cx, move |_, _, session, channel, deferred| {
+    let promise = send_to_player(cx, move |_, _, _, session, channel, deferred| {
         deferred.settle_with(channel, move |mut cx| {
-            let resp =
-                player::get_lyrics(&mut cx, track_uri, session).or_else(|err| cx.throw(err))?;
-            Ok(cx.string(resp))
+            let resp = player::get_lyrics(track_uri, session).or_throw(&mut cx)?;
+
+            match resp {
+                Some(lyrics) => {
+                    let (parsed_obj, _) = create_js_obj_from_lyrics(cx, lyrics);
+                    Ok(parsed_obj.upcast::<JsValue>())
+                }
+                None => Ok(cx.undefined().upcast()),
+            }
         });
     });
 
     Ok(promise)
 }
 
-fn close_player_spirc(mut cx: FunctionContext) -> JsResult<JsUndefined> {
-    cx.this()
+fn load_context_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let context_uri = cx.argument::<JsString>(0)?.value(&mut cx);
+    let options = cx.argument::<JsObject>(1)?;
+    let options = get_context_options_from_obj(&mut cx, options)?;
+    let player_wrapper = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerSpircWrapper>, _>(&mut cx)?;
+    let context = player_wrapper.context();
+    let commands = player_wrapper.commands();
+
+    let promise = send_to_spirc(cx, move |_, session, channel, deferred| {
+        settle_context_load(
+            channel,
+            deferred,
+            session,
+            commands,
+            context,
+            context_uri,
+            options,
+        );
+    });
+
+    Ok(promise)
+}
+
+fn close_player_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let (deferred, promise) = cx.promise();
+    let res = cx
+        .this()
         .downcast_or_throw::<JsBox<JsPlayerSpircWrapper>, _>(&mut cx)?
-        .close()
-        .or_else(|err| cx.throw_error(err.to_string()))?;
+        .close(deferred);
 
-    Ok(cx.undefined())
+    if let Err(deferred) = res {
+        let error =
+            NativeError::new(ErrorCode::PlayerClosed, "Player is already closed").to_js(&mut cx)?;
+        deferred.reject(&mut cx, error);
+    }
+
+    Ok(promise)
+}
+
+fn update_config_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let config = cx.argument::<JsObject>(0)?;
+    let mut reader = get_config_reader(&mut cx, config)?;
+    let player_config = get_player_config_from_obj(&mut cx, config, &mut reader)?;
+    let backend = get_backend_from_obj(&mut cx, config, &mut reader)?;
+    reader.check(&mut cx)?;
+
+    let player_wrapper = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerSpircWrapper>, _>(&mut cx)?;
+    let channel = cx.channel();
+    emit_config_warning(&channel, &player_wrapper.listeners(), &mut reader);
+    backend
+        .validate_update(
+            player_wrapper.pcm_output().is_some(),
+            player_wrapper.capture().is_some(),
+        )
+        .or_throw(&mut cx)?;
+
+    let (deferred, promise) = cx.promise();
+    let res = player_wrapper.update_config(ConfigUpdate::new(player_config, backend, deferred));
+
+    if let Err(deferred) = res {
+        let error =
+            NativeError::new(ErrorCode::PlayerClosed, "Player is already closed").to_js(&mut cx)?;
+        deferred.reject(&mut cx, error);
+    }
+
+    Ok(promise)
 }
 
 fn get_device_id_spirc(mut cx: FunctionContext) -> JsResult<JsValue> {
@@ -261,66 +919,181 @@ fn get_device_id_spirc(mut cx: FunctionContext) -> JsResult<JsValue> {
     return Ok(cx.undefined().as_value(&mut cx));
 }
 
+fn add_event_listener_spirc(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let callback = cx.argument::<JsFunction>(0)?.root(&mut cx);
+    let id = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerSpircWrapper>, _>(&mut cx)?
+        .listeners()
+        .add(callback);
+
+    Ok(cx.number(id))
+}
+
+fn remove_event_listener_spirc(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let id = cx.argument::<JsNumber>(0)?.value(&mut cx);
+    let removed = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerSpircWrapper>, _>(&mut cx)?
+        .listeners()
+        .remove(id as u32);
+
+    let found = removed.is_some();
+    if let Some(callback) = removed {
+        callback.drop(&mut cx);
+    }
+    Ok(cx.boolean(found))
+}
+
+fn get_instance_id_spirc(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let id = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerSpircWrapper>, _>(&mut cx)?
+        .listeners()
+        .instance_id();
+
+    Ok(cx.number(id))
+}
+
+fn get_position_spirc(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let position_ms = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerSpircWrapper>, _>(&mut cx)?
+        .position()
+        .lock()
+        .unwrap()
+        .position_ms();
+
+    Ok(cx.number(position_ms))
+}
+
 fn get_token_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
-    let scopes = cx.argument::<JsString>(0)?.value(&mut cx);
-    let promise = send_to_spirc(cx, move |_, session, channel, deferred| {
-        deferred.settle_with(channel, move |mut cx| -> Result<Handle<JsValue>, _> {
-            let res = block_on(session.token_provider().get_token(scopes.as_str()));
+    let scopes = cx.argument::<JsArray>(0)?;
+    let scopes = get_strings_from_arr(&mut cx, scopes)?;
+    let token_cache = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerSpircWrapper>, _>(&mut cx)?
+        .token_cache();
 
-            match res {
-                Ok(t) => {
-                    let (obj, mut cx) = token_to_obj(cx, t);
-                    Ok(obj.as_value(&mut cx))
-                }
-                Err(_) => Ok(cx.undefined().as_value(&mut cx)),
-            }
-        });
+    let promise = send_to_spirc(cx, move |_, session, channel, deferred| {
+        settle_token(channel, deferred, session, token_cache, scopes);
     });
 
     Ok(promise)
 }
 
+fn resume_pcm_spirc(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let pcm_output = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerSpircWrapper>, _>(&mut cx)?
+        .pcm_output();
+
+    if let Some(pcm_output) = pcm_output {
+        pcm_output.resume();
+    }
+    Ok(cx.undefined())
+}
+
+fn get_capture_spirc(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let capture = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerSpircWrapper>, _>(&mut cx)?
+        .capture();
+
+    capture_to_js(cx, capture)
+}
+
+fn clear_capture_spirc(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let capture = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerSpircWrapper>, _>(&mut cx)?
+        .capture();
+
+    if let Some(capture) = capture {
+        capture.clear();
+    }
+    Ok(cx.undefined())
+}
+
+fn get_volume_spirc(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let volume = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerSpircWrapper>, _>(&mut cx)?
+        .volume();
+
+    match volume {
+        Some(volume) => Ok(cx.number(volume).upcast()),
+        None => Ok(cx.undefined().upcast()),
+    }
+}
+
 fn create_player(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let config = cx.argument::<JsObject>(0)?;
 
-    let credentials = get_credentials_from_obj(&mut cx, config)?;
-    let player_config = get_player_config_from_obj(&mut cx, config)?;
-    let backend = config
-        .get::<JsString, _, _>(&mut cx, "backend")?
-        .value(&mut cx);
-    let cache_config = get_cache_config_from_obj(&mut cx, config)?;
-    let volume_ctrl = get_volume_ctrl_from_obj(&mut cx, config)?;
+    let mut reader = get_config_reader(&mut cx, config)?;
+    let player_config = get_player_config_from_obj(&mut cx, config, &mut reader)?;
+    let session_config = get_session_config_from_obj(&mut cx, config, &mut reader)?;
+    let cache_settings = get_cache_settings_from_obj(&mut cx, config, &mut reader)?;
+    let login = get_login_from_obj(&mut cx, config, &mut reader)?;
+    let backend = get_backend_from_obj(&mut cx, config, &mut reader)?;
+    let mixer_settings = get_mixer_from_obj(&mut cx, config, &mut reader)?;
+    let position_interval = get_position_interval_from_obj(&mut cx, config, &mut reader)?;
+    let reconnect_config = get_reconnect_config_from_obj(&mut cx, config, &mut reader)?;
+    reader.check(&mut cx)?;
+
+    let cache_config = cache_settings.cache().or_throw(&mut cx)?;
+    let canvas_cache = cache_settings.canvas_cache().or_throw(&mut cx)?;
+    let token_cache = cache_settings.token_cache();
+    let login = login.resolve(&cache_config).or_throw(&mut cx)?;
+    let pcm_output = get_pcm_output_from_obj(&mut cx, config, &backend.name)?;
+    let capture = (backend.name == CAPTURE_BACKEND).then(|| Arc::new(Capture::new()));
 
     let callback = cx.argument::<JsFunction>(1)?;
 
     let (deferred, promise) = cx.promise();
     let channel = cx.channel();
 
-    let global = cx.global();
-    global
-        .set(&mut cx, GLOBAL_JS_CALLBACK_METHOD, callback)
-        .unwrap();
-
-    deferred.settle_with(&channel, move |mut cx| {
-        let js_player = JsPlayerWrapper::new(
-            &mut cx,
-            credentials,
-            player_config,
-            cache_config,
-            backend,
-            volume_ctrl,
-        );
-        match js_player {
-            Ok(_) => Ok(cx.boxed(js_player.unwrap())),
-            Err(e) => cx.throw_error(format!("Failed to create player: {}", e.to_string())),
-        }
-    });
+    let listeners = EventListeners::new();
+    listeners.add(callback.root(&mut cx));
+    emit_config_warning(&channel, &listeners, &mut reader);
+
+    login_then(
+        login,
+        session_config.proxy.clone(),
+        channel,
+        deferred,
+        listeners,
+        move |channel, deferred, listeners, credentials| {
+            deferred.settle_with(&channel, move |mut cx| {
+                let js_player = JsPlayerWrapper::new(
+                    &mut cx,
+                    listeners,
+                    position_interval,
+                    reconnect_config,
+                    canvas_cache,
+                    token_cache,
+                    credentials,
+                    session_config,
+                    player_config,
+                    cache_config,
+                    backend,
+                    pcm_output,
+                    capture,
+                    mixer_settings,
+                );
+                match js_player {
+                    Ok(_) => Ok(cx.boxed(js_player.unwrap())),
+                    Err(e) => e.prefixed("Failed to create player").throw(&mut cx),
+                }
+            });
+        },
+    );
 
     return Ok(promise);
 }
 
 fn play(cx: FunctionContext) -> JsResult<JsPromise> {
-    let promise = send_to_player(cx, move |player, _, _, channel, deferred| {
+    let promise = send_to_player(cx, move |player, _, _, _, channel, deferred| {
         player.play();
         deferred.settle_with(channel, move |mut cx| Ok(cx.undefined()));
     });
@@ -329,7 +1102,7 @@ fn play(cx: FunctionContext) -> JsResult<JsPromise> {
 }
 
 fn pause(cx: FunctionContext) -> JsResult<JsPromise> {
-    let promise = send_to_player(cx, move |player, _, _, channel, deferred| {
+    let promise = send_to_player(cx, move |player, _, _, _, channel, deferred| {
         player.pause();
         deferred.settle_with(channel, move |mut cx| Ok(cx.undefined()));
     });
@@ -340,7 +1113,11 @@ fn pause(cx: FunctionContext) -> JsResult<JsPromise> {
 fn seek(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let pos_ms = cx.argument::<JsNumber>(0)?.value(&mut cx);
 
-    let promise = send_to_player(cx, move |player, _, _, channel, deferred| {
+    if let Ok(p) = cx.this().downcast::<JsBox<JsPlayerWrapper>, _>(&mut cx) {
+        p.position().lock().unwrap().seek(pos_ms as u32);
+    }
+
+    let promise = send_to_player(cx, move |player, _, _, _, channel, deferred| {
         player.seek(pos_ms as u32);
         deferred.settle_with(channel, move |mut cx| Ok(cx.undefined()));
     });
@@ -350,7 +1127,7 @@ fn seek(mut cx: FunctionContext) -> JsResult<JsPromise> {
 
 fn set_volume(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let volume = cx.argument::<JsNumber>(0)?.value(&mut cx);
-    let promise = send_to_player(cx, move |_, mixer, _, channel, deferred| {
+    let promise = send_to_player(cx, move |_, mixer, _, _, channel, deferred| {
         mixer.set_volume(volume as u16);
         deferred.settle_with(channel, move |mut cx| Ok(cx.undefined()));
     });
@@ -363,17 +1140,162 @@ fn load_track(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let auto_play = cx.argument::<JsBoolean>(1)?.value(&mut cx);
     let pos_ms = cx.argument::<JsNumber>(2)?.value(&mut cx);
 
-    let promise = send_to_player(cx, move |player, _, _, channel, deferred| {
-        let track_id_res = SpotifyId::from_uri(track_uri.as_str());
-        if track_id_res.is_err() {
-            deferred.settle_with(channel, move |mut cx| {
-                cx.error(track_id_res.err().unwrap().to_string().as_str())
-            });
-            return;
+    let promise = send_to_queue(cx, move |player, queue| {
+        let track_id = SpotifyId::from_uri(track_uri.as_str()).context("uri", &track_uri)?;
+
+        queue.replace(track_id);
+        queue.load_current(player, auto_play, pos_ms as u32);
+        Ok(())
+    })?;
+
+    Ok(promise)
+}
+
+fn queue_add(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let uris = cx.argument::<JsArray>(0)?;
+    let tracks = get_spotify_ids_from_arr(&mut cx, uris)?;
+
+    let promise = send_to_queue(cx, move |_, queue| {
+        queue.enqueue(tracks);
+        Ok(())
+    })?;
+
+    Ok(promise)
+}
+
+fn queue_insert(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let index = cx.argument::<JsNumber>(0)?.value(&mut cx);
+    let uris = cx.argument::<JsArray>(1)?;
+    let tracks = get_spotify_ids_from_arr(&mut cx, uris)?;
+
+    let promise = send_to_queue(cx, move |_, queue| {
+        queue.insert(index as usize, tracks);
+        Ok(())
+    })?;
+
+    Ok(promise)
+}
+
+fn invalid_queue_index(index: f64) -> NativeError {
+    NativeError::new(
+        ErrorCode::InvalidArgument,
+        format!("No track at queue index {}", index),
+    )
+}
+
+fn queue_remove(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let index = cx.argument::<JsNumber>(0)?.value(&mut cx);
+
+    let promise = send_to_queue(cx, move |_, queue| {
+        queue
+            .remove(index as usize)
+            .map(|_| ())
+            .ok_or_else(|| invalid_queue_index(index))
+    })?;
+
+    Ok(promise)
+}
+
+fn queue_move(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let from = cx.argument::<JsNumber>(0)?.value(&mut cx);
+    let to = cx.argument::<JsNumber>(1)?.value(&mut cx);
+
+    let promise = send_to_queue(cx, move |_, queue| {
+        if queue.move_track(from as usize, to as usize) {
+            Ok(())
+        } else {
+            Err(NativeError::new(
+                ErrorCode::InvalidArgument,
+                format!("Cannot move queue index {} to {}", from, to),
+            ))
         }
+    })?;
 
-        player.load(track_id_res.unwrap(), auto_play, pos_ms as u32);
-        deferred.settle_with(channel, move |mut cx| Ok(cx.undefined()));
+    Ok(promise)
+}
+
+fn queue_clear(cx: FunctionContext) -> JsResult<JsPromise> {
+    let promise = send_to_queue(cx, move |_, queue| {
+        queue.clear();
+        Ok(())
+    })?;
+
+    Ok(promise)
+}
+
+fn queue_play(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let index = cx.argument::<JsNumber>(0)?.value(&mut cx);
+    let auto_play = cx.argument::<JsBoolean>(1)?.value(&mut cx);
+    let pos_ms = cx.argument::<JsNumber>(2)?.value(&mut cx);
+
+    let promise = send_to_queue(cx, move |player, queue| {
+        queue
+            .jump(index as usize)
+            .ok_or_else(|| invalid_queue_index(index))?;
+        queue.load_current(player, auto_play, pos_ms as u32);
+        Ok(())
+    })?;
+
+    Ok(promise)
+}
+
+fn queue_next(cx: FunctionContext) -> JsResult<JsPromise> {
+    let promise = send_to_queue(cx, move |player, queue| {
+        if queue.next(true).is_some() {
+            queue.load_current(player, true, 0);
+        }
+        Ok(())
+    })?;
+
+    Ok(promise)
+}
+
+fn queue_previous(cx: FunctionContext) -> JsResult<JsPromise> {
+    let promise = send_to_queue(cx, move |player, queue| {
+        if queue.previous().is_some() {
+            queue.load_current(player, true, 0);
+        }
+        Ok(())
+    })?;
+
+    Ok(promise)
+}
+
+fn queue_set_repeat(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mode = cx.argument::<JsString>(0)?.value(&mut cx);
+
+    let promise = send_to_queue(cx, move |_, queue| {
+        let repeat = RepeatMode::from_str(mode.as_str()).ok_or_else(|| {
+            NativeError::new(
+                ErrorCode::InvalidArgument,
+                format!("Invalid repeat mode {}", mode),
+            )
+        })?;
+        queue.set_repeat(repeat);
+        Ok(())
+    })?;
+
+    Ok(promise)
+}
+
+fn queue_set_shuffle(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let shuffle = cx.argument::<JsBoolean>(0)?.value(&mut cx);
+
+    let promise = send_to_queue(cx, move |_, queue| {
+        queue.set_shuffle(shuffle);
+        Ok(())
+    })?;
+
+    Ok(promise)
+}
+
+fn get_queue(cx: FunctionContext) -> JsResult<JsPromise> {
+    let promise = send_to_player(cx, move |_, _, queue, _, channel, deferred| {
+        let state = queue.lock().unwrap().state();
+        deferred.settle_with(channel, move |cx| {
+            let (obj, _) = create_js_obj_from_queue(cx, state);
+            Ok(obj)
+        });
     });
 
     Ok(promise)
@@ -382,18 +1304,25 @@ fn load_track(mut cx: FunctionContext) -> JsResult<JsPromise> {
 fn load_track_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let track_uri = cx.argument::<JsString>(0)?.value(&mut cx);
     let auto_play = cx.argument::<JsBoolean>(1)?.value(&mut cx);
+    let context = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerSpircWrapper>, _>(&mut cx)?
+        .context();
 
     let promise = send_to_spirc(cx, move |player, _, channel, deferred| {
-        let track_id_res = SpotifyId::from_uri(track_uri.as_str());
-        if track_id_res.is_err() {
-            deferred.settle_with(channel, move |mut cx| {
-                cx.error(track_id_res.err().unwrap().to_string().as_str())
-            });
-            return;
-        }
+        let track_id_res = SpotifyId::from_uri(track_uri.as_str()).context("uri", &track_uri);
+        let track_id = match track_id_res {
+            Ok(track_id) => track_id,
+            Err(err) => {
+                deferred.settle_with(channel, move |mut cx| {
+                    err.throw::<_, Handle<JsUndefined>>(&mut cx)
+                });
+                return;
+            }
+        };
 
         let mut track_ref = TrackRef::new();
-        track_ref.set_gid(Vec::from(track_id_res.unwrap().to_raw()));
+        track_ref.set_gid(Vec::from(track_id.to_raw()));
 
         let command = SpircLoadCommand {
             context_uri: track_uri,
@@ -404,26 +1333,61 @@ fn load_track_spirc(mut cx: FunctionContext) -> JsResult<JsPromise> {
             tracks: vec![track_ref],
         };
 
-        let load_resp = player.load(command);
-        if load_resp.is_err() {
-            deferred.settle_with(channel, move |mut cx| {
-                cx.error(load_resp.err().unwrap().to_string().as_str())
-            });
-            return;
-        }
-        deferred.settle_with(channel, move |mut cx| Ok(cx.undefined()));
+        let load_resp = player::load_spirc(player, &context, command);
+        deferred.settle_with(channel, move |mut cx| {
+            load_resp.or_throw(&mut cx)?;
+            Ok(cx.undefined())
+        });
     });
 
     Ok(promise)
 }
 
-fn close_player(mut cx: FunctionContext) -> JsResult<JsUndefined> {
-    cx.this()
+fn close_player(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let (deferred, promise) = cx.promise();
+    let res = cx
+        .this()
         .downcast_or_throw::<JsBox<JsPlayerWrapper>, _>(&mut cx)?
-        .close()
-        .or_else(|err| cx.throw_error(err.to_string()))?;
+        .close(deferred);
 
-    Ok(cx.undefined())
+    if let Err(deferred) = res {
+        let error =
+            NativeError::new(ErrorCode::PlayerClosed, "Player is already closed").to_js(&mut cx)?;
+        deferred.reject(&mut cx, error);
+    }
+
+    Ok(promise)
+}
+
+fn update_config(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let config = cx.argument::<JsObject>(0)?;
+    let mut reader = get_config_reader(&mut cx, config)?;
+    let player_config = get_player_config_from_obj(&mut cx, config, &mut reader)?;
+    let backend = get_backend_from_obj(&mut cx, config, &mut reader)?;
+    reader.check(&mut cx)?;
+
+    let player_wrapper = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerWrapper>, _>(&mut cx)?;
+    let channel = cx.channel();
+    emit_config_warning(&channel, &player_wrapper.listeners(), &mut reader);
+    backend
+        .validate_update(
+            player_wrapper.pcm_output().is_some(),
+            player_wrapper.capture().is_some(),
+        )
+        .or_throw(&mut cx)?;
+
+    let (deferred, promise) = cx.promise();
+    let res = player_wrapper.update_config(ConfigUpdate::new(player_config, backend, deferred));
+
+    if let Err(deferred) = res {
+        let error =
+            NativeError::new(ErrorCode::PlayerClosed, "Player is already closed").to_js(&mut cx)?;
+        deferred.reject(&mut cx, error);
+    }
+
+    Ok(promise)
 }
 
 fn get_device_id(mut cx: FunctionContext) -> JsResult<JsValue> {
@@ -440,25 +1404,131 @@ fn get_device_id(mut cx: FunctionContext) -> JsResult<JsValue> {
     return Ok(cx.undefined().as_value(&mut cx));
 }
 
+fn add_event_listener(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let callback = cx.argument::<JsFunction>(0)?.root(&mut cx);
+    let id = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerWrapper>, _>(&mut cx)?
+        .listeners()
+        .add(callback);
+
+    Ok(cx.number(id))
+}
+
+fn remove_event_listener(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let id = cx.argument::<JsNumber>(0)?.value(&mut cx);
+    let removed = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerWrapper>, _>(&mut cx)?
+        .listeners()
+        .remove(id as u32);
+
+    let found = removed.is_some();
+    if let Some(callback) = removed {
+        callback.drop(&mut cx);
+    }
+    Ok(cx.boolean(found))
+}
+
+fn get_instance_id(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let id = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerWrapper>, _>(&mut cx)?
+        .listeners()
+        .instance_id();
+
+    Ok(cx.number(id))
+}
+
+fn get_position(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let position_ms = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerWrapper>, _>(&mut cx)?
+        .position()
+        .lock()
+        .unwrap()
+        .position_ms();
+
+    Ok(cx.number(position_ms))
+}
+
 fn get_token(mut cx: FunctionContext) -> JsResult<JsPromise> {
-    let scopes = cx.argument::<JsString>(0)?.value(&mut cx);
-    let promise = send_to_player(cx, move |_, _, session, channel, deferred| {
-        deferred.settle_with(channel, move |mut cx| -> Result<Handle<JsValue>, _> {
-            let res = block_on(session.token_provider().get_token(scopes.as_str()));
-
-            match res {
-                Ok(t) => {
-                    let (obj, mut cx) = token_to_obj(cx, t);
-                    Ok(obj.as_value(&mut cx))
-                }
-                Err(_) => Ok(cx.undefined().as_value(&mut cx)),
-            }
+    let scopes = cx.argument::<JsArray>(0)?;
+    let scopes = get_strings_from_arr(&mut cx, scopes)?;
+    let token_cache = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerWrapper>, _>(&mut cx)?
+        .token_cache();
+
+    let promise = send_to_player(cx, move |_, _, _, session, channel, deferred| {
+        settle_token(channel, deferred, session, token_cache, scopes);
+    });
+
+    Ok(promise)
+}
+
+fn resume_pcm(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let pcm_output = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerWrapper>, _>(&mut cx)?
+        .pcm_output();
+
+    if let Some(pcm_output) = pcm_output {
+        pcm_output.resume();
+    }
+    Ok(cx.undefined())
+}
+
+fn get_capture(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let capture = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerWrapper>, _>(&mut cx)?
+        .capture();
+
+    capture_to_js(cx, capture)
+}
+
+fn clear_capture(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let capture = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerWrapper>, _>(&mut cx)?
+        .capture();
+
+    if let Some(capture) = capture {
+        capture.clear();
+    }
+    Ok(cx.undefined())
+}
+
+fn get_audio_backends(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let (deferred, promise) = cx.promise();
+    let channel = cx.channel();
+
+    // Listing devices may block while the sound server responds
+    thread::spawn(move || {
+        let res = backends::list_backends();
+        deferred.settle_with(&channel, move |mut cx| {
+            let backends = res.or_throw(&mut cx)?;
+            let (arr, _) = create_js_arr_from_backends(cx, backends);
+            Ok(arr)
         });
     });
 
     Ok(promise)
 }
 
+fn get_volume(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let volume = cx
+        .this()
+        .downcast_or_throw::<JsBox<JsPlayerWrapper>, _>(&mut cx)?
+        .volume();
+
+    match volume {
+        Some(volume) => Ok(cx.number(volume).upcast()),
+        None => Ok(cx.undefined().upcast()),
+    }
+}
+
 fn get_log_level(str: &str) -> LevelFilter {
     match str.to_lowercase().as_str() {
         "debug" => LevelFilter::Debug,
@@ -481,30 +1551,80 @@ fn init_logger(mut cx: FunctionContext) -> JsResult<JsUndefined> {
 #[neon::main]
 pub fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("init_logger", init_logger)?;
+    cx.export_function("get_audio_backends", get_audio_backends)?;
 
     cx.export_function("create_player_spirc", create_player_spirc)?;
+    cx.export_function(
+        "create_player_spirc_discovery",
+        create_player_spirc_discovery,
+    )?;
     cx.export_function("play_spirc", play_spirc)?;
     cx.export_function("pause_spirc", pause_spirc)?;
     cx.export_function("seek_spirc", seek_spirc)?;
     cx.export_function("set_volume_spirc", set_volume_spirc)?;
+    cx.export_function("get_volume_spirc", get_volume_spirc)?;
+    cx.export_function("play_pause_spirc", play_pause_spirc)?;
+    cx.export_function("next_spirc", next_spirc)?;
+    cx.export_function("prev_spirc", prev_spirc)?;
+    cx.export_function("volume_up_spirc", volume_up_spirc)?;
+    cx.export_function("volume_down_spirc", volume_down_spirc)?;
+    cx.export_function("shuffle_spirc", shuffle_spirc)?;
+    cx.export_function("repeat_spirc", repeat_spirc)?;
+    cx.export_function("activate_spirc", activate_spirc)?;
+    cx.export_function("disconnect_spirc", disconnect_spirc)?;
     cx.export_function("close_player_spirc", close_player_spirc)?;
+    cx.export_function("update_config_spirc", update_config_spirc)?;
     cx.export_function("get_device_id_spirc", get_device_id_spirc)?;
     cx.export_function("get_token_spirc", get_token_spirc)?;
+    cx.export_function("resume_pcm_spirc", resume_pcm_spirc)?;
+    cx.export_function("get_capture_spirc", get_capture_spirc)?;
+    cx.export_function("clear_capture_spirc", clear_capture_spirc)?;
+    cx.export_function("add_event_listener_spirc", add_event_listener_spirc)?;
+    cx.export_function("remove_event_listener_spirc", remove_event_listener_spirc)?;
+    cx.export_function("get_instance_id_spirc", get_instance_id_spirc)?;
+    cx.export_function("get_position_spirc", get_position_spirc)?;
     cx.export_function("get_canvas_spirc", get_canvas_spirc)?;
+    cx.export_function("get_canvas_file_spirc", get_canvas_file_spirc)?;
+    cx.export_function("get_canvases_spirc", get_canvases_spirc)?;
+    cx.export_function("get_metadata_spirc", get_metadata_spirc)?;
     cx.export_function("get_lyrics_spirc", get_lyrics_spirc)?;
     cx.export_function("load_track_spirc", load_track_spirc)?;
+    cx.export_function("load_context_spirc", load_context_spirc)?;
 
     cx.export_function("create_player", create_player)?;
     cx.export_function("play", play)?;
     cx.export_function("pause", pause)?;
     cx.export_function("seek", seek)?;
     cx.export_function("set_volume", set_volume)?;
+    cx.export_function("get_volume", get_volume)?;
     cx.export_function("close_player", close_player)?;
+    cx.export_function("update_config", update_config)?;
     cx.export_function("get_device_id", get_device_id)?;
     cx.export_function("get_token", get_token)?;
+    cx.export_function("resume_pcm", resume_pcm)?;
+    cx.export_function("get_capture", get_capture)?;
+    cx.export_function("clear_capture", clear_capture)?;
+    cx.export_function("add_event_listener", add_event_listener)?;
+    cx.export_function("remove_event_listener", remove_event_listener)?;
+    cx.export_function("get_instance_id", get_instance_id)?;
+    cx.export_function("get_position", get_position)?;
     cx.export_function("load_track", load_track)?;
     cx.export_function("get_canvas", get_canvas)?;
+    cx.export_function("get_canvas_file", get_canvas_file)?;
+    cx.export_function("get_canvases", get_canvases)?;
+    cx.export_function("get_metadata", get_metadata)?;
     cx.export_function("get_lyrics", get_lyrics)?;
+    cx.export_function("queue_add", queue_add)?;
+    cx.export_function("queue_insert", queue_insert)?;
+    cx.export_function("queue_remove", queue_remove)?;
+    cx.export_function("queue_move", queue_move)?;
+    cx.export_function("queue_clear", queue_clear)?;
+    cx.export_function("queue_play", queue_play)?;
+    cx.export_function("queue_next", queue_next)?;
+    cx.export_function("queue_previous", queue_previous)?;
+    cx.export_function("queue_set_repeat", queue_set_repeat)?;
+    cx.export_function("queue_set_shuffle", queue_set_shuffle)?;
+    cx.export_function("get_queue", get_queue)?;
 
     Ok(())
 }
@@ -0,0 +1,175 @@
+use serde::{Deserialize, Deserializer};
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SyncType {
+    LineSynced,
+    SyllableSynced,
+    Unsynced,
+}
+
+impl SyncType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncType::LineSynced => "line",
+            SyncType::SyllableSynced => "syllable",
+            SyncType::Unsynced => "unsynced",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Syllable {
+    #[serde(deserialize_with = "u64_from_str_or_number")]
+    pub start_time_ms: u64,
+    #[serde(default, deserialize_with = "u64_from_str_or_number")]
+    pub num_chars: u64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Line {
+    #[serde(deserialize_with = "u64_from_str_or_number")]
+    pub start_time_ms: u64,
+    #[serde(default, deserialize_with = "u64_from_str_or_number")]
+    pub end_time_ms: u64,
+    #[serde(default)]
+    pub words: String,
+    #[serde(default)]
+    pub syllables: Vec<Syllable>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Lyrics {
+    pub sync_type: SyncType,
+    pub lines: Vec<Line>,
+    #[serde(default)]
+    pub provider: String,
+    #[serde(default)]
+    pub provider_lyrics_id: String,
+    #[serde(default)]
+    pub provider_display_name: String,
+    #[serde(default)]
+    pub language: String,
+    #[serde(default)]
+    pub is_rtl_language: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Colors {
+    pub background: i64,
+    pub text: i64,
+    pub highlight_text: i64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LyricsResponse {
+    pub lyrics: Lyrics,
+    pub colors: Option<Colors>,
+    #[serde(default)]
+    pub has_vocal_removal: bool,
+}
+
+// Timestamps are sent as strings ("960"), accept plain numbers as well
+fn u64_from_str_or_number<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StrOrNumber {
+        Str(String),
+        Number(u64),
+    }
+
+    match StrOrNumber::deserialize(deserializer)? {
+        StrOrNumber::Str(str) => str.parse().map_err(serde::de::Error::custom),
+        StrOrNumber::Number(number) => Ok(number),
+    }
+}
+
+pub fn parse_lyrics(body: &[u8]) -> Result<LyricsResponse, serde_json::Error> {
+    serde_json::from_slice(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_line_synced_lyrics() {
+        let body = br#"{
+            "lyrics": {
+                "syncType": "LINE_SYNCED",
+                "lines": [
+                    {"startTimeMs": "960", "words": "First line", "syllables": [], "endTimeMs": "0"},
+                    {"startTimeMs": "4320", "words": "Second line", "syllables": [], "endTimeMs": "0"}
+                ],
+                "provider": "MusixMatch",
+                "providerLyricsId": "1234",
+                "providerDisplayName": "Musixmatch",
+                "language": "en",
+                "isRtlLanguage": false
+            },
+            "colors": {"background": -9079435, "text": -16777216, "highlightText": -1},
+            "hasVocalRemoval": false
+        }"#;
+
+        let response = parse_lyrics(body).unwrap();
+        assert_eq!(response.lyrics.sync_type, SyncType::LineSynced);
+        assert_eq!(response.lyrics.lines.len(), 2);
+        assert_eq!(response.lyrics.lines[1].start_time_ms, 4320);
+        assert_eq!(response.lyrics.lines[1].words, "Second line");
+        assert_eq!(response.lyrics.provider_display_name, "Musixmatch");
+        assert_eq!(response.colors.unwrap().highlight_text, -1);
+    }
+
+    #[test]
+    fn parses_syllables_with_numeric_timestamps() {
+        let body = br#"{
+            "lyrics": {
+                "syncType": "SYLLABLE_SYNCED",
+                "lines": [{
+                    "startTimeMs": 1000,
+                    "endTimeMs": 2000,
+                    "words": "Hello world",
+                    "syllables": [
+                        {"startTimeMs": 1000, "numChars": 5},
+                        {"startTimeMs": "1500", "numChars": "5"}
+                    ]
+                }]
+            }
+        }"#;
+
+        let response = parse_lyrics(body).unwrap();
+        let line = &response.lyrics.lines[0];
+        assert_eq!(response.lyrics.sync_type, SyncType::SyllableSynced);
+        assert_eq!(line.end_time_ms, 2000);
+        assert_eq!(line.syllables[1].start_time_ms, 1500);
+        assert_eq!(line.syllables[1].num_chars, 5);
+        assert!(response.colors.is_none());
+    }
+
+    #[test]
+    fn optional_fields_may_be_left_out() {
+        let body = br#"{"lyrics": {"syncType": "UNSYNCED", "lines": [{"startTimeMs": "0"}]}}"#;
+
+        let response = parse_lyrics(body).unwrap();
+        assert_eq!(response.lyrics.sync_type.as_str(), "unsynced");
+        assert_eq!(response.lyrics.lines[0].words, "");
+        assert!(response.lyrics.provider.is_empty());
+        assert!(!response.has_vocal_removal);
+    }
+
+    #[test]
+    fn rejects_invalid_timestamps() {
+        let body =
+            br#"{"lyrics": {"syncType": "LINE_SYNCED", "lines": [{"startTimeMs": "soon"}]}}"#;
+
+        assert!(parse_lyrics(body).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use std::{collections::HashMap, time::Duration};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use librespot::{
+    core::cache::Cache, discovery::Credentials, protocol::authentication::AuthenticationType,
+};
+use log::{debug, info};
+use rand::{distributions::Alphanumeric, Rng};
+use reqwest::Url;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    error::{ErrorCode, NativeError},
+    player::proxied_http_client,
+};
+
+pub const DEFAULT_AUTH_URL: &str = "https://accounts.spotify.com/authorize";
+pub const DEFAULT_TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
+pub const DEFAULT_REDIRECT_PORT: u16 = 8898;
+const REDIRECT_PATH: &str = "/login";
+
+pub struct OAuthConfig {
+    pub client_id: String,
+    pub auth_url: String,
+    pub token_url: String,
+    /// 0 picks a free port
+    pub redirect_port: u16,
+    pub scopes: Vec<String>,
+    /// How long to wait for the user to complete the login in the browser
+    pub timeout: Duration,
+    /// Username sent along with the token, may be left empty
+    pub username: String,
+}
+
+/// How the session logs in, either with credentials known upfront or through
+/// the OAuth flow which has to wait for the browser
+pub enum Login {
+    Credentials(Credentials),
+    OAuth(OAuthConfig),
+}
+
+/// Login read from the config. Credentials saved by an earlier login can
+/// only be looked up once the cache is created
+pub enum LoginConfig {
+    Login(Login),
+    FromCache,
+}
+
+impl LoginConfig {
+    pub fn resolve(self, cache: &Cache) -> Result<Login, NativeError> {
+        match self {
+            LoginConfig::Login(login) => Ok(login),
+            LoginConfig::FromCache => {
+                cache.credentials().map(Login::Credentials).ok_or_else(|| {
+                    NativeError::new(
+                        ErrorCode::AuthFailed,
+                        "No credentials cached, login once with a password or stored credentials",
+                    )
+                })
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    expires_in: u64,
+    #[serde(default)]
+    scope: String,
+}
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// Runs the authorization code flow with PKCE. `on_url` receives the URL the
+/// user has to open, the redirect is then received on a loopback listener
+#[tokio::main]
+pub async fn authorize(
+    config: OAuthConfig,
+    proxy: Option<Url>,
+    on_url: impl FnOnce(String),
+) -> Result<Credentials, NativeError> {
+    let listener = TcpListener::bind(("127.0.0.1", config.redirect_port))
+        .await
+        .map_err(|err| {
+            NativeError::from(err)
+                .prefixed("Failed to start the redirect listener")
+                .context("port", config.redirect_port)
+        })?;
+    let redirect_uri = format!(
+        "http://127.0.0.1:{}{}",
+        listener.local_addr()?.port(),
+        REDIRECT_PATH
+    );
+
+    let verifier = random_string(64);
+    let state = random_string(16);
+
+    let url = Url::parse_with_params(
+        &config.auth_url,
+        &[
+            ("client_id", config.client_id.as_str()),
+            ("response_type", "code"),
+            ("redirect_uri", redirect_uri.as_str()),
+            ("code_challenge_method", "S256"),
+            ("code_challenge", code_challenge(&verifier).as_str()),
+            ("scope", config.scopes.join(" ").as_str()),
+            ("state", state.as_str()),
+        ],
+    )
+    .map_err(|err| {
+        NativeError::new(ErrorCode::InvalidConfig, "Invalid authorization URL")
+            .with_cause(err)
+            .context("url", &config.auth_url)
+    })?;
+    on_url(url.to_string());
+
+    let code = tokio::time::timeout(config.timeout, receive_code(&listener, &state))
+        .await
+        .map_err(|_| {
+            NativeError::new(
+                ErrorCode::AuthFailed,
+                "Timed out waiting for the authorization redirect",
+            )
+        })??;
+
+    let token = exchange_code(&config, proxy.as_ref(), &code, &redirect_uri, &verifier).await?;
+    info!("Received access token with scopes {}", token.scope);
+    debug!("Access token expires in {}s", token.expires_in);
+
+    Ok(Credentials {
+        username: config.username,
+        auth_type: AuthenticationType::AUTHENTICATION_SPOTIFY_TOKEN,
+        auth_data: token.access_token.into_bytes(),
+    })
+}
+
+// Waits for the browser to be redirected back with the authorization code,
+// ignoring unrelated requests such as the favicon
+async fn receive_code(listener: &TcpListener, state: &str) -> Result<String, NativeError> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+
+        let mut buf = vec![0; 8192];
+        let len = stream.read(&mut buf).await?;
+        let request = String::from_utf8_lossy(&buf[..len]);
+
+        let url = request
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|path| Url::parse(&format!("http://127.0.0.1{}", path)).ok());
+
+        let url = match url {
+            Some(url) if url.path() == REDIRECT_PATH => url,
+            _ => {
+                respond(&mut stream, "404 Not Found", "Not found").await;
+                continue;
+            }
+        };
+
+        let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        let res = if let Some(error) = params.get("error") {
+            Err(
+                NativeError::new(ErrorCode::AuthFailed, "Authorization was denied")
+                    .context("error", error),
+            )
+        } else if params.get("state").map(String::as_str) != Some(state) {
+            Err(NativeError::new(
+                ErrorCode::AuthFailed,
+                "Authorization redirect has a mismatching state",
+            ))
+        } else {
+            params.get("code").cloned().ok_or_else(|| {
+                NativeError::new(
+                    ErrorCode::AuthFailed,
+                    "Authorization redirect is missing the code",
+                )
+            })
+        };
+
+        match res {
+            Ok(_) => {
+                respond(
+                    &mut stream,
+                    "200 OK",
+                    "Login successful, you can close this window",
+                )
+                .await
+            }
+            Err(_) => {
+                respond(
+                    &mut stream,
+                    "400 Bad Request",
+                    "Login failed, you can close this window",
+                )
+                .await
+            }
+        }
+        return res;
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    // The browser going away doesn't affect the login
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+async fn exchange_code(
+    config: &OAuthConfig,
+    proxy: Option<&Url>,
+    code: &str,
+    redirect_uri: &str,
+    verifier: &str,
+) -> Result<TokenResponse, NativeError> {
+    let body = proxied_http_client(proxy)?
+        .post(&config.token_url)
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("client_id", config.client_id.as_str()),
+            ("code_verifier", verifier),
+        ])
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|err| match err.status() {
+            Some(status) if status.is_client_error() => {
+                NativeError::new(ErrorCode::AuthFailed, "Authorization code was rejected")
+                    .with_cause(err)
+            }
+            _ => NativeError::from(err).prefixed("Failed to exchange the authorization code"),
+        })?
+        .bytes()
+        .await?;
+
+    Ok(serde_json::from_slice(&body)?)
+}
@@ -0,0 +1,151 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Condvar, Mutex,
+};
+
+use librespot::playback::{
+    audio_backend::{Sink, SinkError, SinkResult},
+    config::AudioFormat,
+    convert::Converter,
+    decoder::AudioPacket,
+    NUM_CHANNELS, SAMPLE_RATE,
+};
+use neon::{
+    handle::Root,
+    prelude::{Channel, Context, Object},
+    result::JsResult,
+    types::{buffer::TypedArray, JsBoolean, JsFunction, JsValue},
+};
+
+pub const JS_BACKEND: &str = "js";
+
+/// Delivers decoded audio of a player to a JS callback. The callback may
+/// return `false` to pause delivery until `resume` is called, which blocks
+/// the player thread so playback doesn't run ahead of the consumer
+pub struct PcmOutput {
+    channel: Channel,
+    callback: Root<JsFunction>,
+    paused: Mutex<bool>,
+    resumed: Condvar,
+    closed: AtomicBool,
+}
+
+impl PcmOutput {
+    pub fn new(channel: Channel, callback: Root<JsFunction>) -> Self {
+        Self {
+            channel,
+            callback,
+            paused: Mutex::new(false),
+            resumed: Condvar::new(),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn resume(&self) {
+        *self.paused.lock().unwrap() = false;
+        self.resumed.notify_all();
+    }
+
+    /// Unblocks a paused player so it can be shut down, frames written
+    /// afterwards are dropped
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.resume();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    fn send(self: &Arc<Self>, format: AudioFormat, data: Vec<u8>) -> SinkResult<()> {
+        if self.is_closed() {
+            return Ok(());
+        }
+
+        let output = self.clone();
+        let res = self
+            .channel
+            .send(move |mut cx| {
+                let mut buffer = cx.buffer(data.len())?;
+                buffer.as_mut_slice(&mut cx).copy_from_slice(&data);
+
+                let frame = cx.empty_object();
+                frame.set(&mut cx, "data", buffer)?;
+                let sample_rate = cx.number(SAMPLE_RATE);
+                frame.set(&mut cx, "sample_rate", sample_rate)?;
+                let channels = cx.number(NUM_CHANNELS);
+                frame.set(&mut cx, "channels", channels)?;
+                let format = cx.string(format!("{:?}", format));
+                frame.set(&mut cx, "format", format)?;
+
+                let callback = output.callback.to_inner(&mut cx);
+                let ret: JsResult<JsValue> = callback.call_with(&cx).arg(frame).apply(&mut cx);
+
+                // Anything but an explicit false keeps the audio flowing
+                let wants_more = match ret {
+                    Ok(ret) => ret
+                        .downcast::<JsBoolean, _>(&mut cx)
+                        .map(|ret| ret.value(&mut cx))
+                        .unwrap_or(true),
+                    Err(_) => true,
+                };
+                if !wants_more {
+                    *output.paused.lock().unwrap() = true;
+                }
+                Ok(())
+            })
+            .join();
+
+        if let Err(err) = res {
+            return Err(SinkError::OnWrite(format!(
+                "Failed to deliver audio to JS: {:?}",
+                err
+            )));
+        }
+
+        let mut paused = self.paused.lock().unwrap();
+        while *paused && !self.is_closed() {
+            paused = self.resumed.wait(paused).unwrap();
+        }
+        Ok(())
+    }
+}
+
+pub struct JsSink {
+    output: Arc<PcmOutput>,
+    format: AudioFormat,
+}
+
+impl JsSink {
+    pub fn new(output: Arc<PcmOutput>, format: AudioFormat) -> Self {
+        Self { output, format }
+    }
+}
+
+fn to_bytes<T, const N: usize>(samples: Vec<T>, f: impl Fn(T) -> [u8; N]) -> Vec<u8> {
+    samples.into_iter().flat_map(f).collect()
+}
+
+impl Sink for JsSink {
+    fn write(&mut self, packet: AudioPacket, converter: &mut Converter) -> SinkResult<()> {
+        let samples = packet
+            .samples()
+            .map_err(|err| SinkError::OnWrite(err.to_string()))?;
+
+        // Native endianness, so the data can be viewed as a typed array in JS
+        let data = match self.format {
+            AudioFormat::F64 => samples.iter().flat_map(|s| s.to_ne_bytes()).collect(),
+            AudioFormat::F32 => to_bytes(converter.f64_to_f32(samples), f32::to_ne_bytes),
+            AudioFormat::S32 => to_bytes(converter.f64_to_s32(samples), i32::to_ne_bytes),
+            AudioFormat::S24 => to_bytes(converter.f64_to_s24(samples), i32::to_ne_bytes),
+            AudioFormat::S16 => to_bytes(converter.f64_to_s16(samples), i16::to_ne_bytes),
+            AudioFormat::S24_3 => {
+                return Err(SinkError::InvalidParams(
+                    "The js backend does not support the S24_3 format".to_string(),
+                ))
+            }
+        };
+
+        self.output.send(self.format, data)
+    }
+}
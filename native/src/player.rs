@@ -1,88 +1,360 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
+use futures::future::try_join_all;
 use futures_util::StreamExt;
 
 use librespot;
+use librespot::connect::config::ConnectConfig;
+use librespot::connect::spirc::{Spirc, SpircLoadCommand};
 use librespot::core::cache::Cache;
-use librespot::core::SpotifyId;
+use librespot::core::http_client::HttpClientError;
 use librespot::core::{authentication::Credentials, config::SessionConfig, session::Session};
-use librespot::discovery::DeviceType;
+use librespot::core::{spotify_id::SpotifyItemType, Error, SpotifyId};
+use librespot::metadata::{Album, Artist, Episode, Metadata, Playlist, Show, Track};
 
-use librespot::playback::audio_backend::SinkBuilder;
-use librespot::playback::config::{PlayerConfig, VolumeCtrl};
+use librespot::playback::audio_backend::Sink;
+use librespot::playback::config::PlayerConfig;
+use librespot::playback::mixer;
 use librespot::playback::mixer::{Mixer, MixerConfig};
 use librespot::playback::player::Player;
-use librespot::playback::{audio_backend, mixer};
-use neon::prelude::{Context, Handle};
-use neon::types::JsError;
+use librespot::protocol::spirc::TrackRef;
 use protobuf::Message;
 use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use reqwest::Url;
 use tokio;
 
+use crate::backends::BackendConfig;
+use crate::canvas_cache::CanvasCache;
 use crate::canvaz::entity_canvaz_request::Entity;
+use crate::canvaz::entity_canvaz_response::Canvaz;
 use crate::canvaz::{EntityCanvazRequest, EntityCanvazResponse};
+use crate::capture_sink::{Capture, CaptureSink};
+use crate::error::{ErrorCode, NativeError, ResultExt};
+use crate::lyrics::{parse_lyrics, LyricsResponse};
+use crate::pcm_sink::{JsSink, PcmOutput};
+
+/// Mixer of a player, validated when the player is created
+#[derive(Clone)]
+pub struct MixerSettings {
+    /// softvol, or alsa for hardware volume
+    pub name: String,
+    pub config: MixerConfig,
+}
+
+/// Mixer of the player currently in use, replaced when the player is
+/// re-created after reconnecting
+pub type SharedMixer = Arc<Mutex<Option<Arc<dyn Mixer>>>>;
 
 pub fn new_player(
-    backend_str: String,
+    backend: BackendConfig,
     session: Session,
     player_config: PlayerConfig,
-    volume_ctrl: String,
-) -> (Arc<Player>, Arc<dyn Mixer>) {
-    let backend: SinkBuilder;
-    if backend_str.is_empty() {
-        backend = audio_backend::find(Some("rodio".to_string())).unwrap();
-    } else {
-        backend = audio_backend::find(Some(backend_str)).unwrap();
-    }
+    mixer_settings: MixerSettings,
+    pcm_output: Option<Arc<PcmOutput>>,
+    capture: Option<Arc<Capture>>,
+) -> Result<(Arc<Player>, Arc<dyn Mixer>), NativeError> {
+    // Both were validated when the config was read, they are looked up before
+    // starting the player so a failure rejects instead of panicking its thread
+    let mixer_fn = mixer::find(Some(mixer_settings.name.as_str())).ok_or_else(|| {
+        NativeError::new(ErrorCode::InvalidConfig, "Mixer is not available")
+            .context("mixer", &mixer_settings.name)
+    })?;
+    let mixer = mixer_fn(mixer_settings.config);
 
-    let mut mixer_config = MixerConfig::default();
-    mixer_config.volume_ctrl =
-        VolumeCtrl::from_str_with_range(volume_ctrl.as_str(), VolumeCtrl::DEFAULT_DB_RANGE)
-            .unwrap_or_else(|_| VolumeCtrl::Log(VolumeCtrl::DEFAULT_DB_RANGE));
-
-    let mixer = mixer::find(None).unwrap()(mixer_config);
+    let format = backend.format;
+    let sink_builder: Box<dyn FnOnce() -> Box<dyn Sink> + Send> = if let Some(output) = pcm_output {
+        Box::new(move || Box::new(JsSink::new(output, format)))
+    } else if let Some(capture) = capture.clone() {
+        Box::new(move || Box::new(CaptureSink::new(capture)))
+    } else {
+        let build = backend.sink_builder().ok_or_else(|| {
+            NativeError::new(ErrorCode::InvalidConfig, "Audio backend is not available")
+                .context("backend", &backend.name)
+        })?;
+        let device = backend.device;
+        Box::new(move || build(device, format))
+    };
 
     let p = Player::new(
         player_config,
         session.clone(),
         mixer.get_soft_volume(),
-        move || (backend)(None, librespot::playback::config::AudioFormat::F32),
+        sink_builder,
     );
 
-    return (p, mixer);
+    if let Some(capture) = capture {
+        capture.follow(p.get_player_event_channel());
+    }
+
+    return Ok((p, mixer));
 }
 
-pub fn create_session(cache_config: Cache) -> Session {
-    let session_config = SessionConfig::default();
+pub fn create_session(session_config: SessionConfig, cache_config: Cache) -> Session {
     let session = Session::new(session_config, Some(cache_config));
 
     return session;
 }
 
-#[allow(dead_code)]
+pub async fn connect_session(
+    session_config: SessionConfig,
+    cache_config: Cache,
+    credentials: Credentials,
+) -> Result<Session, Error> {
+    let session = create_session(session_config, cache_config);
+    // Saves the reusable credentials when the cache has a credentials_location
+    session.connect(credentials, true).await?;
+    Ok(session)
+}
+
 #[tokio::main]
-pub async fn start_discovery(client_id: String) -> Credentials {
-    let device_id = "test";
-
-    let mut discovery = librespot::discovery::Discovery::builder(device_id, client_id.as_str())
-        .name("test device")
-        .device_type(DeviceType::Computer)
-        .port(9001)
-        .launch()
+pub async fn start_discovery(
+    device_id: String,
+    client_id: String,
+    connect_config: ConnectConfig,
+    port: u16,
+) -> Result<Credentials, Error> {
+    let mut discovery = librespot::discovery::Discovery::builder(device_id, client_id)
+        .name(connect_config.name)
+        .device_type(connect_config.device_type)
+        .port(port)
+        .launch()?;
+
+    discovery
+        .next()
+        .await
+        .ok_or_else(|| Error::unavailable("Discovery stopped before receiving credentials"))
+}
+
+/// Context last loaded on a spirc, loaded again when spirc is replaced since
+/// the connect state doesn't carry over to the new one
+#[derive(Clone)]
+pub struct LoadedContext {
+    pub context_uri: String,
+    pub tracks: Vec<TrackRef>,
+    pub shuffle: bool,
+    pub repeat: bool,
+}
+
+pub type SharedContext = Arc<Mutex<Option<LoadedContext>>>;
+
+impl LoadedContext {
+    /// Command loading the context at the track, `None` if it isn't part of it
+    pub fn load_command(
+        self,
+        track_id: SpotifyId,
+        start_playing: bool,
+    ) -> Option<SpircLoadCommand> {
+        let gid = track_id.to_raw();
+        let playing_track_index = self
+            .tracks
+            .iter()
+            .position(|track| track.gid() == &gid[..])?;
+
+        Some(SpircLoadCommand {
+            context_uri: self.context_uri,
+            start_playing,
+            shuffle: self.shuffle,
+            repeat: self.repeat,
+            playing_track_index: playing_track_index as u32,
+            tracks: self.tracks,
+        })
+    }
+}
+
+/// Loads the command and remembers its context
+pub fn load_spirc(
+    spirc: &Spirc,
+    context: &SharedContext,
+    command: SpircLoadCommand,
+) -> Result<(), Error> {
+    let loaded = LoadedContext {
+        context_uri: command.context_uri.clone(),
+        tracks: command.tracks.clone(),
+        shuffle: command.shuffle,
+        repeat: command.repeat,
+    };
+
+    spirc.load(command)?;
+    *context.lock().unwrap() = Some(loaded);
+    Ok(())
+}
+
+pub struct ContextLoadOptions {
+    pub tracks: Option<Vec<SpotifyId>>,
+    pub start_index: u32,
+    pub start_track: Option<SpotifyId>,
+    pub position_ms: u32,
+    pub start_playing: bool,
+    pub shuffle: bool,
+    pub repeat: bool,
+}
+
+fn resolve_context_tracks(
+    context_id: SpotifyId,
+    session: Session,
+) -> Result<Vec<SpotifyId>, Error> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .unwrap();
+
+    runtime.block_on(async {
+        match context_id.item_type {
+            SpotifyItemType::Album => {
+                let album = Album::get(&session, &context_id).await?;
+                Ok(album.tracks().cloned().collect())
+            }
+            SpotifyItemType::Playlist => {
+                let playlist = Playlist::get(&session, &context_id).await?;
+                Ok(playlist.tracks().cloned().collect())
+            }
+            SpotifyItemType::Artist => {
+                let artist = Artist::get(&session, &context_id).await?;
+                Ok(artist.top_tracks.for_country(&session.country()).to_vec())
+            }
+            SpotifyItemType::Show => {
+                let show = Show::get(&session, &context_id).await?;
+                Ok(show.episodes.to_vec())
+            }
+            SpotifyItemType::Track | SpotifyItemType::Episode => Ok(vec![context_id]),
+            _ => Err(Error::invalid_argument(format!(
+                "Cannot resolve tracks for context {}",
+                context_id
+            ))),
+        }
+    })
+}
+
+pub enum MetadataItem {
+    Track(Track),
+    Album(Album),
+    // Top tracks are resolved for the country of the session
+    Artist(Artist, Vec<SpotifyId>),
+    Playlist(Playlist),
+    Episode(Episode),
+    Show(Show),
+}
+
+pub fn get_metadata(session: Session, uri: String) -> Result<MetadataItem, Error> {
+    let id = SpotifyId::from_uri(uri.as_str())?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_io()
+        .enable_time()
+        .build()
         .unwrap();
 
-    discovery.next().await.unwrap()
+    runtime.block_on(async {
+        match id.item_type {
+            SpotifyItemType::Track => Ok(MetadataItem::Track(Track::get(&session, &id).await?)),
+            SpotifyItemType::Album => Ok(MetadataItem::Album(Album::get(&session, &id).await?)),
+            SpotifyItemType::Artist => {
+                let artist = Artist::get(&session, &id).await?;
+                let top_tracks = artist.top_tracks.for_country(&session.country()).to_vec();
+                Ok(MetadataItem::Artist(artist, top_tracks))
+            }
+            SpotifyItemType::Playlist => {
+                Ok(MetadataItem::Playlist(Playlist::get(&session, &id).await?))
+            }
+            SpotifyItemType::Episode => {
+                Ok(MetadataItem::Episode(Episode::get(&session, &id).await?))
+            }
+            SpotifyItemType::Show => Ok(MetadataItem::Show(Show::get(&session, &id).await?)),
+            _ => Err(Error::invalid_argument(format!(
+                "Metadata is not available for {}",
+                uri
+            ))),
+        }
+    })
 }
 
-pub fn get_lyrics<'a, C>(
-    cx: &mut C,
+/// Command loading the context, resolving its tracks unless they are given.
+/// Fetches metadata, so it is not run on the command thread
+pub fn context_load_command(
+    session: Session,
+    context_uri: String,
+    options: ContextLoadOptions,
+) -> Result<SpircLoadCommand, Error> {
+    let tracks = match options.tracks {
+        Some(tracks) => tracks,
+        None => resolve_context_tracks(SpotifyId::from_uri(context_uri.as_str())?, session)?,
+    };
+
+    if tracks.is_empty() {
+        return Err(Error::not_found(format!(
+            "Context {} has no tracks",
+            context_uri
+        )));
+    }
+
+    let playing_track_index = match options.start_track {
+        Some(start_track) => tracks
+            .iter()
+            .position(|t| *t == start_track)
+            .ok_or_else(|| {
+                Error::not_found(format!("{} is not part of {}", start_track, context_uri))
+            })? as u32,
+        None => options.start_index,
+    };
+
+    if playing_track_index as usize >= tracks.len() {
+        return Err(Error::out_of_range(format!(
+            "Start index {} is out of range for {} tracks",
+            playing_track_index,
+            tracks.len()
+        )));
+    }
+
+    let mut track_refs = vec![];
+    for track in tracks {
+        let mut track_ref = TrackRef::new();
+        track_ref.set_gid(Vec::from(track.to_raw()));
+        // Episodes can only be told apart from tracks by their uri
+        track_ref.set_uri(track.to_uri()?);
+        track_refs.push(track_ref);
+    }
+
+    Ok(SpircLoadCommand {
+        context_uri,
+        start_playing: options.start_playing,
+        shuffle: options.shuffle,
+        repeat: options.repeat,
+        playing_track_index,
+        tracks: track_refs,
+    })
+}
+
+/// Loads a resolved context, starting `position_ms` into its first track
+pub fn load_context(
+    spirc: &Spirc,
+    context: &SharedContext,
+    command: SpircLoadCommand,
+    position_ms: u32,
+) -> Result<(), Error> {
+    load_spirc(spirc, context, command)?;
+
+    if position_ms > 0 {
+        spirc.set_position_ms(position_ms)?;
+    }
+
+    Ok(())
+}
+
+fn is_not_found(err: &Error) -> bool {
+    matches!(
+        err.error.downcast_ref::<HttpClientError>(),
+        Some(HttpClientError::StatusCode(code)) if code.as_u16() == 404
+    )
+}
+
+/// Fetches and parses the lyrics of a track. Resolves to `None` when Spotify
+/// has no lyrics for it
+pub fn get_lyrics(
     track_uri: String,
     session: Session,
-) -> Result<String, Handle<'a, JsError>>
-where
-    C: Context<'a>,
-{
-    let session_clone = session.clone();
+) -> Result<Option<LyricsResponse>, NativeError> {
     let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_io()
         .enable_time()
@@ -90,30 +362,222 @@ where
         .unwrap();
 
     runtime.block_on(async {
-        let track_id_res = SpotifyId::from_uri(track_uri.as_str())
-            .or_else(|err| Err(cx.error(err.to_string()).unwrap()))?;
+        let track_id = SpotifyId::from_uri(track_uri.as_str()).context("uri", &track_uri)?;
 
-        let resp = session_clone
+        let resp = match session.spclient().get_lyrics(&track_id).await {
+            Ok(resp) => resp,
+            Err(err) if is_not_found(&err) => return Ok(None),
+            Err(err) => return Err(NativeError::from(err).context("uri", track_uri)),
+        };
+
+        let lyrics = parse_lyrics(&resp)
+            .prefixed("Failed to parse lyrics")
+            .context("uri", track_uri)?;
+
+        Ok(Some(lyrics))
+    })
+}
+
+// Requests made outside of librespot go through the proxy of the session too
+fn http_client(session: &Session) -> Result<reqwest::Client, NativeError> {
+    proxied_http_client(session.config().proxy.as_ref())
+}
+
+/// HTTP client going through the proxy from the session config, if any
+pub fn proxied_http_client(proxy: Option<&Url>) -> Result<reqwest::Client, NativeError> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy.as_str()).prefixed("Invalid proxy")?);
+    }
+    builder.build().prefixed("Failed to build request builder")
+}
+
+async fn request_canvases(
+    session: &Session,
+    entity_uris: Vec<String>,
+) -> Result<EntityCanvazResponse, NativeError> {
+    let mut req = EntityCanvazRequest::new();
+    for entity_uri in entity_uris {
+        let mut entity = Entity::new();
+        entity.entity_uri = entity_uri;
+        req.entities.push(entity);
+    }
+
+    let url = format!(
+        "{}/canvaz-cache/v0/canvases",
+        session
             .spclient()
-            .get_lyrics(&track_id_res)
+            .base_url()
             .await
-            .or_else(|err| Err(cx.error(err.to_string()).unwrap()))?;
+            .prefixed("Failed to resolve spclient")?
+    );
+    let token = session
+        .token_provider()
+        .get_token("playlist-read")
+        .await
+        .prefixed("Failed to get access_token")?
+        .access_token;
 
-        let str = String::from_utf8(resp.to_vec())
-            .or_else(|err| Err(cx.error(err.to_string()).unwrap()))?;
-        Ok(str)
-    })
+    let body = req
+        .write_to_bytes()
+        .prefixed("Failed write body to bytes")?;
+
+    let resp = http_client(session)?
+        .post(url)
+        .header(CONTENT_TYPE, "application/x-protobuf")
+        .bearer_auth(token)
+        .header(CONTENT_LENGTH, body.len())
+        .body(body)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .prefixed("Failed to send request")?;
+
+    let bytes = resp.bytes().await.prefixed("Failed to get response body")?;
+
+    EntityCanvazResponse::parse_from_tokio_bytes(&bytes).prefixed("Failed to parse request")
 }
 
-pub fn get_canvas<'a, C>(
-    cx: &mut C,
+pub fn get_canvas(
     track_uri: String,
     session: Session,
-) -> Result<EntityCanvazResponse, Handle<'a, JsError>>
-where
-    C: Context<'a>,
-{
-    let session_clone = session.clone();
+) -> Result<EntityCanvazResponse, NativeError> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .unwrap();
+
+    runtime
+        .block_on(request_canvases(&session, vec![track_uri.clone()]))
+        .context("uri", track_uri)
+}
+
+// Upper bound of entities sent in a single canvaz request
+const CANVAS_BATCH_SIZE: usize = 50;
+
+/// Canvases grouped by the entity they belong to, in request order. Entities
+/// without a canvas are listed in `misses`
+pub struct CanvasBatch {
+    pub canvases: Vec<(String, Vec<Canvaz>)>,
+    pub misses: Vec<String>,
+    pub ttl_in_seconds: i64,
+}
+
+pub fn get_canvases(
+    session: Session,
+    entity_uris: Vec<String>,
+) -> Result<CanvasBatch, NativeError> {
+    let unique_uris = dedup_uris(entity_uris);
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .unwrap();
+
+    let responses = runtime.block_on(try_join_all(
+        unique_uris
+            .chunks(CANVAS_BATCH_SIZE)
+            .map(|chunk| request_canvases(&session, chunk.to_vec())),
+    ))?;
+
+    Ok(merge_canvas_responses(unique_uris, responses))
+}
+
+// Keeps the first occurrence of every uri, in order
+fn dedup_uris(entity_uris: Vec<String>) -> Vec<String> {
+    let mut unique_uris: Vec<String> = Vec::with_capacity(entity_uris.len());
+    for entity_uri in entity_uris {
+        if !unique_uris.contains(&entity_uri) {
+            unique_uris.push(entity_uri);
+        }
+    }
+    unique_uris
+}
+
+// Groups the canvases of the chunked responses by entity, in the order the
+// entities were requested
+fn merge_canvas_responses(
+    unique_uris: Vec<String>,
+    responses: Vec<EntityCanvazResponse>,
+) -> CanvasBatch {
+    let mut ttl_in_seconds: Option<i64> = None;
+    let mut by_entity: HashMap<String, Vec<Canvaz>> = HashMap::new();
+    for resp in responses {
+        // Each chunk carries its own TTL, the batch is only as fresh as the shortest one
+        ttl_in_seconds =
+            Some(ttl_in_seconds.map_or(resp.ttl_in_seconds, |ttl| ttl.min(resp.ttl_in_seconds)));
+
+        for canvas in resp.canvases {
+            by_entity
+                .entry(canvas.entity_uri.clone())
+                .or_default()
+                .push(canvas);
+        }
+    }
+
+    let mut batch = CanvasBatch {
+        canvases: vec![],
+        misses: vec![],
+        ttl_in_seconds: ttl_in_seconds.unwrap_or(0),
+    };
+    for entity_uri in unique_uris {
+        match by_entity.remove(&entity_uri) {
+            Some(canvases) => batch.canvases.push((entity_uri, canvases)),
+            None => batch.misses.push(entity_uri),
+        }
+    }
+
+    batch
+}
+
+// Streams the canvas into memory, giving up as soon as it grows past the
+// size limit of the cache it would be stored in
+async fn download_canvas(
+    session: &Session,
+    url: &str,
+    size_limit: u64,
+) -> Result<Vec<u8>, NativeError> {
+    let too_large = || {
+        NativeError::new(ErrorCode::IoError, "Canvas exceeds the cache size limit")
+            .context("size_limit", size_limit)
+    };
+
+    let mut resp = http_client(session)?
+        .get(url)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .prefixed("Failed to download canvas")?;
+
+    if resp.content_length().map_or(false, |len| len > size_limit) {
+        return Err(too_large());
+    }
+
+    let mut data = vec![];
+    while let Some(chunk) = resp.chunk().await.prefixed("Failed to get canvas body")? {
+        if (data.len() + chunk.len()) as u64 > size_limit {
+            return Err(too_large());
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    Ok(data)
+}
+
+/// Downloads the canvas of an entity into the canvas cache and returns its
+/// local path. Resolves to `None` if the entity has no canvas. A previously
+/// cached file is returned if Spotify can't be reached
+pub fn get_canvas_file(
+    session: Session,
+    cache: &CanvasCache,
+    entity_uri: String,
+) -> Result<Option<PathBuf>, NativeError> {
+    if let Some(path) = cache.get_fresh(&entity_uri) {
+        return Ok(Some(path));
+    }
+
     let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_io()
         .enable_time()
@@ -121,65 +585,101 @@ where
         .unwrap();
 
     runtime.block_on(async {
-        let spclient = session_clone.spclient();
+        let resp = match request_canvases(&session, vec![entity_uri.clone()]).await {
+            Ok(resp) => resp,
+            Err(err) => return cache.get_stale(&entity_uri).map(Some).ok_or(err),
+        };
 
-        let mut req = EntityCanvazRequest::new();
-        let mut entity = Entity::new();
-        entity.entity_uri = track_uri.clone();
-        req.entities.push(entity.clone());
+        let ttl = resp.ttl_in_seconds.max(0) as u64;
+        let canvas = match resp
+            .canvases
+            .into_iter()
+            .find(|canvas| canvas.entity_uri == entity_uri)
+        {
+            Some(canvas) => canvas,
+            None => {
+                cache.remove(&entity_uri);
+                return Ok(None);
+            }
+        };
+
+        if let Some(path) = cache.revalidate(&entity_uri, &canvas.url, &canvas.etag, ttl) {
+            return Ok(Some(path));
+        }
+
+        let data = match download_canvas(&session, &canvas.url, cache.size_limit()).await {
+            Ok(data) => data,
+            Err(err) => return cache.get_stale(&entity_uri).map(Some).ok_or(err),
+        };
 
-        println!("{}", protobuf::text_format::print_to_string(&req));
+        cache
+            .insert(&entity_uri, &canvas.url, &canvas.etag, ttl, &data)
+            .map(Some)
+            .prefixed("Failed to cache canvas")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uris(uris: &[&str]) -> Vec<String> {
+        uris.iter().map(|uri| uri.to_string()).collect()
+    }
+
+    fn response(ttl_in_seconds: i64, canvases: &[(&str, &str)]) -> EntityCanvazResponse {
+        let mut resp = EntityCanvazResponse::new();
+        resp.ttl_in_seconds = ttl_in_seconds;
+        for (entity_uri, url) in canvases {
+            let mut canvas = Canvaz::new();
+            canvas.entity_uri = entity_uri.to_string();
+            canvas.url = url.to_string();
+            resp.canvases.push(canvas);
+        }
+        resp
+    }
+
+    #[test]
+    fn duplicate_uris_are_requested_once() {
+        let unique = dedup_uris(uris(&["a", "b", "a", "c", "b"]));
+        assert_eq!(unique, uris(&["a", "b", "c"]));
+    }
 
-        let url = format!(
-            "{}/canvaz-cache/v0/canvases",
-            spclient.base_url().await.unwrap()
+    #[test]
+    fn chunks_are_merged_in_request_order() {
+        let batch = merge_canvas_responses(
+            uris(&["a", "b", "c", "d"]),
+            vec![
+                response(600, &[("b", "https://c/b.mp4"), ("a", "https://c/a.mp4")]),
+                response(300, &[("d", "https://c/d1.mp4"), ("d", "https://c/d2.mp4")]),
+            ],
         );
-        let token = session
-            .token_provider()
-            .get_token("playlist-read")
-            .await
-            .or_else(|err| {
-                Err(cx
-                    .error(format!("Failed to get access_token {}", err.to_string()))
-                    .unwrap())
-            })?
-            .access_token;
-
-        let body = req.write_to_bytes().or_else(|err| {
-            Err(cx
-                .error(format!("Failed write body to bytes {}", err.to_string()))
-                .unwrap())
-        })?;
 
-        let resp = reqwest::Client::builder()
-            .build()
-            .or_else(|err| Err(cx.error(format!("Failed to build request builder {}", err))))
-            .unwrap()
-            .post(url)
-            .header(CONTENT_TYPE, "application/x-protobuf")
-            .bearer_auth(token)
-            .header(CONTENT_LENGTH, body.len())
-            .body(body)
-            .send()
-            .await
-            .or_else(|err| {
-                Err(cx
-                    .error(format!("Failed to send request {}", err.to_string()))
-                    .unwrap())
-            })?;
-
-        let bytes = resp.bytes().await.or_else(|err| {
-            Err(cx
-                .error(format!("Failed to get response body {}", err.to_string()))
-                .unwrap())
-        })?;
+        let canvases: Vec<(&str, Vec<&str>)> = batch
+            .canvases
+            .iter()
+            .map(|(uri, canvases)| {
+                let urls = canvases.iter().map(|canvas| canvas.url.as_str()).collect();
+                (uri.as_str(), urls)
+            })
+            .collect();
+        assert_eq!(
+            canvases,
+            vec![
+                ("a", vec!["https://c/a.mp4"]),
+                ("b", vec!["https://c/b.mp4"]),
+                ("d", vec!["https://c/d1.mp4", "https://c/d2.mp4"]),
+            ]
+        );
+        assert_eq!(batch.misses, uris(&["c"]));
+        assert_eq!(batch.ttl_in_seconds, 300);
+    }
 
-        let data = EntityCanvazResponse::parse_from_tokio_bytes(&bytes.clone()).or_else(|err| {
-            Err(cx
-                .error(format!("Failed to parse request {}", err.to_string()))
-                .unwrap())
-        })?;
+    #[test]
+    fn batch_without_responses_has_no_ttl() {
+        let batch = merge_canvas_responses(vec![], vec![]);
 
-        Ok(data)
-    })
+        assert!(batch.canvases.is_empty());
+        assert_eq!(batch.ttl_in_seconds, 0);
+    }
 }
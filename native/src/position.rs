@@ -0,0 +1,212 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use librespot::{core::SpotifyId, playback::player::PlayerEvent};
+use neon::prelude::Channel;
+
+use crate::{events::EventListeners, utils::create_js_obj_from_position};
+
+/// Playback position anchored to the last position reported by the player.
+/// While playing, the elapsed time since that report is added on read so the
+/// position never accumulates drift between events
+pub struct PositionTracker {
+    track_id: Option<SpotifyId>,
+    position_ms: u32,
+    anchor: Instant,
+    playing: bool,
+}
+
+pub type SharedPosition = Arc<Mutex<PositionTracker>>;
+
+impl PositionTracker {
+    pub fn new() -> Self {
+        Self {
+            track_id: None,
+            position_ms: 0,
+            anchor: Instant::now(),
+            playing: false,
+        }
+    }
+
+    fn reset(&mut self, track_id: Option<SpotifyId>, position_ms: u32, playing: bool) {
+        self.track_id = track_id;
+        self.position_ms = position_ms;
+        self.anchor = Instant::now();
+        self.playing = playing;
+    }
+
+    pub fn update(&mut self, event: &PlayerEvent) {
+        match *event {
+            PlayerEvent::Playing {
+                track_id,
+                position_ms,
+                ..
+            } => self.reset(Some(track_id), position_ms, true),
+            PlayerEvent::Paused {
+                track_id,
+                position_ms,
+                ..
+            }
+            | PlayerEvent::Loading {
+                track_id,
+                position_ms,
+                ..
+            } => self.reset(Some(track_id), position_ms, false),
+            PlayerEvent::Seeked {
+                track_id,
+                position_ms,
+                ..
+            }
+            | PlayerEvent::PositionCorrection {
+                track_id,
+                position_ms,
+                ..
+            } => self.reset(Some(track_id), position_ms, self.playing),
+            PlayerEvent::Stopped { track_id, .. } | PlayerEvent::EndOfTrack { track_id, .. } => {
+                self.reset(Some(track_id), 0, false)
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies a seek requested by the user right away, without waiting for
+    /// the player to confirm it
+    pub fn seek(&mut self, position_ms: u32) {
+        self.reset(self.track_id, position_ms, self.playing);
+    }
+
+    pub fn position_ms(&self) -> u32 {
+        if self.playing {
+            self.position_ms + self.anchor.elapsed().as_millis() as u32
+        } else {
+            self.position_ms
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn track_id(&self) -> Option<SpotifyId> {
+        self.track_id
+    }
+}
+
+/// Emits PositionChanged events while playing. Must be called from within the
+/// player's runtime, the task ends when the runtime shuts down
+pub fn start_position_updates(
+    interval: Duration,
+    position: SharedPosition,
+    channel: Channel,
+    listeners: EventListeners,
+) {
+    if interval.is_zero() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+
+            let (track_id, position_ms) = {
+                let position = position.lock().unwrap();
+                if !position.is_playing() {
+                    continue;
+                }
+                (position.track_id(), position.position_ms())
+            };
+
+            listeners.emit(&channel, move |cx| {
+                create_js_obj_from_position(cx, track_id, position_ms)
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track() -> SpotifyId {
+        SpotifyId::from_uri("spotify:track:4uLU6hMCjMI75M1A2tKUQC").unwrap()
+    }
+
+    // Pretends the last report was received `elapsed_ms` ago
+    fn age(tracker: &mut PositionTracker, elapsed_ms: u64) {
+        tracker.anchor -= Duration::from_millis(elapsed_ms);
+    }
+
+    #[test]
+    fn position_advances_while_playing() {
+        let mut tracker = PositionTracker::new();
+        tracker.update(&PlayerEvent::Playing {
+            play_request_id: 1,
+            track_id: track(),
+            position_ms: 1000,
+        });
+        age(&mut tracker, 500);
+
+        assert!(tracker.is_playing());
+        assert_eq!(tracker.track_id(), Some(track()));
+        assert!(tracker.position_ms() >= 1500);
+    }
+
+    #[test]
+    fn position_holds_while_paused() {
+        let mut tracker = PositionTracker::new();
+        tracker.update(&PlayerEvent::Paused {
+            play_request_id: 1,
+            track_id: track(),
+            position_ms: 1000,
+        });
+        age(&mut tracker, 500);
+
+        assert!(!tracker.is_playing());
+        assert_eq!(tracker.position_ms(), 1000);
+    }
+
+    #[test]
+    fn seek_keeps_playing_state() {
+        let mut tracker = PositionTracker::new();
+        tracker.update(&PlayerEvent::Paused {
+            play_request_id: 1,
+            track_id: track(),
+            position_ms: 1000,
+        });
+        tracker.seek(30000);
+        assert_eq!(tracker.position_ms(), 30000);
+
+        tracker.update(&PlayerEvent::Playing {
+            play_request_id: 1,
+            track_id: track(),
+            position_ms: 30000,
+        });
+        tracker.update(&PlayerEvent::PositionCorrection {
+            play_request_id: 1,
+            track_id: track(),
+            position_ms: 29000,
+        });
+        assert!(tracker.is_playing());
+        assert!(tracker.position_ms() >= 29000);
+    }
+
+    #[test]
+    fn stopping_resets_the_position() {
+        let mut tracker = PositionTracker::new();
+        tracker.update(&PlayerEvent::Playing {
+            play_request_id: 1,
+            track_id: track(),
+            position_ms: 1000,
+        });
+        tracker.update(&PlayerEvent::EndOfTrack {
+            play_request_id: 1,
+            track_id: track(),
+        });
+
+        assert!(!tracker.is_playing());
+        assert_eq!(tracker.position_ms(), 0);
+    }
+}
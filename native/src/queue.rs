@@ -0,0 +1,442 @@
+use librespot::{core::SpotifyId, playback::player::Player};
+use rand::{seq::SliceRandom, Rng};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RepeatMode {
+    Off,
+    One,
+    All,
+}
+
+impl RepeatMode {
+    pub fn from_str(str: &str) -> Option<Self> {
+        match str.to_lowercase().as_str() {
+            "off" => Some(RepeatMode::Off),
+            "one" => Some(RepeatMode::One),
+            "all" => Some(RepeatMode::All),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RepeatMode::Off => "off",
+            RepeatMode::One => "one",
+            RepeatMode::All => "all",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct QueueState {
+    pub tracks: Vec<SpotifyId>,
+    pub current_index: Option<usize>,
+    pub repeat: RepeatMode,
+    pub shuffle: bool,
+}
+
+pub struct PlayQueue {
+    tracks: Vec<SpotifyId>,
+    // Play order as indices into `tracks`. Identity unless shuffled
+    order: Vec<usize>,
+    // Index into `order` of the track currently loaded in the player. Once
+    // that track is removed, of the track before it
+    position: Option<usize>,
+    // Set while the track loaded in the player is no longer in the queue,
+    // there is no current track until the next one is loaded
+    current_removed: bool,
+    repeat: RepeatMode,
+    shuffle: bool,
+    // Request id of the last track loaded from the queue. Events carrying
+    // any other id belong to a track that was replaced in the meantime
+    play_request_id: Option<u64>,
+    // Loads the player has not reported a request id for yet
+    pending_loads: usize,
+}
+
+impl PlayQueue {
+    pub fn new() -> Self {
+        Self {
+            tracks: vec![],
+            order: vec![],
+            position: None,
+            current_removed: false,
+            repeat: RepeatMode::Off,
+            shuffle: false,
+            play_request_id: None,
+            pending_loads: 0,
+        }
+    }
+
+    pub fn state(&self) -> QueueState {
+        QueueState {
+            tracks: self.tracks.clone(),
+            current_index: self.current_index(),
+            repeat: self.repeat,
+            shuffle: self.shuffle,
+        }
+    }
+
+    fn current_index(&self) -> Option<usize> {
+        if self.current_removed {
+            return None;
+        }
+        self.position_index()
+    }
+
+    fn position_index(&self) -> Option<usize> {
+        self.position.map(|p| self.order[p])
+    }
+
+    pub fn current(&self) -> Option<SpotifyId> {
+        self.current_index().map(|i| self.tracks[i])
+    }
+
+    pub fn enqueue(&mut self, tracks: Vec<SpotifyId>) {
+        let len = self.tracks.len();
+        self.insert(len, tracks);
+    }
+
+    pub fn insert(&mut self, index: usize, tracks: Vec<SpotifyId>) {
+        let index = index.min(self.tracks.len());
+        let count = tracks.len();
+        self.tracks.splice(index..index, tracks);
+
+        self.remap(|i| Some(if i >= index { i + count } else { i }));
+
+        let mut rng = rand::thread_rng();
+        for i in index..index + count {
+            if self.shuffle {
+                let start = self.position.map(|p| p + 1).unwrap_or(0);
+                let pos = rng.gen_range(start..=self.order.len());
+                self.order.insert(pos, i);
+            } else {
+                self.order.push(i);
+            }
+        }
+
+        if !self.shuffle {
+            let current = self.position_index();
+            self.order.sort_unstable();
+            self.position = current;
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<SpotifyId> {
+        if index >= self.tracks.len() {
+            return None;
+        }
+
+        let removed = self.tracks.remove(index);
+        self.remap(|i| match i {
+            i if i == index => None,
+            i if i > index => Some(i - 1),
+            i => Some(i),
+        });
+
+        Some(removed)
+    }
+
+    pub fn move_track(&mut self, from: usize, to: usize) -> bool {
+        if from >= self.tracks.len() || to >= self.tracks.len() {
+            return false;
+        }
+
+        let track = self.tracks.remove(from);
+        self.tracks.insert(to, track);
+
+        self.remap(|i| {
+            Some(if i == from {
+                to
+            } else if from < to && i > from && i <= to {
+                i - 1
+            } else if to < from && i >= to && i < from {
+                i + 1
+            } else {
+                i
+            })
+        });
+
+        if !self.shuffle {
+            let current = self.position_index();
+            self.order.sort_unstable();
+            self.position = current;
+        }
+
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.tracks.clear();
+        self.order.clear();
+        self.position = None;
+        self.current_removed = false;
+        self.play_request_id = None;
+    }
+
+    /// Replaces the queue with a single track, used by plain `load_track` calls
+    pub fn replace(&mut self, track: SpotifyId) {
+        self.clear();
+        self.enqueue(vec![track]);
+        self.position = Some(0);
+    }
+
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.repeat = repeat;
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        if self.shuffle == shuffle {
+            return;
+        }
+        self.shuffle = shuffle;
+
+        let current = self.position_index();
+        let mut order: Vec<usize> = (0..self.tracks.len()).collect();
+        if shuffle {
+            // Keep the playing track first so shuffling never interrupts it
+            order.retain(|i| Some(*i) != current);
+            order.shuffle(&mut rand::thread_rng());
+            if let Some(current) = current {
+                order.insert(0, current);
+            }
+        }
+
+        self.order = order;
+        self.position = current.and_then(|c| self.order.iter().position(|i| *i == c));
+    }
+
+    /// Jumps to a track by its index in the queue
+    pub fn jump(&mut self, index: usize) -> Option<SpotifyId> {
+        let position = self.order.iter().position(|i| *i == index)?;
+        self.position = Some(position);
+        self.current_removed = false;
+        self.current()
+    }
+
+    fn next_position(&self, user_initiated: bool) -> Option<usize> {
+        if self.order.is_empty() {
+            return None;
+        }
+
+        if self.repeat == RepeatMode::One && !user_initiated && !self.current_removed {
+            return self.position;
+        }
+
+        let next = self.position.map(|p| p + 1).unwrap_or(0);
+        if next < self.order.len() {
+            Some(next)
+        } else if self.repeat != RepeatMode::Off {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    /// Track that will play once the current one ends, used for preloading
+    pub fn peek_next(&self) -> Option<SpotifyId> {
+        self.next_position(false)
+            .map(|p| self.tracks[self.order[p]])
+    }
+
+    pub fn next(&mut self, user_initiated: bool) -> Option<SpotifyId> {
+        let next = self.next_position(user_initiated)?;
+        self.position = Some(next);
+        self.current_removed = false;
+        self.current()
+    }
+
+    pub fn previous(&mut self) -> Option<SpotifyId> {
+        if self.current_removed {
+            // The position already is on the track before the removed one
+            self.current_removed = false;
+            if self.position.is_none() && !self.order.is_empty() {
+                self.position = Some(0);
+            }
+            return self.current();
+        }
+
+        let position = self.position?;
+        self.position = Some(match position {
+            0 if self.repeat == RepeatMode::All => self.order.len() - 1,
+            0 => 0,
+            p => p - 1,
+        });
+        self.current()
+    }
+
+    /// Loads the current queue track into the player. Its request id is
+    /// reported by the player afterwards, see `request_started`
+    pub fn load_current(&mut self, player: &Player, start_playing: bool, position_ms: u32) {
+        if let Some(track) = self.current() {
+            player.load(track, start_playing, position_ms);
+            self.play_request_id = None;
+            self.pending_loads += 1;
+        }
+    }
+
+    /// Records the request id of a load from the queue. Only the last of
+    /// several loads in a row becomes the current request
+    pub fn request_started(&mut self, play_request_id: u64) {
+        self.pending_loads = self.pending_loads.saturating_sub(1);
+        if self.pending_loads == 0 {
+            self.play_request_id = Some(play_request_id);
+        }
+    }
+
+    pub fn is_current_request(&self, play_request_id: u64) -> bool {
+        self.play_request_id == Some(play_request_id)
+    }
+
+    fn remap(&mut self, f: impl Fn(usize) -> Option<usize>) {
+        let position = self.position;
+        let current = self.position_index().map(&f);
+
+        self.order = self.order.iter().filter_map(|i| f(*i)).collect();
+        self.position = match current {
+            Some(Some(c)) => self.order.iter().position(|i| *i == c),
+            // The track at the position was removed, step back so the next
+            // call continues with whatever took its place
+            Some(None) => {
+                self.current_removed = true;
+                position.and_then(|p| p.checked_sub(1))
+            }
+            None => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(n: usize) -> SpotifyId {
+        SpotifyId::from_uri(&format!("spotify:track:{:022}", n)).unwrap()
+    }
+
+    fn queue(len: usize) -> PlayQueue {
+        let mut queue = PlayQueue::new();
+        queue.enqueue((0..len).map(track).collect());
+        queue
+    }
+
+    #[test]
+    fn shuffle_keeps_current_and_plays_every_track_once() {
+        let mut queue = queue(5);
+        queue.jump(2);
+        queue.set_shuffle(true);
+
+        assert_eq!(queue.current(), Some(track(2)));
+        assert_eq!(queue.state().current_index, Some(2));
+
+        let mut played: Vec<SpotifyId> = std::iter::from_fn(|| queue.next(false)).collect();
+        played.sort_by_key(|id| id.id);
+        assert_eq!(played, vec![track(0), track(1), track(3), track(4)]);
+    }
+
+    #[test]
+    fn unshuffle_restores_queue_order() {
+        let mut queue = queue(5);
+        queue.jump(2);
+        queue.set_shuffle(true);
+        queue.next(false);
+        let current = queue.current();
+        queue.set_shuffle(false);
+
+        assert_eq!(queue.current(), current);
+        let index = queue.state().current_index.unwrap();
+        assert_eq!(current, Some(track(index)));
+        assert_eq!(queue.next(false), (index + 1 < 5).then(|| track(index + 1)));
+    }
+
+    #[test]
+    fn repeat_one_repeats_unless_skipped() {
+        let mut queue = queue(3);
+        queue.jump(2);
+        queue.set_repeat(RepeatMode::One);
+
+        assert_eq!(queue.peek_next(), Some(track(2)));
+        assert_eq!(queue.next(false), Some(track(2)));
+        // Skipping leaves the track, wrapping around like repeat all
+        assert_eq!(queue.next(true), Some(track(0)));
+    }
+
+    #[test]
+    fn repeat_all_wraps_around() {
+        let mut queue = queue(3);
+        queue.jump(2);
+        queue.set_repeat(RepeatMode::All);
+
+        assert_eq!(queue.next(false), Some(track(0)));
+        assert_eq!(queue.previous(), Some(track(2)));
+    }
+
+    #[test]
+    fn repeat_off_stops_at_the_ends() {
+        let mut queue = queue(3);
+        queue.jump(2);
+
+        assert_eq!(queue.next(false), None);
+        queue.jump(0);
+        assert_eq!(queue.previous(), Some(track(0)));
+    }
+
+    #[test]
+    fn removing_a_preceding_track_keeps_current() {
+        let mut queue = queue(4);
+        queue.jump(2);
+
+        assert_eq!(queue.remove(0), Some(track(0)));
+        assert_eq!(queue.current(), Some(track(2)));
+        assert_eq!(queue.state().current_index, Some(1));
+        assert_eq!(queue.next(false), Some(track(3)));
+    }
+
+    #[test]
+    fn removing_current_continues_with_the_following_track() {
+        let mut queue = queue(4);
+        queue.jump(2);
+
+        assert_eq!(queue.remove(2), Some(track(2)));
+        assert_eq!(queue.next(false), Some(track(3)));
+
+        queue.jump(0);
+        queue.remove(0);
+        assert_eq!(queue.current(), None);
+        assert_eq!(queue.next(false), Some(track(1)));
+    }
+
+    #[test]
+    fn removing_current_leaves_no_current_track() {
+        let mut queue = queue(4);
+        queue.jump(2);
+        queue.set_repeat(RepeatMode::One);
+        queue.remove(2);
+
+        assert_eq!(queue.current(), None);
+        assert_eq!(queue.state().current_index, None);
+        assert_eq!(queue.peek_next(), Some(track(3)));
+        assert_eq!(queue.previous(), Some(track(1)));
+        assert_eq!(queue.state().current_index, Some(1));
+    }
+
+    #[test]
+    fn only_the_last_of_several_loads_is_the_current_request() {
+        let mut queue = queue(2);
+        queue.pending_loads = 2;
+
+        queue.request_started(1);
+        assert!(!queue.is_current_request(1));
+        queue.request_started(2);
+        assert!(queue.is_current_request(2));
+    }
+
+    #[test]
+    fn removing_out_of_range_does_nothing() {
+        let mut queue = queue(2);
+        queue.jump(1);
+
+        assert_eq!(queue.remove(2), None);
+        assert_eq!(queue.current(), Some(track(1)));
+    }
+}
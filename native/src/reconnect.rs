@@ -0,0 +1,186 @@
+use std::{future::Future, time::Duration};
+
+use librespot::core::{Session, SpotifyId};
+use log::{info, warn};
+use neon::prelude::Channel;
+use tokio::sync::oneshot;
+
+use crate::{error::NativeError, events::EventListeners, utils::create_js_obj_from_reconnect};
+
+const DISCONNECT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct ReconnectConfig {
+    pub enabled: bool,
+    /// 0 keeps retrying until the player is closed
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_attempts: 10,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// Exponential backoff, doubling the delay after every failed attempt
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+
+    fn is_exhausted(&self, attempt: u32) -> bool {
+        self.max_attempts != 0 && attempt >= self.max_attempts
+    }
+}
+
+pub enum ReconnectEvent {
+    Reconnecting { attempt: u32, delay: Duration },
+    Reconnected { attempts: u32 },
+    Failed { attempts: u32, error: NativeError },
+}
+
+pub enum ReconnectOutcome<T, S> {
+    Connected(T),
+    Failed,
+    /// The player was closed while reconnecting
+    Shutdown(Option<S>),
+}
+
+/// Playback state captured when the connection was lost, restored once the
+/// player has been re-created
+#[derive(Clone, Copy)]
+pub struct Resume {
+    pub track_id: Option<SpotifyId>,
+    pub position_ms: u32,
+    pub playing: bool,
+}
+
+/// Resolves once the session has been invalidated, e.g. after the connection
+/// to the access point dropped
+pub async fn wait_for_disconnect(session: &Session) {
+    while !session.is_invalid() {
+        tokio::time::sleep(DISCONNECT_POLL_INTERVAL).await;
+    }
+}
+
+fn emit(channel: &Channel, listeners: &EventListeners, event: ReconnectEvent) {
+    listeners.emit(channel, move |cx| create_js_obj_from_reconnect(cx, event));
+}
+
+/// Retries `connect`, which establishes a new session, with exponential
+/// backoff until it succeeds, the attempts run out or the player is closed
+pub async fn reconnect<T, S, E, F, Fut>(
+    config: &ReconnectConfig,
+    shutdown_rx: &mut oneshot::Receiver<S>,
+    channel: &Channel,
+    listeners: &EventListeners,
+    mut connect: F,
+) -> ReconnectOutcome<T, S>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Into<NativeError>,
+{
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let delay = config.delay(attempt);
+        emit(
+            channel,
+            listeners,
+            ReconnectEvent::Reconnecting { attempt, delay },
+        );
+
+        let res = tokio::select! {
+            shutdown = &mut *shutdown_rx => return ReconnectOutcome::Shutdown(shutdown.ok()),
+            res = async {
+                tokio::time::sleep(delay).await;
+                connect().await
+            } => res,
+        };
+
+        match res {
+            Ok(connected) => {
+                info!("Reconnected after {} attempt(s)", attempt);
+                emit(
+                    channel,
+                    listeners,
+                    ReconnectEvent::Reconnected { attempts: attempt },
+                );
+                return ReconnectOutcome::Connected(connected);
+            }
+            Err(err) => {
+                let err: NativeError = err.into();
+                warn!("Reconnect attempt {} failed: {}", attempt, err);
+                if config.is_exhausted(attempt) {
+                    let error = err.prefixed("Failed to reconnect");
+                    emit(
+                        channel,
+                        listeners,
+                        ReconnectEvent::Failed {
+                            attempts: attempt,
+                            error,
+                        },
+                    );
+                    return ReconnectOutcome::Failed;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(max_attempts: u32) -> ReconnectConfig {
+        ReconnectConfig {
+            enabled: true,
+            max_attempts,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn delay_doubles_after_every_attempt() {
+        let config = config(10);
+
+        assert_eq!(config.delay(1), Duration::from_secs(1));
+        assert_eq!(config.delay(2), Duration::from_secs(2));
+        assert_eq!(config.delay(3), Duration::from_secs(4));
+        assert_eq!(config.delay(4), Duration::from_secs(8));
+    }
+
+    #[test]
+    fn delay_is_capped_at_max_delay() {
+        let config = config(10);
+
+        assert_eq!(config.delay(5), Duration::from_secs(10));
+        assert_eq!(config.delay(40), Duration::from_secs(10));
+        assert_eq!(config.delay(u32::MAX), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn attempts_run_out_at_max_attempts() {
+        let config = config(3);
+
+        assert!(!config.is_exhausted(2));
+        assert!(config.is_exhausted(3));
+    }
+
+    #[test]
+    fn zero_max_attempts_retries_forever() {
+        assert!(!config(0).is_exhausted(u32::MAX));
+    }
+}
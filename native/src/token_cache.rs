@@ -0,0 +1,344 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use librespot::core::Session;
+use log::{debug, warn};
+use neon::prelude::Channel;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{ErrorCode, NativeError},
+    events::EventListeners,
+    utils::create_js_event_from_token,
+};
+
+const TOKEN_FILE: &str = "tokens.json";
+const KEYMASTER_CLIENT_ID: &str = "65b708073fc0480ea92a077233ca87bd";
+
+/// Tokens are refreshed, and no longer handed out, once they are this close
+/// to expiring
+const REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+// Upper bound on how long the refresh task sleeps, so tokens added in the
+// meantime are picked up
+const REFRESH_POLL_INTERVAL: Duration = Duration::from_secs(60);
+// Lower bound, so a failing refresh is not retried in a busy loop
+const REFRESH_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CachedToken {
+    pub access_token: String,
+    pub token_type: String,
+    pub scopes: Vec<String>,
+    /// Unix timestamp in milliseconds
+    pub expires_at: u64,
+    username: String,
+}
+
+impl CachedToken {
+    fn covers(&self, scopes: &[String]) -> bool {
+        scopes.iter().all(|scope| self.scopes.contains(scope))
+    }
+
+    pub fn expires_in(&self) -> Duration {
+        Duration::from_millis(self.expires_at.saturating_sub(now_ms()))
+    }
+
+    fn needs_refresh(&self) -> bool {
+        self.expires_in() <= REFRESH_MARGIN
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KeymasterToken {
+    access_token: String,
+    expires_in: u64,
+    token_type: String,
+    scope: Vec<String>,
+}
+
+/// Access tokens of the logged in user, persisted next to the credentials so
+/// they survive restarts
+pub struct TokenCache {
+    path: Option<PathBuf>,
+    tokens: Mutex<Vec<CachedToken>>,
+}
+
+impl TokenCache {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        let path = dir.map(|dir| dir.join(TOKEN_FILE));
+
+        let mut tokens: Vec<CachedToken> = path
+            .as_ref()
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        tokens.retain(|token| token.expires_at > now_ms());
+
+        Self {
+            path,
+            tokens: Mutex::new(tokens),
+        }
+    }
+
+    /// Token of the user holding every requested scope, if it is not about
+    /// to expire
+    pub fn get(&self, username: &str, scopes: &[String]) -> Option<CachedToken> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|token| token.username == username && token.covers(scopes))
+            .filter(|token| !token.needs_refresh())
+            .max_by_key(|token| token.expires_at)
+            .cloned()
+    }
+
+    pub fn insert(&self, token: CachedToken) {
+        let mut tokens = self.tokens.lock().unwrap();
+        let now = now_ms();
+
+        // Tokens with a subset of the scopes are superseded by the new one
+        tokens.retain(|cached| {
+            cached.expires_at > now
+                && !(cached.username == token.username && token.covers(&cached.scopes))
+        });
+        tokens.push(token);
+
+        self.persist(&tokens);
+    }
+
+    // Time until the first token of the user has to be refreshed
+    fn next_refresh(&self, username: &str) -> Option<Duration> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|token| token.username == username)
+            .map(|token| token.expires_in().saturating_sub(REFRESH_MARGIN))
+            .min()
+    }
+
+    // Scopes of the tokens of the user to refresh. Tokens that expired
+    // without being refreshed are dropped instead of retried forever, the
+    // next get_token requests a new one
+    fn due(&self, username: &str) -> Vec<Vec<String>> {
+        let mut tokens = self.tokens.lock().unwrap();
+        let now = now_ms();
+        let count = tokens.len();
+        tokens.retain(|token| token.expires_at > now);
+        if tokens.len() != count {
+            debug!("Dropped {} expired tokens", count - tokens.len());
+            self.persist(&tokens);
+        }
+
+        tokens
+            .iter()
+            .filter(|token| token.username == username && token.needs_refresh())
+            .map(|token| token.scopes.clone())
+            .collect()
+    }
+
+    fn persist(&self, tokens: &[CachedToken]) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+
+        let res = serde_json::to_vec(tokens)
+            .map_err(std::io::Error::from)
+            .and_then(|data| {
+                let tmp = path.with_extension("json.tmp");
+                fs::write(&tmp, data)?;
+                fs::rename(tmp, path)
+            });
+
+        if let Err(err) = res {
+            warn!("Failed to write token cache: {}", err);
+        }
+    }
+}
+
+/// Requests a new token from the keymaster, bypassing the tokens librespot
+/// keeps until they are just about to expire
+pub async fn request_token(
+    session: &Session,
+    scopes: &[String],
+) -> Result<CachedToken, NativeError> {
+    let uri = format!(
+        "hm://keymaster/token/authenticated?scope={}&client_id={}&device_id={}",
+        scopes.join(","),
+        KEYMASTER_CLIENT_ID,
+        session.device_id()
+    );
+
+    let response = session.mercury().get(uri)?.await?;
+    let payload = response
+        .payload
+        .first()
+        .ok_or_else(|| NativeError::new(ErrorCode::ParseError, "Token response is empty"))?;
+    let token: KeymasterToken = serde_json::from_slice(payload)?;
+
+    Ok(CachedToken {
+        access_token: token.access_token,
+        token_type: token.token_type,
+        scopes: token.scope,
+        expires_at: now_ms() + token.expires_in * 1000,
+        username: session.username(),
+    })
+}
+
+/// Returns a cached token covering the scopes, requesting a new one otherwise
+pub async fn get_token(
+    session: &Session,
+    cache: &TokenCache,
+    scopes: &[String],
+) -> Result<CachedToken, NativeError> {
+    if let Some(token) = cache.get(&session.username(), scopes) {
+        return Ok(token);
+    }
+
+    let token = request_token(session, scopes).await?;
+    cache.insert(token.clone());
+    Ok(token)
+}
+
+/// Refreshes cached tokens shortly before they expire and emits
+/// TokenRefreshed events. Must be called from within the player's runtime,
+/// the task ends once the session is invalidated
+pub fn start_token_refresh(
+    cache: Arc<TokenCache>,
+    session: Session,
+    channel: Channel,
+    listeners: EventListeners,
+) {
+    tokio::spawn(async move {
+        loop {
+            let username = session.username();
+            let delay = cache
+                .next_refresh(&username)
+                .unwrap_or(REFRESH_POLL_INTERVAL)
+                .clamp(REFRESH_RETRY_INTERVAL, REFRESH_POLL_INTERVAL);
+            tokio::time::sleep(delay).await;
+
+            if session.is_invalid() {
+                break;
+            }
+
+            for scopes in cache.due(&username) {
+                match request_token(&session, &scopes).await {
+                    Ok(token) => {
+                        debug!("Refreshed token for scopes {}", scopes.join(","));
+                        cache.insert(token.clone());
+                        listeners.emit(&channel, move |cx| create_js_event_from_token(cx, token));
+                    }
+                    Err(err) => warn!("Failed to refresh token: {}", err),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(username: &str, scopes: &[&str], expires_in: Duration) -> CachedToken {
+        CachedToken {
+            access_token: format!("{}:{}", username, scopes.join(",")),
+            token_type: "Bearer".to_string(),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+            expires_at: now_ms() + expires_in.as_millis() as u64,
+            username: username.to_string(),
+        }
+    }
+
+    fn scopes(scopes: &[&str]) -> Vec<String> {
+        scopes.iter().map(|scope| scope.to_string()).collect()
+    }
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    #[test]
+    fn token_is_returned_for_a_subset_of_its_scopes() {
+        let cache = TokenCache::new(None);
+        cache.insert(token("user", &["streaming", "user-read-private"], HOUR));
+
+        let found = cache.get("user", &scopes(&["streaming"]));
+        assert_eq!(
+            found.unwrap().scopes,
+            scopes(&["streaming", "user-read-private"])
+        );
+        assert!(cache.get("user", &scopes(&["user-read-email"])).is_none());
+    }
+
+    #[test]
+    fn tokens_of_other_users_are_not_returned() {
+        let cache = TokenCache::new(None);
+        cache.insert(token("user", &["streaming"], HOUR));
+
+        assert!(cache.get("other", &scopes(&["streaming"])).is_none());
+    }
+
+    #[test]
+    fn tokens_about_to_expire_are_not_returned() {
+        let cache = TokenCache::new(None);
+        cache.insert(token("user", &["streaming"], REFRESH_MARGIN / 2));
+
+        assert!(cache.get("user", &scopes(&["streaming"])).is_none());
+        assert_eq!(cache.due("user"), vec![scopes(&["streaming"])]);
+    }
+
+    #[test]
+    fn expired_tokens_are_dropped_instead_of_refreshed() {
+        let cache = TokenCache::new(None);
+        cache.tokens.lock().unwrap().push(CachedToken {
+            expires_at: now_ms() - 1,
+            ..token("user", &["streaming"], HOUR)
+        });
+
+        assert!(cache.due("user").is_empty());
+        assert!(cache.tokens.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn token_with_more_scopes_supersedes_cached_ones() {
+        let cache = TokenCache::new(None);
+        cache.insert(token("user", &["streaming"], HOUR));
+        cache.insert(token("user", &["user-read-email"], HOUR));
+        cache.insert(token("user", &["streaming", "user-read-private"], HOUR));
+
+        let tokens = cache.tokens.lock().unwrap();
+        let cached: Vec<&Vec<String>> = tokens.iter().map(|token| &token.scopes).collect();
+        assert_eq!(
+            cached,
+            vec![
+                &scopes(&["user-read-email"]),
+                &scopes(&["streaming", "user-read-private"])
+            ]
+        );
+    }
+
+    #[test]
+    fn tokens_are_persisted_and_loaded() {
+        let dir = std::env::temp_dir().join(format!("token-cache-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        TokenCache::new(Some(dir.clone())).insert(token("user", &["streaming"], HOUR));
+        let found = TokenCache::new(Some(dir.clone())).get("user", &scopes(&["streaming"]));
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(found.unwrap().access_token, "user:streaming");
+    }
+}
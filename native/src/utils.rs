@@ -1,66 +1,766 @@
-use std::{path::PathBuf, str::FromStr};
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
 
 use librespot::{
     connect::config::ConnectConfig,
-    core::{cache::Cache, spotify_id::SpotifyId, token::Token},
+    core::{cache::Cache, config::SessionConfig, spotify_id::SpotifyId},
     discovery::{Credentials, DeviceType},
+    metadata::{
+        artist::Artists,
+        audio::{AudioItem, UniqueFields},
+        image::Images,
+        restriction::Restrictions,
+        Album,
+    },
     playback::{
-        config::{Bitrate, NormalisationMethod, NormalisationType, PlayerConfig},
-        dither::{mk_ditherer, TriangularDitherer},
+        config::{
+            AudioFormat, Bitrate, NormalisationMethod, NormalisationType, PlayerConfig, VolumeCtrl,
+        },
+        dither::find_ditherer,
+        mixer::{self, MixerConfig},
         player::PlayerEvent,
+        NUM_CHANNELS, SAMPLE_RATE,
     },
     protocol::authentication::AuthenticationType,
 };
 use neon::{
     prelude::{Context, FunctionContext, Handle, Object},
     result::Throw,
-    types::{JsArray, JsBoolean, JsNumber, JsObject, JsString, JsValue, Value},
+    types::{JsArray, JsFunction, JsObject, JsString, JsValue, Value},
 };
+use reqwest::Url;
+
+use crate::{
+    backends::{BackendConfig, BackendInfo},
+    canvas_cache::{CanvasCache, DEFAULT_CANVAS_SIZE_LIMIT},
+    canvaz::{entity_canvaz_response::Canvaz, EntityCanvazResponse},
+    capture_sink::CapturedSegment,
+    config_reader::{join_path, ConfigReader, IssueKind},
+    error::{ErrorCode, NativeError, ResultExt},
+    lyrics::LyricsResponse,
+    oauth::{
+        Login, LoginConfig, OAuthConfig, DEFAULT_AUTH_URL, DEFAULT_REDIRECT_PORT, DEFAULT_TOKEN_URL,
+    },
+    pcm_sink::{PcmOutput, JS_BACKEND},
+    player::{CanvasBatch, ContextLoadOptions, MetadataItem, MixerSettings},
+    queue::QueueState,
+    reconnect::{ReconnectConfig, ReconnectEvent},
+    token_cache::{CachedToken, TokenCache},
+};
+
+const DEFAULT_POSITION_INTERVAL_MS: u64 = 500;
+
+fn create_js_obj_from_canvaz<'a, C>(cx: C, c: &Canvaz) -> (Handle<'a, JsObject>, C)
+where
+    C: Context<'a>,
+{
+    let mut artist = StructToObj::new(cx);
+    artist.add_string("uri", c.artist.uri.clone());
+    artist.add_string("name", c.artist.name.clone());
+    artist.add_string("avatar", c.artist.avatar.clone());
+
+    let artist_obj = artist.finalize();
 
-use crate::canvaz::EntityCanvazResponse;
+    let mut inner = StructToObj::new(artist.context);
+    inner.add_string("id", c.id.clone());
+    inner.add_string("url", c.url.clone());
+    inner.add_string("file_id", c.file_id.clone());
+    inner.add_string("entity_uri", c.entity_uri.clone());
+    inner.add_bool("explicit", c.explicit);
+    inner.add_string("uploaded_by", c.uploaded_by.clone());
+    inner.add_string("etag", c.etag.clone());
+    inner.add_string("canvas_uri", c.canvas_uri.clone());
+    inner.add_string("storylines_id", c.storylines_id.clone());
+    inner.add_number("type_", c.type_.value() as f64);
+
+    inner.add_obj("artist", artist_obj);
+
+    let inner_obj = inner.finalize();
+    (inner_obj, inner.context)
+}
+
+fn create_js_arr_from_canvases<'a, C>(mut cx: C, canvases: &[Canvaz]) -> (Handle<'a, JsArray>, C)
+where
+    C: Context<'a>,
+{
+    let canvas_arr = cx.empty_array();
+
+    for (i, c) in canvases.iter().enumerate() {
+        let (inner_obj, next_cx) = create_js_obj_from_canvaz(cx, c);
+        cx = next_cx;
+
+        canvas_arr.set(&mut cx, i as u32, inner_obj).unwrap();
+    }
+
+    (canvas_arr, cx)
+}
 
 pub fn create_js_obj_from_canvas<'a, C>(
-    mut cx: C,
+    cx: C,
     data: EntityCanvazResponse,
 ) -> (Handle<'a, JsObject>, C)
 where
     C: Context<'a>,
 {
-    let canvas_arr = cx.empty_array();
+    let (canvas_arr, cx) = create_js_arr_from_canvases(cx, &data.canvases);
+
+    let mut obj = StructToObj::new(cx);
+    obj.add_array("canvases", canvas_arr);
+    obj.add_number("ttl_in_seconds", data.ttl_in_seconds as f64);
+
+    let js_obj = obj.finalize();
+    (js_obj, obj.context)
+}
+
+pub fn create_js_obj_from_canvas_batch<'a, C>(
+    mut cx: C,
+    data: CanvasBatch,
+) -> (Handle<'a, JsObject>, C)
+where
+    C: Context<'a>,
+{
+    let canvases_obj = cx.empty_object();
+    for (entity_uri, canvases) in data.canvases.iter() {
+        let (canvas_arr, next_cx) = create_js_arr_from_canvases(cx, canvases);
+        cx = next_cx;
+
+        canvases_obj
+            .set(&mut cx, entity_uri.as_str(), canvas_arr)
+            .unwrap();
+    }
+
+    let misses_arr = cx.empty_array();
+    for (i, entity_uri) in data.misses.iter().enumerate() {
+        let uri = cx.string(entity_uri);
+        misses_arr.set(&mut cx, i as u32, uri).unwrap();
+    }
+
+    let mut obj = StructToObj::new(cx);
+    obj.add_obj("canvases", canvases_obj)
+        .add_array("misses", misses_arr)
+        .add_number("ttl_in_seconds", data.ttl_in_seconds as f64);
+
+    let js_obj = obj.finalize();
+    (js_obj, obj.context)
+}
+
+pub fn create_js_obj_from_lyrics<'a, C>(
+    mut cx: C,
+    data: LyricsResponse,
+) -> (Handle<'a, JsObject>, C)
+where
+    C: Context<'a>,
+{
+    let lyrics = data.lyrics;
+    let lines_arr = cx.empty_array();
 
-    for (i, c) in data.canvases.iter().enumerate() {
-        let mut artist = StructToObj::new(cx);
-        artist.add_string("uri", c.artist.uri.clone());
-        artist.add_string("name", c.artist.name.clone());
-        artist.add_string("avatar", c.artist.avatar.clone());
+    for (i, line) in lyrics.lines.into_iter().enumerate() {
+        let syllables_arr = cx.empty_array();
+        for (j, syllable) in line.syllables.iter().enumerate() {
+            let mut inner = StructToObj::new(cx);
+            inner
+                .add_u64("start_time_ms", syllable.start_time_ms)
+                .add_u64("num_chars", syllable.num_chars);
 
-        let artist_obj = artist.finalize();
+            let inner_obj = inner.finalize();
+            cx = inner.context;
 
-        cx = artist.context;
+            syllables_arr.set(&mut cx, j as u32, inner_obj).unwrap();
+        }
 
         let mut inner = StructToObj::new(cx);
-        inner.add_string("id", c.id.clone());
-        inner.add_string("url", c.url.clone());
-        inner.add_string("file_id", c.file_id.clone());
-        inner.add_string("entity_uri", c.entity_uri.clone());
-        inner.add_bool("explicit", c.explicit);
-        inner.add_string("uploaded_by", c.uploaded_by.clone());
-        inner.add_string("etag", c.etag.clone());
-        inner.add_string("canvas_uri", c.canvas_uri.clone());
-        inner.add_string("storylines_id", c.storylines_id.clone());
-        inner.add_number("type_", c.type_.value() as f64);
-
-        inner.add_obj("artist", artist_obj);
+        inner
+            .add_u64("start_time_ms", line.start_time_ms)
+            .add_u64("end_time_ms", line.end_time_ms)
+            .add_string("words", line.words)
+            .add_array("syllables", syllables_arr);
 
         let inner_obj = inner.finalize();
         cx = inner.context;
 
-        canvas_arr.set(&mut cx, i as u32, inner_obj).unwrap();
+        lines_arr.set(&mut cx, i as u32, inner_obj).unwrap();
     }
 
+    let colors_obj = match data.colors {
+        Some(colors) => {
+            let mut colors_obj = StructToObj::new(cx);
+            colors_obj
+                .add_number("background", colors.background as f64)
+                .add_number("text", colors.text as f64)
+                .add_number("highlight_text", colors.highlight_text as f64);
+
+            let js_obj = colors_obj.finalize();
+            cx = colors_obj.context;
+            Some(js_obj)
+        }
+        None => None,
+    };
+
     let mut obj = StructToObj::new(cx);
-    obj.add_array("canvases", canvas_arr);
-    obj.add_number("ttl_in_seconds", data.ttl_in_seconds as f64);
+    obj.add_string("sync_type", lyrics.sync_type.as_str().to_string())
+        .add_array("lines", lines_arr)
+        .add_string("language", lyrics.language)
+        .add_bool("is_rtl_language", lyrics.is_rtl_language)
+        .add_string("provider", lyrics.provider)
+        .add_string("provider_lyrics_id", lyrics.provider_lyrics_id)
+        .add_string("provider_display_name", lyrics.provider_display_name)
+        .add_bool("has_vocal_removal", data.has_vocal_removal);
+
+    if let Some(colors_obj) = colors_obj {
+        obj.add_obj("colors", colors_obj);
+    }
+
+    let js_obj = obj.finalize();
+    (js_obj, obj.context)
+}
+
+const IMAGE_URL_PREFIX: &str = "https://i.scdn.co/image/";
+
+fn create_js_arr<'a, C, T>(
+    mut cx: C,
+    items: impl IntoIterator<Item = T>,
+    f: impl Fn(C, T) -> (Handle<'a, JsValue>, C),
+) -> (Handle<'a, JsArray>, C)
+where
+    C: Context<'a>,
+{
+    let arr = cx.empty_array();
+    for (i, item) in items.into_iter().enumerate() {
+        let (value, next_cx) = f(cx, item);
+        cx = next_cx;
+
+        arr.set(&mut cx, i as u32, value).unwrap();
+    }
+    (arr, cx)
+}
+
+fn create_js_arr_from_strings<'a, C>(
+    cx: C,
+    items: impl IntoIterator<Item = String>,
+) -> (Handle<'a, JsArray>, C)
+where
+    C: Context<'a>,
+{
+    create_js_arr(cx, items, |mut cx, item| {
+        let value = cx.string(item).upcast();
+        (value, cx)
+    })
+}
+
+fn create_js_arr_from_ids<'a, 'b, C>(
+    cx: C,
+    ids: impl IntoIterator<Item = &'b SpotifyId>,
+) -> (Handle<'a, JsArray>, C)
+where
+    C: Context<'a>,
+{
+    create_js_arr_from_strings(cx, ids.into_iter().map(|id| id.to_string()))
+}
+
+fn create_js_arr_from_images<'a, C>(cx: C, images: &Images) -> (Handle<'a, JsArray>, C)
+where
+    C: Context<'a>,
+{
+    create_js_arr(cx, images.iter(), |cx, image| {
+        let mut obj = StructToObj::new(cx);
+        obj.add_string(
+            "url",
+            format!("{}{}", IMAGE_URL_PREFIX, image.id.to_base16()),
+        )
+        .add_number("width", image.width as f64)
+        .add_number("height", image.height as f64)
+        .add_string("size", format!("{:?}", image.size).to_lowercase());
+
+        let js_obj = obj.finalize().upcast();
+        (js_obj, obj.context)
+    })
+}
+
+fn create_js_arr_from_artists<'a, C>(cx: C, artists: &Artists) -> (Handle<'a, JsArray>, C)
+where
+    C: Context<'a>,
+{
+    create_js_arr(cx, artists.iter(), |cx, artist| {
+        let mut obj = StructToObj::new(cx);
+        obj.add_spotify_id("uri", artist.id)
+            .add_string("name", artist.name.clone());
+
+        let js_obj = obj.finalize().upcast();
+        (js_obj, obj.context)
+    })
+}
+
+fn create_js_arr_from_restrictions<'a, C>(
+    cx: C,
+    restrictions: &Restrictions,
+) -> (Handle<'a, JsArray>, C)
+where
+    C: Context<'a>,
+{
+    create_js_arr(cx, restrictions.iter(), |cx, restriction| {
+        let (catalogues, cx) =
+            create_js_arr_from_strings(cx, restriction.catalogue_strs.iter().cloned());
+        let (allowed, cx) = match &restriction.countries_allowed {
+            Some(countries) => {
+                let (arr, cx) = create_js_arr_from_strings(cx, countries.iter().cloned());
+                (Some(arr), cx)
+            }
+            None => (None, cx),
+        };
+        let (forbidden, cx) = match &restriction.countries_forbidden {
+            Some(countries) => {
+                let (arr, cx) = create_js_arr_from_strings(cx, countries.iter().cloned());
+                (Some(arr), cx)
+            }
+            None => (None, cx),
+        };
+
+        let mut obj = StructToObj::new(cx);
+        obj.add_array("catalogues", catalogues);
+        if let Some(allowed) = allowed {
+            obj.add_array("countries_allowed", allowed);
+        }
+        if let Some(forbidden) = forbidden {
+            obj.add_array("countries_forbidden", forbidden);
+        }
+
+        let js_obj = obj.finalize().upcast();
+        (js_obj, obj.context)
+    })
+}
+
+fn create_js_obj_from_album<'a, C>(cx: C, album: &Album) -> (Handle<'a, JsObject>, C)
+where
+    C: Context<'a>,
+{
+    let (artists, cx) = create_js_arr_from_artists(cx, &album.artists);
+    let (covers, cx) = create_js_arr_from_images(cx, &album.covers);
+    let (genres, cx) = create_js_arr_from_strings(cx, album.genres.iter().cloned());
+    let (tracks, cx) = create_js_arr_from_ids(cx, album.tracks());
+    let (restrictions, cx) = create_js_arr_from_restrictions(cx, &album.restrictions);
+
+    let mut obj = StructToObj::new(cx);
+    obj.add_string("type", "album".to_string())
+        .add_spotify_id("uri", album.id)
+        .add_string("name", album.name.clone())
+        .add_array("artists", artists)
+        .add_string(
+            "album_type",
+            format!("{:?}", album.album_type).to_lowercase(),
+        )
+        .add_string("label", album.label.clone())
+        .add_number("release_date", album.date.as_timestamp_ms() as f64)
+        .add_number("popularity", album.popularity as f64)
+        .add_array("genres", genres)
+        .add_array("covers", covers)
+        .add_array("tracks", tracks)
+        .add_array("restrictions", restrictions);
+
+    let js_obj = obj.finalize();
+    (js_obj, obj.context)
+}
+
+pub fn create_js_obj_from_metadata<'a, C>(cx: C, item: MetadataItem) -> (Handle<'a, JsObject>, C)
+where
+    C: Context<'a>,
+{
+    match item {
+        MetadataItem::Track(track) => {
+            let (artists, cx) = create_js_arr_from_artists(cx, &track.artists);
+            let (album, cx) = create_js_obj_from_album(cx, &track.album);
+            let (covers, cx) = create_js_arr_from_images(cx, &track.album.covers);
+            let (restrictions, cx) = create_js_arr_from_restrictions(cx, &track.restrictions);
+            let (alternatives, cx) = create_js_arr_from_ids(cx, track.alternatives.iter());
+
+            let mut obj = StructToObj::new(cx);
+            obj.add_string("type", "track".to_string())
+                .add_spotify_id("uri", track.id)
+                .add_string("name", track.name)
+                .add_number("duration_ms", track.duration as f64)
+                .add_array("artists", artists)
+                .add_obj("album", album)
+                .add_array("covers", covers)
+                .add_number("track_number", track.number as f64)
+                .add_number("disc_number", track.disc_number as f64)
+                .add_bool("explicit", track.is_explicit)
+                .add_number("popularity", track.popularity as f64)
+                .add_bool("has_lyrics", track.has_lyrics)
+                .add_array("restrictions", restrictions)
+                .add_array("alternatives", alternatives);
+
+            let js_obj = obj.finalize();
+            (js_obj, obj.context)
+        }
+        MetadataItem::Album(album) => create_js_obj_from_album(cx, &album),
+        MetadataItem::Artist(artist, top_tracks) => {
+            let (genres, cx) = create_js_arr_from_strings(cx, artist.genre.iter().cloned());
+            let (portraits, cx) = create_js_arr_from_images(cx, &artist.portraits);
+            let (top_tracks, cx) = create_js_arr_from_ids(cx, top_tracks.iter());
+            let (albums, cx) = create_js_arr_from_ids(cx, artist.albums_current());
+            let (singles, cx) = create_js_arr_from_ids(cx, artist.singles_current());
+            let (related, cx) = create_js_arr_from_artists(cx, &artist.related);
+            let (restrictions, cx) = create_js_arr_from_restrictions(cx, &artist.restrictions);
+
+            let mut obj = StructToObj::new(cx);
+            obj.add_string("type", "artist".to_string())
+                .add_spotify_id("uri", artist.id)
+                .add_string("name", artist.name)
+                .add_number("popularity", artist.popularity as f64)
+                .add_array("genres", genres)
+                .add_array("portraits", portraits)
+                .add_array("top_tracks", top_tracks)
+                .add_array("albums", albums)
+                .add_array("singles", singles)
+                .add_array("related", related)
+                .add_array("restrictions", restrictions);
+
+            let js_obj = obj.finalize();
+            (js_obj, obj.context)
+        }
+        MetadataItem::Playlist(playlist) => {
+            let (tracks, cx) = create_js_arr_from_ids(cx, playlist.tracks());
+
+            let mut obj = StructToObj::new(cx);
+            obj.add_string("type", "playlist".to_string())
+                .add_spotify_id("uri", playlist.id)
+                .add_string("name", playlist.name().to_string())
+                .add_string("description", playlist.attributes.description.clone())
+                .add_bool("collaborative", playlist.attributes.is_collaborative)
+                .add_number("length", playlist.length as f64)
+                .add_array("tracks", tracks);
+
+            let js_obj = obj.finalize();
+            (js_obj, obj.context)
+        }
+        MetadataItem::Episode(episode) => {
+            let (covers, cx) = create_js_arr_from_images(cx, &episode.covers);
+            let (restrictions, cx) = create_js_arr_from_restrictions(cx, &episode.restrictions);
+
+            let mut obj = StructToObj::new(cx);
+            obj.add_string("type", "episode".to_string())
+                .add_spotify_id("uri", episode.id)
+                .add_string("name", episode.name)
+                .add_string("show_name", episode.show_name)
+                .add_string("description", episode.description)
+                .add_number("duration_ms", episode.duration as f64)
+                .add_number(
+                    "publish_time",
+                    episode.publish_time.as_timestamp_ms() as f64,
+                )
+                .add_string("language", episode.language)
+                .add_bool("explicit", episode.is_explicit)
+                .add_array("covers", covers)
+                .add_array("restrictions", restrictions);
+
+            let js_obj = obj.finalize();
+            (js_obj, obj.context)
+        }
+        MetadataItem::Show(show) => {
+            let (covers, cx) = create_js_arr_from_images(cx, &show.covers);
+            let (episodes, cx) = create_js_arr_from_ids(cx, show.episodes.iter());
+            let (restrictions, cx) = create_js_arr_from_restrictions(cx, &show.restrictions);
+
+            let mut obj = StructToObj::new(cx);
+            obj.add_string("type", "show".to_string())
+                .add_spotify_id("uri", show.id)
+                .add_string("name", show.name)
+                .add_string("description", show.description)
+                .add_string("publisher", show.publisher)
+                .add_string("language", show.language)
+                .add_bool("explicit", show.is_explicit)
+                .add_array("covers", covers)
+                .add_array("episodes", episodes)
+                .add_array("restrictions", restrictions);
+
+            let js_obj = obj.finalize();
+            (js_obj, obj.context)
+        }
+    }
+}
+
+pub fn create_js_obj_from_queue<'a, C>(mut cx: C, state: QueueState) -> (Handle<'a, JsObject>, C)
+where
+    C: Context<'a>,
+{
+    let tracks_arr = cx.empty_array();
+    for (i, track) in state.tracks.iter().enumerate() {
+        let uri = cx.string(track.to_string());
+        tracks_arr.set(&mut cx, i as u32, uri).unwrap();
+    }
+
+    let mut obj = StructToObj::new(cx);
+    obj.add_array("tracks", tracks_arr)
+        .add_string("repeat", state.repeat.as_str().to_string())
+        .add_bool("shuffle", state.shuffle);
+
+    if let Some(current_index) = state.current_index {
+        obj.add_u64("current_index", current_index as u64);
+    }
+
+    let js_obj = obj.finalize();
+    (js_obj, obj.context)
+}
+
+pub fn create_js_event_from_queue<'a, C>(cx: C, state: QueueState) -> (Handle<'a, JsObject>, C)
+where
+    C: Context<'a>,
+{
+    let (obj, mut cx) = create_js_obj_from_queue(cx, state);
+    let event = cx.string("QueueChanged");
+    obj.set(&mut cx, "event", event)
+        .expect("Failed to write field name event to obj");
+    (obj, cx)
+}
+
+pub fn create_js_obj_from_position<'a, C>(
+    cx: C,
+    track_id: Option<SpotifyId>,
+    position_ms: u32,
+) -> (Handle<'a, JsObject>, C)
+where
+    C: Context<'a>,
+{
+    let mut obj = StructToObj::new(cx);
+    obj.add_event("PositionChanged")
+        .add_u64("position_ms", position_ms as u64);
+
+    if let Some(track_id) = track_id {
+        obj.add_spotify_id("track_id", track_id);
+    }
+
+    let js_obj = obj.finalize();
+    (js_obj, obj.context)
+}
+
+pub fn create_js_obj_from_config_changed<'a, C>(
+    cx: C,
+    player_config: PlayerConfig,
+    backend: BackendConfig,
+) -> (Handle<'a, JsObject>, C)
+where
+    C: Context<'a>,
+{
+    let bitrate = match player_config.bitrate {
+        Bitrate::Bitrate96 => "96",
+        Bitrate::Bitrate160 => "160",
+        Bitrate::Bitrate320 => "320",
+    };
+
+    let mut obj = StructToObj::new(cx);
+    obj.add_event("ConfigChanged")
+        .add_string("bitrate", bitrate.to_string())
+        .add_bool("gapless", player_config.gapless)
+        .add_bool("passThrough", player_config.passthrough)
+        .add_bool("normalization", player_config.normalisation)
+        .add_string("backend", backend.name)
+        .add_string("format", format!("{:?}", backend.format));
+
+    if let Some(device) = backend.device {
+        obj.add_string("device", device);
+    }
+
+    let js_obj = obj.finalize();
+    (js_obj, obj.context)
+}
+
+pub fn create_js_obj_from_capture<'a, C>(
+    cx: C,
+    segments: Vec<CapturedSegment>,
+) -> (Handle<'a, JsObject>, C)
+where
+    C: Context<'a>,
+{
+    let (segments, cx) = create_js_arr(cx, segments, |cx, segment| {
+        let mut obj = StructToObj::new(cx);
+        obj.add_u64("start_position_ms", segment.start_position_ms as u64)
+            .add_u64("samples", segment.samples)
+            .add_u64("duration_ms", segment.duration_ms())
+            .add_number("peak", segment.peak)
+            .add_u64("starts", segment.starts as u64)
+            .add_u64("stops", segment.stops as u64);
+
+        if let Some(track_id) = segment.track_id {
+            obj.add_spotify_id("track_id", track_id);
+        }
+        if let Some(first_write_ms) = segment.first_write_ms {
+            obj.add_u64("first_write_ms", first_write_ms);
+        }
+        if let Some(last_write_ms) = segment.last_write_ms {
+            obj.add_u64("last_write_ms", last_write_ms);
+        }
+
+        let js_obj = obj.finalize().upcast();
+        (js_obj, obj.context)
+    });
+
+    let mut obj = StructToObj::new(cx);
+    obj.add_u64("sample_rate", SAMPLE_RATE as u64)
+        .add_u64("channels", NUM_CHANNELS as u64)
+        .add_array("segments", segments);
+
+    let js_obj = obj.finalize();
+    (js_obj, obj.context)
+}
+
+pub fn create_js_arr_from_backends<'a, C>(
+    cx: C,
+    backends: Vec<BackendInfo>,
+) -> (Handle<'a, JsArray>, C)
+where
+    C: Context<'a>,
+{
+    create_js_arr(cx, backends, |cx, backend| {
+        let (devices, cx) = create_js_arr(cx, backend.devices, |cx, device| {
+            let mut obj = StructToObj::new(cx);
+            obj.add_string("name", device.name)
+                .add_bool("is_default", device.is_default);
+
+            let js_obj = obj.finalize().upcast();
+            (js_obj, obj.context)
+        });
+
+        let mut obj = StructToObj::new(cx);
+        obj.add_string("name", backend.name)
+            .add_bool("is_default", backend.is_default)
+            .add_array("devices", devices);
+
+        let js_obj = obj.finalize().upcast();
+        (js_obj, obj.context)
+    })
+}
+
+pub fn create_js_obj_from_reconnect<'a, C>(
+    cx: C,
+    event: ReconnectEvent,
+) -> (Handle<'a, JsObject>, C)
+where
+    C: Context<'a>,
+{
+    let mut obj = StructToObj::new(cx);
+    let error = match event {
+        ReconnectEvent::Reconnecting { attempt, delay } => {
+            obj.add_event("Reconnecting")
+                .add_u64("attempt", attempt as u64)
+                .add_u64("delay_ms", delay.as_millis() as u64);
+            None
+        }
+        ReconnectEvent::Reconnected { attempts } => {
+            obj.add_event("Reconnected")
+                .add_u64("attempts", attempts as u64);
+            None
+        }
+        ReconnectEvent::Failed { attempts, error } => {
+            obj.add_event("ReconnectFailed")
+                .add_u64("attempts", attempts as u64);
+            Some(error)
+        }
+    };
+
+    let js_obj = obj.finalize();
+    let mut cx = obj.context;
+    if let Some(error) = error {
+        let error = error
+            .to_js(&mut cx)
+            .expect("Failed to create reconnect error");
+        js_obj
+            .set(&mut cx, "error", error)
+            .expect("Failed to write field name error to obj");
+    }
+    (js_obj, cx)
+}
+
+fn create_js_obj_from_audio_item<'a, C>(cx: C, item: AudioItem) -> (Handle<'a, JsObject>, C)
+where
+    C: Context<'a>,
+{
+    let (covers, cx) = create_js_arr(cx, item.covers.iter(), |cx, cover| {
+        let mut obj = StructToObj::new(cx);
+        obj.add_string("url", cover.url.clone())
+            .add_number("width", cover.width as f64)
+            .add_number("height", cover.height as f64)
+            .add_string("size", format!("{:?}", cover.size).to_lowercase());
+
+        let js_obj = obj.finalize().upcast();
+        (js_obj, obj.context)
+    });
+    let (language, cx) = create_js_arr_from_strings(cx, item.language);
+    let (alternatives, cx) = match &item.alternatives {
+        Some(alternatives) => {
+            let (arr, cx) = create_js_arr_from_ids(cx, alternatives.iter());
+            (Some(arr), cx)
+        }
+        None => (None, cx),
+    };
+
+    let (unique_fields, cx) = match item.unique_fields {
+        UniqueFields::Track {
+            artists,
+            album,
+            album_artists,
+            popularity,
+            number,
+            disc_number,
+        } => {
+            let (artists, cx) = create_js_arr(cx, artists.iter(), |cx, artist| {
+                let mut obj = StructToObj::new(cx);
+                obj.add_spotify_id("uri", artist.id)
+                    .add_string("name", artist.name.clone())
+                    .add_string("role", format!("{:?}", artist.role).to_lowercase());
+
+                let js_obj = obj.finalize().upcast();
+                (js_obj, obj.context)
+            });
+            let (album_artists, cx) = create_js_arr_from_strings(cx, album_artists);
+
+            let mut obj = StructToObj::new(cx);
+            obj.add_string("type", "track".to_string())
+                .add_array("artists", artists)
+                .add_string("album", album)
+                .add_array("album_artists", album_artists)
+                .add_number("popularity", popularity as f64)
+                .add_number("number", number as f64)
+                .add_number("disc_number", disc_number as f64);
+
+            let js_obj = obj.finalize();
+            (js_obj, obj.context)
+        }
+        UniqueFields::Episode {
+            description,
+            publish_time,
+            show_name,
+        } => {
+            let mut obj = StructToObj::new(cx);
+            obj.add_string("type", "episode".to_string())
+                .add_string("description", description)
+                .add_number("publish_time", publish_time.as_timestamp_ms() as f64)
+                .add_string("show_name", show_name);
+
+            let js_obj = obj.finalize();
+            (js_obj, obj.context)
+        }
+    };
+
+    let mut obj = StructToObj::new(cx);
+    obj.add_spotify_id("track_id", item.track_id)
+        .add_string("uri", item.uri)
+        .add_string("name", item.name)
+        .add_u64("duration_ms", item.duration_ms as u64)
+        .add_array("covers", covers)
+        .add_array("language", language)
+        .add_bool("explicit", item.is_explicit)
+        .add_obj("unique_fields", unique_fields);
+
+    match item.availability {
+        Ok(()) => obj.add_bool("available", true),
+        Err(reason) => obj
+            .add_bool("available", false)
+            .add_string("unavailable_reason", format!("{:?}", reason)),
+    };
+
+    if let Some(alternatives) = alternatives {
+        obj.add_array("alternatives", alternatives);
+    }
 
     let js_obj = obj.finalize();
     (js_obj, obj.context)
@@ -70,6 +770,18 @@ pub fn create_js_obj_from_event<'a, C>(cx: C, event: PlayerEvent) -> (Handle<'a,
 where
     C: Context<'a>,
 {
+    // The nested audio item has to be built before the event object takes the context
+    if let PlayerEvent::TrackChanged { audio_item } = event {
+        let (audio_item_obj, cx) = create_js_obj_from_audio_item(cx, *audio_item);
+
+        let mut obj = StructToObj::new(cx);
+        obj.add_event("TrackChanged")
+            .add_obj("audio_item", audio_item_obj);
+
+        let js_obj = obj.finalize();
+        return (js_obj, obj.context);
+    }
+
     let mut obj = StructToObj::new(cx);
     match event {
         PlayerEvent::Stopped {
@@ -152,9 +864,7 @@ where
             .add_u64("play_request_id", play_request_id)
             .add_spotify_id("track_id", track_id)
             .add_u64("position_ms", position_ms as u64),
-        PlayerEvent::TrackChanged { audio_item } => obj
-            .add_event("TrackChanged")
-            .add_string("audio_item", audio_item.track_id.to_string()),
+        PlayerEvent::TrackChanged { .. } => unreachable!("TrackChanged is converted above"),
 
         PlayerEvent::SessionConnected {
             connection_id,
@@ -292,15 +1002,19 @@ impl<'a, C: Context<'a>> StructToObj<'a, C> {
     }
 }
 
-pub fn token_to_obj<'a, C>(cx: C, token: Token) -> (Handle<'a, JsObject>, C)
+pub fn token_to_obj<'a, C>(cx: C, token: CachedToken) -> (Handle<'a, JsObject>, C)
 where
     C: Context<'a>,
 {
+    let expires_in = token.expires_in();
+    let (scopes, cx) = create_js_arr_from_strings(cx, token.scopes);
+
     let mut obj = StructToObj::new(cx);
     obj.add_string("access_token", token.access_token)
         .add_string("token_type", token.token_type)
-        .add_u128("expires_in", token.expires_in.as_millis())
-        .add_string("scopes", token.scopes.join(","));
+        .add_u128("expires_in", expires_in.as_millis())
+        .add_u64("expiry_from_epoch", token.expires_at)
+        .add_array("scopes", scopes);
 
     let js_obj = obj.finalize();
     let ctx = obj.context;
@@ -308,10 +1022,70 @@ where
     return (js_obj, ctx);
 }
 
-fn get_auth_type(auth_type: String) -> AuthenticationType {
-    match auth_type.as_str() {
-        "AUTHENTICATION_USER_PASS" => AuthenticationType::AUTHENTICATION_USER_PASS,
-        "AUTHENTICATION_STORED_SPOTIFY_CREDENTIALS" => AuthenticationType::AUTHENTICATION_USER_PASS,
+pub fn create_js_event_from_token<'a, C>(cx: C, token: CachedToken) -> (Handle<'a, JsObject>, C)
+where
+    C: Context<'a>,
+{
+    let (token, cx) = token_to_obj(cx, token);
+
+    let mut obj = StructToObj::new(cx);
+    obj.add_event("TokenRefreshed").add_obj("token", token);
+
+    let js_obj = obj.finalize();
+    (js_obj, obj.context)
+}
+
+pub fn create_js_obj_from_credentials<'a, C>(
+    cx: C,
+    event: &str,
+    credentials: Credentials,
+) -> (Handle<'a, JsObject>, C)
+where
+    C: Context<'a>,
+{
+    let mut obj = StructToObj::new(cx);
+    obj.add_event(event)
+        .add_string("username", credentials.username)
+        .add_string("auth_type", format!("{:?}", credentials.auth_type))
+        .add_string("auth_data", STANDARD.encode(credentials.auth_data));
+
+    let js_obj = obj.finalize();
+    return (js_obj, obj.context);
+}
+
+pub fn create_js_obj_from_authorization_url<'a, C>(cx: C, url: String) -> (Handle<'a, JsObject>, C)
+where
+    C: Context<'a>,
+{
+    let mut obj = StructToObj::new(cx);
+    obj.add_event("AuthorizationUrl").add_string("url", url);
+
+    let js_obj = obj.finalize();
+    (js_obj, obj.context)
+}
+
+pub fn create_js_obj_from_config_warning<'a, C>(
+    cx: C,
+    unknown_keys: Vec<String>,
+) -> (Handle<'a, JsObject>, C)
+where
+    C: Context<'a>,
+{
+    let (unknown_keys, cx) = create_js_arr_from_strings(cx, unknown_keys);
+
+    let mut obj = StructToObj::new(cx);
+    obj.add_event("ConfigWarning")
+        .add_array("unknown_keys", unknown_keys);
+
+    let js_obj = obj.finalize();
+    (js_obj, obj.context)
+}
+
+fn get_auth_type(auth_type: &str) -> AuthenticationType {
+    match auth_type {
+        "AUTHENTICATION_STORED_SPOTIFY_CREDENTIALS" => {
+            AuthenticationType::AUTHENTICATION_STORED_SPOTIFY_CREDENTIALS
+        }
         "AUTHENTICATION_STORED_FACEBOOK_CREDENTIALS" => {
             AuthenticationType::AUTHENTICATION_STORED_FACEBOOK_CREDENTIALS
         }
@@ -321,138 +1095,838 @@ fn get_auth_type(auth_type: String) -> AuthenticationType {
     }
 }
 
-pub fn get_credentials_from_obj(
+/// Credentials to log in with. Stored credentials are passed as a base64
+/// blob in `authData`, `fromCache` uses the ones saved under credentials_location
+fn get_credentials_from_obj(
     cx: &mut FunctionContext,
-    obj: Handle<JsObject>,
-) -> Result<Credentials, Throw> {
-    let auth_config = obj.get::<JsObject, _, _>(cx, "auth")?;
-    Ok(Credentials {
-        username: auth_config.get::<JsString, _, _>(cx, "username")?.value(cx),
-        auth_type: get_auth_type(auth_config.get::<JsString, _, _>(cx, "authType")?.value(cx)),
-        auth_data: auth_config
-            .get::<JsString, _, _>(cx, "password")?
-            .value(cx)
+    auth_config: Handle<JsObject>,
+    reader: &mut ConfigReader,
+) -> Result<LoginConfig, Throw> {
+    let path = AUTH_CONFIG;
+    if reader.bool(cx, auth_config, path, "fromCache", false)? {
+        return Ok(LoginConfig::FromCache);
+    }
+
+    let username = reader
+        .required_string(cx, auth_config, path, "username")?
+        .unwrap_or_default();
+    let auth_type = reader.choice(
+        cx,
+        auth_config,
+        path,
+        "authType",
+        AUTH_TYPES[0].to_string(),
+        AUTH_TYPES,
+    )?;
+    let auth_type = get_auth_type(&auth_type);
+
+    let auth_data = match auth_type {
+        AuthenticationType::AUTHENTICATION_STORED_SPOTIFY_CREDENTIALS
+        | AuthenticationType::AUTHENTICATION_STORED_FACEBOOK_CREDENTIALS => {
+            let auth_data = reader
+                .required_string(cx, auth_config, path, "authData")?
+                .unwrap_or_default();
+            STANDARD.decode(auth_data).unwrap_or_else(|_| {
+                reader.issue(
+                    join_path(path, "authData"),
+                    IssueKind::UnknownValue,
+                    "must be base64 encoded stored credentials",
+                );
+                vec![]
+            })
+        }
+        _ => reader
+            .required_string(cx, auth_config, path, "password")?
+            .unwrap_or_default()
             .into_bytes(),
+    };
+
+    Ok(LoginConfig::Login(Login::Credentials(Credentials {
+        username,
+        auth_type,
+        auth_data,
+    })))
+}
+
+/// OAuth login when `auth.oauth` is set, otherwise the credentials in `auth`
+pub fn get_login_from_obj(
+    cx: &mut FunctionContext,
+    obj: Handle<JsObject>,
+    reader: &mut ConfigReader,
+) -> Result<LoginConfig, Throw> {
+    let auth_config = match reader.required_object(cx, obj, "", AUTH_CONFIG)? {
+        Some(auth_config) => auth_config,
+        // Reported as missing, the reader throws before the login is used
+        None => {
+            return Ok(LoginConfig::Login(Login::Credentials(
+                Credentials::with_password("", ""),
+            )))
+        }
+    };
+    reader.warn_unknown_keys(cx, auth_config, AUTH_CONFIG, AUTH_KEYS)?;
+
+    let oauth = match reader.object(cx, auth_config, AUTH_CONFIG, "oauth")? {
+        Some(oauth) => oauth,
+        None => return get_credentials_from_obj(cx, auth_config, reader),
+    };
+    let path = &join_path(AUTH_CONFIG, "oauth");
+    reader.warn_unknown_keys(cx, oauth, path, OAUTH_KEYS)?;
+
+    let client_id = reader.string(
+        cx,
+        oauth,
+        path,
+        "client_id",
+        &SessionConfig::default().client_id,
+    )?;
+    let auth_url = reader.string(cx, oauth, path, "auth_url", DEFAULT_AUTH_URL)?;
+    let token_url = reader.string(cx, oauth, path, "token_url", DEFAULT_TOKEN_URL)?;
+    let username = reader.string(cx, auth_config, AUTH_CONFIG, "username", "")?;
+
+    let redirect_port = reader.number(
+        cx,
+        oauth,
+        path,
+        "redirect_port",
+        DEFAULT_REDIRECT_PORT as f64,
+        0.0..=u16::MAX as f64,
+    )? as u16;
+
+    let scopes = match reader.array(cx, oauth, path, "scopes")? {
+        Some(arr) => get_strings_from_arr(cx, arr)?,
+        None => vec!["streaming".to_string()],
+    };
+
+    let timeout = reader.number(
+        cx,
+        oauth,
+        path,
+        "timeout",
+        DEFAULT_OAUTH_TIMEOUT_MS,
+        0.0..=f64::MAX,
+    )?;
+
+    Ok(LoginConfig::Login(Login::OAuth(OAuthConfig {
+        client_id,
+        auth_url,
+        token_url,
+        redirect_port,
+        scopes,
+        timeout: Duration::from_millis(timeout as u64),
+        username,
+    })))
+}
+
+pub fn get_spotify_ids_from_arr(
+    cx: &mut FunctionContext,
+    arr: Handle<JsArray>,
+) -> Result<Vec<SpotifyId>, Throw> {
+    let mut ids = vec![];
+    for uri in arr.to_vec(cx)? {
+        let uri = uri.downcast_or_throw::<JsString, _>(cx)?.value(cx);
+        ids.push(
+            SpotifyId::from_uri(uri.as_str())
+                .context("uri", &uri)
+                .or_throw(cx)?,
+        );
+    }
+    Ok(ids)
+}
+
+pub fn get_strings_from_arr(
+    cx: &mut FunctionContext,
+    arr: Handle<JsArray>,
+) -> Result<Vec<String>, Throw> {
+    let mut strings = vec![];
+    for value in arr.to_vec(cx)? {
+        strings.push(value.downcast_or_throw::<JsString, _>(cx)?.value(cx));
+    }
+    Ok(strings)
+}
+
+pub fn get_position_interval_from_obj(
+    cx: &mut FunctionContext,
+    obj: Handle<JsObject>,
+    reader: &mut ConfigReader,
+) -> Result<Duration, Throw> {
+    let interval = reader.number(
+        cx,
+        obj,
+        "",
+        "pos_update_interval",
+        DEFAULT_POSITION_INTERVAL_MS as f64,
+        0.0..=f64::MAX,
+    )?;
+    Ok(Duration::from_millis(interval as u64))
+}
+
+pub fn get_reconnect_config_from_obj(
+    cx: &mut FunctionContext,
+    obj: Handle<JsObject>,
+    reader: &mut ConfigReader,
+) -> Result<ReconnectConfig, Throw> {
+    let defaults = ReconnectConfig::default();
+    let path = RECONNECT_CONFIG;
+    let reconnect = match reader.object(cx, obj, "", path)? {
+        Some(reconnect) => reconnect,
+        None => return Ok(defaults),
+    };
+    reader.warn_unknown_keys(cx, reconnect, path, RECONNECT_KEYS)?;
+
+    let max_attempts = reader.number(
+        cx,
+        reconnect,
+        path,
+        "max_attempts",
+        defaults.max_attempts as f64,
+        0.0..=u32::MAX as f64,
+    )?;
+    let initial_delay = reader.number(
+        cx,
+        reconnect,
+        path,
+        "initial_delay",
+        defaults.initial_delay.as_millis() as f64,
+        0.0..=f64::MAX,
+    )?;
+    let max_delay = reader.number(
+        cx,
+        reconnect,
+        path,
+        "max_delay",
+        defaults.max_delay.as_millis() as f64,
+        0.0..=f64::MAX,
+    )?;
+
+    Ok(ReconnectConfig {
+        enabled: reader.bool(cx, reconnect, path, "enabled", defaults.enabled)?,
+        max_attempts: max_attempts as u32,
+        initial_delay: Duration::from_millis(initial_delay as u64),
+        max_delay: Duration::from_millis(max_delay as u64),
+    })
+}
+
+const DEFAULT_MIXER: &str = "softvol";
+const ALSA_MIXER: &str = "alsa";
+const DEFAULT_DITHERER: &str = "tpdf";
+const MAX_VOLUME_RANGE_DB: f64 = 100.0;
+const DEFAULT_DEVICE_NAME: &str = "librespot";
+const DEFAULT_INITIAL_VOLUME: f64 = 32768.0;
+const DEFAULT_OAUTH_TIMEOUT_MS: f64 = 300_000.0;
+
+const NORMALIZATION_CONFIG: &str = "normalizationConfig";
+const CONNECT_CONFIG: &str = "connectConfig";
+const CACHE_CONFIG: &str = "cache";
+const SESSION_CONFIG: &str = "session";
+const AUTH_CONFIG: &str = "auth";
+const MIXER_CONFIG: &str = "mixer";
+const RECONNECT_CONFIG: &str = "reconnect";
+
+const BITRATES: &[&str] = &["96", "160", "320"];
+const DITHERERS: &[&str] = &["none", "gpdf", "tpdf", "tpdf_hp"];
+const NORMALIZATION_TYPES: &[&str] = &["auto", "album", "track"];
+const NORMALIZATION_METHODS: &[&str] = &["basic", "dynamic"];
+const FORMATS: &[&str] = &["F64", "F32", "S32", "S24", "S24_3", "S16"];
+const VOLUME_CTRLS: &[&str] = &["cubic", "fixed", "linear", "log"];
+// The first one is the default
+const AUTH_TYPES: &[&str] = &[
+    "AUTHENTICATION_USER_PASS",
+    "AUTHENTICATION_STORED_SPOTIFY_CREDENTIALS",
+    "AUTHENTICATION_STORED_FACEBOOK_CREDENTIALS",
+    "AUTHENTICATION_SPOTIFY_TOKEN",
+    "AUTHENTICATION_FACEBOOK_TOKEN",
+];
+const DEVICE_TYPES: &[&str] = &[
+    "computer",
+    "tablet",
+    "smartphone",
+    "speaker",
+    "tv",
+    "avr",
+    "stb",
+    "audiodongle",
+    "gameconsole",
+    "castaudio",
+    "castvideo",
+    "automobile",
+    "smartwatch",
+    "chromebook",
+    "carthing",
+    "homething",
+];
+
+// Keys the native module reads, anything else in the config is reported
+const CONFIG_KEYS: &[&str] = &[
+    "auth",
+    "cache",
+    "pos_update_interval",
+    "reconnect",
+    "backend",
+    "device",
+    "format",
+    "ditherer",
+    "pcm_callback",
+    "gapless",
+    "bitrate",
+    "passThrough",
+    "normalizationConfig",
+    "connectConfig",
+    "session",
+    "volumeCtrl",
+    "mixer",
+    "logLevel",
+];
+const NORMALIZATION_KEYS: &[&str] = &[
+    "normalization",
+    "normalizationType",
+    "normalizationMethod",
+    "normalizationPregain",
+    "normalizationThreshold",
+    "normalizationAttackCF",
+    "normalizationReleaseCF",
+    "normalizationKneeDB",
+];
+const CONNECT_KEYS: &[&str] = &[
+    "name",
+    "deviceType",
+    "initialVolume",
+    "hasVolumeControl",
+    "discoveryPort",
+];
+const SESSION_KEYS: &[&str] = &[
+    "client_id",
+    "device_id",
+    "proxy",
+    "ap_port",
+    "autoplay",
+    "tmp_dir",
+];
+const CACHE_KEYS: &[&str] = &[
+    "credentials_location",
+    "volume_location",
+    "audio_location",
+    "size_limiter",
+    "canvas_location",
+    "canvas_size_limit",
+];
+const AUTH_KEYS: &[&str] = &[
+    "username",
+    "password",
+    "authType",
+    "authData",
+    "fromCache",
+    "oauth",
+];
+const OAUTH_KEYS: &[&str] = &[
+    "client_id",
+    "redirect_port",
+    "scopes",
+    "auth_url",
+    "token_url",
+    "timeout",
+];
+const MIXER_KEYS: &[&str] = &["name", "device", "control", "index", "volume_range"];
+const RECONNECT_KEYS: &[&str] = &["enabled", "max_attempts", "initial_delay", "max_delay"];
+const CONTEXT_OPTION_KEYS: &[&str] = &[
+    "tracks",
+    "startIndex",
+    "startTrack",
+    "positionMs",
+    "autoPlay",
+    "shuffle",
+    "repeat",
+];
+
+/// Reader for the config passed to the native module, warning about the
+/// top-level keys it doesn't know
+pub fn get_config_reader(
+    cx: &mut FunctionContext,
+    obj: Handle<JsObject>,
+) -> Result<ConfigReader, Throw> {
+    let mut reader = ConfigReader::new();
+    reader.warn_unknown_keys(cx, obj, "", CONFIG_KEYS)?;
+    Ok(reader)
+}
+
+/// Mixer selected by `mixer`, with the volume curve of `volumeCtrl`
+pub fn get_mixer_from_obj(
+    cx: &mut FunctionContext,
+    obj: Handle<JsObject>,
+    reader: &mut ConfigReader,
+) -> Result<MixerSettings, Throw> {
+    let defaults = MixerConfig::default();
+    let path = MIXER_CONFIG;
+    let mixer = match reader.object(cx, obj, "", path)? {
+        Some(mixer) => mixer,
+        None => cx.empty_object(),
+    };
+    reader.warn_unknown_keys(cx, mixer, path, MIXER_KEYS)?;
+
+    let name = reader.string(cx, mixer, path, "name", DEFAULT_MIXER)?;
+    if mixer::find(Some(name.as_str())).is_none() {
+        let message = if name == ALSA_MIXER {
+            "requires building with the alsa-backend feature".to_string()
+        } else {
+            format!("must be an available mixer, got \"{}\"", name)
+        };
+        reader.issue(join_path(path, "name"), IssueKind::UnknownValue, message);
+    }
+
+    let index = reader.number(
+        cx,
+        mixer,
+        path,
+        "index",
+        defaults.index as f64,
+        0.0..=u32::MAX as f64,
+    )?;
+    if index.fract() != 0.0 {
+        reader.issue(
+            join_path(path, "index"),
+            IssueKind::WrongType,
+            "must be an integer",
+        );
+    }
+
+    let volume_range = reader.number(
+        cx,
+        mixer,
+        path,
+        "volume_range",
+        VolumeCtrl::DEFAULT_DB_RANGE,
+        0.0..=MAX_VOLUME_RANGE_DB,
+    )?;
+    if volume_range == 0.0 {
+        reader.issue(
+            join_path(path, "volume_range"),
+            IssueKind::OutOfRange,
+            format!("must be above 0 and at most {}", MAX_VOLUME_RANGE_DB),
+        );
+    }
+
+    let volume_ctrl = reader.choice(cx, obj, "", "volumeCtrl", "log".to_string(), VOLUME_CTRLS)?;
+    let volume_ctrl = VolumeCtrl::from_str_with_range(&volume_ctrl, volume_range)
+        .unwrap_or(VolumeCtrl::Log(volume_range));
+
+    Ok(MixerSettings {
+        name,
+        config: MixerConfig {
+            device: reader.string(cx, mixer, path, "device", &defaults.device)?,
+            control: reader.string(cx, mixer, path, "control", &defaults.control)?,
+            index: index as u32,
+            volume_ctrl,
+        },
     })
 }
 
-pub fn get_volume_ctrl_from_obj(
+/// Port of the zeroconf server, 0 lets the OS pick a free one
+pub fn get_discovery_port_from_obj(
     cx: &mut FunctionContext,
     obj: Handle<JsObject>,
-) -> Result<String, Throw> {
-    Ok(obj.get::<JsString, _, _>(cx, "volumeCtrl")?.value(cx))
+    reader: &mut ConfigReader,
+) -> Result<u16, Throw> {
+    let path = CONNECT_CONFIG;
+    let connect_config = match reader.object(cx, obj, "", path)? {
+        Some(connect_config) => connect_config,
+        None => return Ok(0),
+    };
+
+    Ok(reader.number(
+        cx,
+        connect_config,
+        path,
+        "discoveryPort",
+        0.0,
+        0.0..=u16::MAX as f64,
+    )? as u16)
 }
 
+/// Playback settings, each left out falls back to its default
 pub fn get_player_config_from_obj(
     cx: &mut FunctionContext,
     obj: Handle<JsObject>,
+    reader: &mut ConfigReader,
 ) -> Result<PlayerConfig, Throw> {
-    let normalization_config = obj.get::<JsObject, _, _>(cx, "normalizationConfig")?;
+    let bitrate = reader.choice(cx, obj, "", "bitrate", Bitrate::Bitrate320, BITRATES)?;
+    let gapless = reader.bool(cx, obj, "", "gapless", false)?;
+    let passthrough = reader.bool(cx, obj, "", "passThrough", false)?;
+
+    // Only applies when converting to integer formats
+    let ditherer = reader.choice(
+        cx,
+        obj,
+        "",
+        "ditherer",
+        DEFAULT_DITHERER.to_string(),
+        DITHERERS,
+    )?;
+    let ditherer = match ditherer.as_str() {
+        "none" => None,
+        name => find_ditherer(Some(name.to_string())),
+    };
+
+    let path = NORMALIZATION_CONFIG;
+    let normalization = match reader.object(cx, obj, "", path)? {
+        Some(normalization) => normalization,
+        None => cx.empty_object(),
+    };
+    reader.warn_unknown_keys(cx, normalization, path, NORMALIZATION_KEYS)?;
 
     Ok(PlayerConfig {
-        bitrate: Bitrate::from_str(obj.get::<JsString, _, _>(cx, "bitrate")?.value(cx).as_str())
-            .unwrap_or_default(),
-        gapless: obj.get::<JsBoolean, _, _>(cx, "gapless")?.value(cx),
-        passthrough: obj.get::<JsBoolean, _, _>(cx, "passThrough")?.value(cx),
-        normalisation: normalization_config
-            .get::<JsBoolean, _, _>(cx, "normalization")?
-            .value(cx),
-        normalisation_type: NormalisationType::from_str(
-            normalization_config
-                .get::<JsString, _, _>(cx, "normalizationType")?
-                .value(cx)
-                .as_str(),
-        )
-        .unwrap_or_default(),
-        normalisation_method: NormalisationMethod::from_str(
-            normalization_config
-                .get::<JsString, _, _>(cx, "normalizationMethod")?
-                .value(cx)
-                .as_str(),
-        )
-        .unwrap_or_default(),
-        normalisation_pregain_db: normalization_config
-            .get::<JsNumber, _, _>(cx, "normalizationPregain")?
-            .value(cx),
-        normalisation_threshold_dbfs: normalization_config
-            .get::<JsNumber, _, _>(cx, "normalizationThreshold")?
-            .value(cx),
-        normalisation_attack_cf: normalization_config
-            .get::<JsNumber, _, _>(cx, "normalizationAttackCF")?
-            .value(cx),
-        normalisation_release_cf: normalization_config
-            .get::<JsNumber, _, _>(cx, "normalizationReleaseCF")?
-            .value(cx),
-        normalisation_knee_db: normalization_config
-            .get::<JsNumber, _, _>(cx, "normalizationKneeDB")?
-            .value(cx),
-        ditherer: Some(mk_ditherer::<TriangularDitherer>),
+        bitrate,
+        gapless,
+        passthrough,
+        normalisation: reader.bool(cx, normalization, path, "normalization", false)?,
+        normalisation_type: reader.choice(
+            cx,
+            normalization,
+            path,
+            "normalizationType",
+            NormalisationType::Auto,
+            NORMALIZATION_TYPES,
+        )?,
+        normalisation_method: reader.choice(
+            cx,
+            normalization,
+            path,
+            "normalizationMethod",
+            NormalisationMethod::Basic,
+            NORMALIZATION_METHODS,
+        )?,
+        normalisation_pregain_db: reader.number(
+            cx,
+            normalization,
+            path,
+            "normalizationPregain",
+            0.0,
+            f64::MIN..=f64::MAX,
+        )?,
+        normalisation_threshold_dbfs: reader.number(
+            cx,
+            normalization,
+            path,
+            "normalizationThreshold",
+            0.0,
+            f64::MIN..=0.0,
+        )?,
+        normalisation_attack_cf: reader.number(
+            cx,
+            normalization,
+            path,
+            "normalizationAttackCF",
+            0.0,
+            0.0..=1.0,
+        )?,
+        normalisation_release_cf: reader.number(
+            cx,
+            normalization,
+            path,
+            "normalizationReleaseCF",
+            0.0,
+            0.0..=1.0,
+        )?,
+        normalisation_knee_db: reader.number(
+            cx,
+            normalization,
+            path,
+            "normalizationKneeDB",
+            0.0,
+            0.0..=f64::MAX,
+        )?,
+        ditherer,
     })
 }
 
+/// How the device shows up in Spotify Connect, each setting left out falls
+/// back to its default
 pub fn get_connect_config_from_obj(
     cx: &mut FunctionContext,
     obj: Handle<JsObject>,
+    reader: &mut ConfigReader,
 ) -> Result<ConnectConfig, Throw> {
-    let connect_config = obj.get::<JsObject, _, _>(cx, "connectConfig")?;
+    let path = CONNECT_CONFIG;
+    let connect_config = match reader.object(cx, obj, "", path)? {
+        Some(connect_config) => connect_config,
+        None => cx.empty_object(),
+    };
+    reader.warn_unknown_keys(cx, connect_config, path, CONNECT_KEYS)?;
+
     Ok(ConnectConfig {
-        name: connect_config.get::<JsString, _, _>(cx, "name")?.value(cx),
-        device_type: DeviceType::from_str(
-            connect_config
-                .get::<JsString, _, _>(cx, "deviceType")?
-                .value(cx)
-                .as_str(),
-        )
-        .unwrap_or_default(),
-        initial_volume: Some(
-            connect_config
-                .get::<JsNumber, _, _>(cx, "initialVolume")?
-                .value(cx) as u16,
-        ),
-        has_volume_ctrl: connect_config
-            .get::<JsBoolean, _, _>(cx, "hasVolumeControl")?
-            .value(cx),
+        name: reader.string(cx, connect_config, path, "name", DEFAULT_DEVICE_NAME)?,
+        device_type: reader.choice(
+            cx,
+            connect_config,
+            path,
+            "deviceType",
+            DeviceType::Computer,
+            DEVICE_TYPES,
+        )?,
+        initial_volume: Some(reader.number(
+            cx,
+            connect_config,
+            path,
+            "initialVolume",
+            DEFAULT_INITIAL_VOLUME,
+            0.0..=u16::MAX as f64,
+        )? as u16),
+        has_volume_ctrl: reader.bool(cx, connect_config, path, "hasVolumeControl", true)?,
     })
 }
 
-fn get_path_from_str(
+/// Identity of the device and how it reaches Spotify, each setting left out
+/// falls back to librespot's default. The device id is random then, so
+/// Connect apps list a new device on every start
+pub fn get_session_config_from_obj(
     cx: &mut FunctionContext,
     obj: Handle<JsObject>,
+    reader: &mut ConfigReader,
+) -> Result<SessionConfig, Throw> {
+    let defaults = SessionConfig::default();
+    let path = SESSION_CONFIG;
+    let session = match reader.object(cx, obj, "", path)? {
+        Some(session) => session,
+        None => cx.empty_object(),
+    };
+    reader.warn_unknown_keys(cx, session, path, SESSION_KEYS)?;
+
+    let proxy = match reader.optional_string(cx, session, path, "proxy")? {
+        Some(proxy) => match Url::parse(&proxy) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Some(url),
+            _ => {
+                reader.issue(
+                    join_path(path, "proxy"),
+                    IssueKind::UnknownValue,
+                    format!("must be an http or https URL, got \"{}\"", proxy),
+                );
+                None
+            }
+        },
+        None => None,
+    };
+
+    Ok(SessionConfig {
+        client_id: reader.string(cx, session, path, "client_id", &defaults.client_id)?,
+        device_id: reader.string(cx, session, path, "device_id", &defaults.device_id)?,
+        proxy,
+        ap_port: reader
+            .optional_number(cx, session, path, "ap_port", 1.0..=u16::MAX as f64)?
+            .map(|port| port as u16),
+        tmp_dir: reader
+            .optional_string(cx, session, path, "tmp_dir")?
+            .map(PathBuf::from)
+            .unwrap_or(defaults.tmp_dir),
+        // Left to the account setting unless set
+        autoplay: reader.optional_bool(cx, session, path, "autoplay")?,
+    })
+}
+
+fn get_optional_value<'a, V: Value>(
+    cx: &mut FunctionContext<'a>,
+    obj: Handle<JsObject>,
     key: &str,
-) -> Result<Option<PathBuf>, Throw> {
-    let str_js = obj.get_value(cx, key)?;
-    if str_js.is_a::<JsString, _>(cx) {
-        let str = str_js.downcast_or_throw::<JsString, _>(cx)?.value(cx);
-        return Ok(Some(
-            PathBuf::from_str(str.as_str())
-                .or_else(|err| Err(cx.throw_error(err.to_string()).unwrap()))?,
-        ));
+) -> Result<Option<Handle<'a, V>>, Throw> {
+    let value = obj.get_value(cx, key)?;
+    if value.is_a::<V, _>(cx) {
+        return Ok(Some(value.downcast_or_throw::<V, _>(cx)?));
     }
     Ok(None)
 }
 
-pub fn get_cache_config_from_obj(
+/// Options of a context load, its problems are thrown as one error
+pub fn get_context_options_from_obj(
     cx: &mut FunctionContext,
     obj: Handle<JsObject>,
-) -> Result<Cache, Throw> {
-    let cache_config = obj
-        .get::<JsObject, _, _>(cx, "cache")
-        .or_else(|err| Err(cx.throw_error(err.to_string()).unwrap()))?;
-
-    let size_limiter_js = cache_config.get_value(cx, "size_limiter")?;
-    let mut size_limiter: Option<u64> = None;
-    if size_limiter_js.is_a::<JsNumber, _>(cx) {
-        size_limiter = Some(
-            size_limiter_js
-                .downcast_or_throw::<JsNumber, _>(cx)?
-                .value(cx) as u64,
+) -> Result<ContextLoadOptions, Throw> {
+    let mut reader = ConfigReader::new();
+    reader.warn_unknown_keys(cx, obj, "", CONTEXT_OPTION_KEYS)?;
+
+    let tracks = reader.array(cx, obj, "", "tracks")?;
+    let start_track = reader.optional_string(cx, obj, "", "startTrack")?;
+    let options = ContextLoadOptions {
+        tracks: None,
+        start_index: reader.number(cx, obj, "", "startIndex", 0.0, 0.0..=u32::MAX as f64)? as u32,
+        start_track: None,
+        position_ms: reader.number(cx, obj, "", "positionMs", 0.0, 0.0..=u32::MAX as f64)? as u32,
+        start_playing: reader.bool(cx, obj, "", "autoPlay", false)?,
+        shuffle: reader.bool(cx, obj, "", "shuffle", false)?,
+        repeat: reader.bool(cx, obj, "", "repeat", false)?,
+    };
+    reader.check(cx)?;
+
+    // Invalid uris are thrown with their own error code
+    let tracks = match tracks {
+        Some(arr) => Some(get_spotify_ids_from_arr(cx, arr)?),
+        None => None,
+    };
+    let start_track = match start_track {
+        Some(uri) => Some(
+            SpotifyId::from_uri(uri.as_str())
+                .context("uri", &uri)
+                .or_throw(cx)?,
+        ),
+        None => None,
+    };
+
+    Ok(ContextLoadOptions {
+        tracks,
+        start_track,
+        ..options
+    })
+}
+
+/// `backend` with the optional `device` and sample `format` it opens, the
+/// js backend also requires a `pcm_callback`
+pub fn get_backend_from_obj(
+    cx: &mut FunctionContext,
+    obj: Handle<JsObject>,
+    reader: &mut ConfigReader,
+) -> Result<BackendConfig, Throw> {
+    let backend = BackendConfig {
+        name: reader.string(cx, obj, "", "backend", "")?,
+        device: reader.optional_string(cx, obj, "", "device")?,
+        format: reader.choice(cx, obj, "", "format", AudioFormat::F32, FORMATS)?,
+    };
+    backend.validate(reader).or_throw(cx)?;
+
+    if backend.name == JS_BACKEND
+        && get_optional_value::<JsFunction>(cx, obj, "pcm_callback")?.is_none()
+    {
+        reader.issue(
+            "pcm_callback".to_string(),
+            IssueKind::Missing,
+            "is required by the js backend",
         );
     }
-    Ok(Cache::new(
-        get_path_from_str(cx, cache_config, "credentials_location")?,
-        get_path_from_str(cx, cache_config, "volume_location")?,
-        get_path_from_str(cx, cache_config, "audio_location")?,
-        size_limiter,
-    )
-    .or_else(|err| Err(cx.throw_error(err.to_string()).unwrap()))?)
+
+    Ok(backend)
+}
+
+/// The js backend delivers the decoded audio to `pcm_callback`, which is
+/// required when it is selected
+pub fn get_pcm_output_from_obj(
+    cx: &mut FunctionContext,
+    obj: Handle<JsObject>,
+    backend: &str,
+) -> Result<Option<Arc<PcmOutput>>, Throw> {
+    if backend != JS_BACKEND {
+        return Ok(None);
+    }
+
+    let callback = match get_optional_value::<JsFunction>(cx, obj, "pcm_callback")? {
+        Some(callback) => callback.root(cx),
+        None => {
+            return NativeError::new(
+                ErrorCode::InvalidConfig,
+                "The js backend requires a pcm_callback",
+            )
+            .throw(cx)
+        }
+    };
+
+    let mut channel = cx.channel();
+    channel.unref(cx);
+    Ok(Some(Arc::new(PcmOutput::new(channel, callback))))
+}
+
+/// Locations of the caches, each left out is disabled. The caches are only
+/// created once the whole config was checked, since creating them touches
+/// the disk
+pub struct CacheSettings {
+    credentials_location: Option<PathBuf>,
+    volume_location: Option<PathBuf>,
+    audio_location: Option<PathBuf>,
+    size_limiter: Option<u64>,
+    canvas_location: Option<PathBuf>,
+    canvas_size_limit: u64,
+}
+
+impl CacheSettings {
+    pub fn cache(&self) -> Result<Cache, NativeError> {
+        Cache::new(
+            self.credentials_location.clone(),
+            self.volume_location.clone(),
+            self.audio_location.clone(),
+            self.size_limiter,
+        )
+        .prefixed("Failed to create cache")
+    }
+
+    /// Canvas media is stored in `canvas_location`, or in a `canvas`
+    /// directory within the audio cache. Without an audio cache it is kept
+    /// next to the cached credentials, and disabled if neither is configured
+    pub fn canvas_cache(&self) -> Result<Option<Arc<CanvasCache>>, NativeError> {
+        let location = match (
+            &self.canvas_location,
+            &self.audio_location,
+            &self.credentials_location,
+        ) {
+            (Some(location), _, _) => location.clone(),
+            (None, Some(location), _) | (None, None, Some(location)) => location.join("canvas"),
+            (None, None, None) => return Ok(None),
+        };
+
+        let canvas_cache = CanvasCache::new(location, self.canvas_size_limit)
+            .prefixed("Failed to create canvas cache")?;
+        Ok(Some(Arc::new(canvas_cache)))
+    }
+
+    pub fn token_cache(&self) -> Arc<TokenCache> {
+        Arc::new(TokenCache::new(self.credentials_location.clone()))
+    }
+}
+
+pub fn get_cache_settings_from_obj(
+    cx: &mut FunctionContext,
+    obj: Handle<JsObject>,
+    reader: &mut ConfigReader,
+) -> Result<CacheSettings, Throw> {
+    let path = CACHE_CONFIG;
+    let cache_config = match reader.object(cx, obj, "", path)? {
+        Some(cache_config) => cache_config,
+        None => cx.empty_object(),
+    };
+    reader.warn_unknown_keys(cx, cache_config, path, CACHE_KEYS)?;
+
+    let credentials_location = reader
+        .optional_string(cx, cache_config, path, "credentials_location")?
+        .map(PathBuf::from);
+    let audio_location = reader
+        .optional_string(cx, cache_config, path, "audio_location")?
+        .map(PathBuf::from);
+    // The volume is kept next to the audio files unless configured
+    let volume_location = reader
+        .optional_string(cx, cache_config, path, "volume_location")?
+        .map(PathBuf::from)
+        .or_else(|| audio_location.clone());
+    let canvas_location = reader
+        .optional_string(cx, cache_config, path, "canvas_location")?
+        .map(PathBuf::from);
+
+    let size_limiter =
+        reader.optional_number(cx, cache_config, path, "size_limiter", 0.0..=f64::MAX)?;
+    if size_limiter.is_some() && audio_location.is_none() {
+        reader.issue(
+            join_path(path, "audio_location"),
+            IssueKind::Missing,
+            "is required when size_limiter is set",
+        );
+    }
+
+    let canvas_size_limit = reader.number(
+        cx,
+        cache_config,
+        path,
+        "canvas_size_limit",
+        DEFAULT_CANVAS_SIZE_LIMIT as f64,
+        0.0..=f64::MAX,
+    )?;
+
+    Ok(CacheSettings {
+        credentials_location,
+        volume_location,
+        audio_location,
+        size_limiter: size_limiter.map(|limit| limit as u64),
+        canvas_location,
+        canvas_size_limit: canvas_size_limit as u64,
+    })
 }
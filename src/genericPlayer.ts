@@ -1,11 +1,15 @@
 import EventEmitter from "events"
-import { TokenHandler } from "./tokenHandler"
-import { PositionHolder } from "./positionHolder"
+import { Readable } from "stream"
 import {
+  AudioBackend,
+  Capture,
   ConstructorConfig,
+  PcmFrame,
   Token,
+  CanvazBatchResponse,
   CanvazResponse,
   LyricsResponse,
+  Metadata,
 } from "./types"
 import { PlayerEvent, PlayerEventTypes, TokenScope } from "./types"
 import { TRACK_REGEX, _librespotModule } from "./utils"
@@ -30,9 +34,28 @@ export function safe_execution(
   return descriptor
 }
 
+/**
+ * Native threads keep the event callback alive until the player is closed, so it
+ * only holds a weak reference. Otherwise the player could never be garbage collected
+ */
+function weakEventCallback(player: GenericPlayer) {
+  const ref = new WeakRef(player)
+  return (event: PlayerEvent) => ref.deref()?.emitPlayerEvent(event)
+}
+
+/**
+ * pcmStream and the pcm_callback feeding it, which is kept alive natively like the
+ * event callback
+ */
+function weakPcmStream(
+  player: GenericPlayer
+): [Readable, (frame: PcmFrame) => boolean] {
+  const ref = new WeakRef(player)
+  const pcmStream = new Readable({ read: () => ref.deref()?.resumePcm() })
+  return [pcmStream, (frame) => pcmStream.push(frame.data)]
+}
+
 export abstract class GenericPlayer {
-  protected tokenHandler: TokenHandler
-  protected _positionHolder: PositionHolder
   public eventEmitter = new EventEmitter()
 
   protected playerInstance: PlayerNativeObject | undefined
@@ -41,87 +64,53 @@ export abstract class GenericPlayer {
 
   protected device_id!: string
 
+  protected instance_id!: number
+
   protected _isInitialized = false
 
+  protected config!: NativeConfig
+
+  /**
+   * Decoded audio when using the js backend without a pcm_callback
+   */
+  public pcmStream: Readable | undefined
+
   public get isInitialized() {
     return this._isInitialized
   }
 
   protected abstract onPlayerInitialized(): void
 
-  private validateConfig(config: ConstructorConfig): FullConstructorConfig {
-    if (!config.auth) {
-      throw new Error("missing auth details from config")
-    }
-
-    if (!config.auth.username || !config.auth.password) {
-      throw new Error("missing username or password from config")
-    }
-
-    config.auth.authType = config.auth.authType ?? "AUTHENTICATION_USER_PASS"
-
-    config.backend = config.backend ?? ""
-    config.bitrate = config.bitrate ?? "320"
-    config.gapless = config.gapless ?? false
-    config.passThrough = config.passThrough ?? false
-
-    config.connectConfig = {
-      deviceType: config.connectConfig?.deviceType ?? "computer",
-      hasVolumeControl: config.connectConfig?.hasVolumeControl ?? true,
-      initialVolume: config.connectConfig?.initialVolume ?? 32768,
-      name: config.connectConfig?.name ?? "librespot",
-    }
-
-    config.normalizationConfig = {
-      normalization: config.normalizationConfig?.normalization ?? false,
-      normalizationType:
-        config.normalizationConfig?.normalizationType ?? "auto",
-      normalizationMethod:
-        config.normalizationConfig?.normalizationMethod ?? "basic",
-      normalizationAttackCF:
-        config.normalizationConfig?.normalizationAttackCF ?? 0,
-      normalizationKneeDB: config.normalizationConfig?.normalizationKneeDB ?? 0,
-      normalizationPregain:
-        config.normalizationConfig?.normalizationPregain ?? 0,
-      normalizationReleaseCF:
-        config.normalizationConfig?.normalizationReleaseCF ?? 0,
-      normalizationThreshold:
-        config.normalizationConfig?.normalizationThreshold ?? 0,
+  /**
+   * Sets up pcmStream when using the js backend without a pcm_callback. Everything
+   * else is validated natively, which also applies the defaults
+   */
+  private prepareConfig(config: ConstructorConfig): NativeConfig {
+    if (config.backend === "js" && !config.pcm_callback) {
+      const [pcmStream, pcmCallback] = weakPcmStream(this)
+      this.pcmStream = pcmStream
+      config.pcm_callback = pcmCallback
     }
 
-    config.cache = {
-      audio_location: config.cache?.audio_location,
-      credentials_location: config.cache?.credentials_location,
-      volume_location: config.cache?.audio_location,
-      size_limiter: config.cache?.size_limiter,
-    }
-
-    config.pos_update_interval = config.pos_update_interval ?? 500
-
-    config.volumeCtrl = config.volumeCtrl ?? "log"
-
-    return config as FullConstructorConfig
+    return config
   }
 
   constructor(
     config: ConstructorConfig,
     playerConstructMethod:
       | "create_player"
-      | "create_player_spirc" = "create_player"
+      | "create_player_spirc"
+      | "create_player_spirc_discovery" = "create_player"
   ) {
-    let validatedConfig = this.validateConfig(config)
-    this.tokenHandler = new TokenHandler(
-      validatedConfig.cache?.credentials_location
-    )
-    this._positionHolder = new PositionHolder(config.pos_update_interval)
+    this.config = this.prepareConfig(config)
 
     if (config.logLevel) {
       _librespotModule.init_logger(config.logLevel)
     }
 
     _librespotModule[playerConstructMethod](
-      validatedConfig,
-      this.player_event_callback.bind(this)
+      this.config,
+      weakEventCallback(this)
     )
       .then((val) => {
         this.playerInstance = val
@@ -139,16 +128,12 @@ export abstract class GenericPlayer {
           error: e,
         })
       })
-
-    this._positionHolder.callback = (position_ms) => {
-      this.eventEmitter.emit("TimeUpdated", {
-        event: "TimeUpdated",
-        position_ms,
-      })
-    }
   }
 
-  private player_event_callback(event: PlayerEvent) {
+  /**
+   * Emits an event received from the native player
+   */
+  public emitPlayerEvent(event: PlayerEvent) {
     this.eventEmitter.emit(event.event, event)
   }
 
@@ -157,32 +142,11 @@ export abstract class GenericPlayer {
       this._volume = event.volume
     })
 
-    this.addListener("Playing", (e) => {
-      this._positionHolder.setListener()
-      this._positionHolder.position = e.position_ms
-    })
-
-    this.addListener("Paused", (e) => {
-      this._positionHolder.clearListener()
-      this._positionHolder.position = e.position_ms
-    })
-
-    this.addListener("Stopped", (e) => {
-      this._positionHolder.clearListener()
-      this._positionHolder.position = 0
-    })
-
-    this.addListener("PositionCorrection", (e) => {
-      this._positionHolder.position = e.position_ms
-    })
-
-    this.addListener("Seeked", (e) => {
-      this._positionHolder.position = e.position_ms
-    })
-
-    this.addListener("TrackChanged", () => {
-      this._positionHolder.clearListener()
-      this._positionHolder.position = 0
+    this.addListener("PositionChanged", (e) => {
+      this.eventEmitter.emit("TimeUpdated", {
+        event: "TimeUpdated",
+        position_ms: e.position_ms,
+      })
     })
   }
 
@@ -215,10 +179,24 @@ export abstract class GenericPlayer {
     this.registerListeners()
   }
 
+  /**
+   * Lists the audio backends and the output devices each of them offers
+   */
+  public static getAudioBackends(): Promise<AudioBackend[]> {
+    return _librespotModule.get_audio_backends()
+  }
+
   public getDeviceId() {
     return this.device_id
   }
 
+  /**
+   * Id of the native player instance, attached to every event as instance_id
+   */
+  public getInstanceId() {
+    return this.instance_id
+  }
+
   protected validateUri(val: string): [string | undefined, string | undefined] {
     const match = val.match(TRACK_REGEX)
 
@@ -238,17 +216,52 @@ export abstract class GenericPlayer {
     return [undefined, undefined]
   }
 
+  protected validateTrackUris(vals: string[]): string[] {
+    return vals
+      .map((val) => this.validateUri(val))
+      .filter(([uri, type]) => uri && type === "track")
+      .map(([uri]) => uri as string)
+  }
+
+  /**
+   * Merges the update into the current config, what both leave out falls back to
+   * the native defaults
+   */
+  protected mergeConfig(update: Partial<ConstructorConfig>): NativeConfig {
+    return this.prepareConfig({
+      ...this.config,
+      ...update,
+      normalizationConfig: {
+        ...this.config.normalizationConfig,
+        ...update.normalizationConfig,
+      },
+    })
+  }
+
+  public abstract updateConfig(
+    update: Partial<ConstructorConfig>
+  ): Promise<void>
   public abstract setVolume(volume: number, raw?: boolean): Promise<void>
   public abstract load(
     trackURIs: string | string[],
     autoPlay?: boolean,
     startPosition?: number
   ): Promise<void>
-  public abstract getToken(...scopes: TokenScope[]): Promise<Token | undefined>
+  public abstract getToken(...scopes: TokenScope[]): Promise<Token>
+  public abstract resumePcm(): void
+  public abstract getCapture(): Capture
+  public abstract clearCapture(): void
+  public abstract addEventListener(
+    callback: (event: PlayerEvent) => void
+  ): number
+  public abstract removeEventListener(listenerId: number): boolean
   public abstract getVolume(raw?: boolean): number
   public abstract seek(posMs: number): Promise<void>
   public abstract close(): Promise<void>
   public abstract getCurrentPosition(): number
   public abstract getCanvas(track: string): Promise<CanvazResponse | undefined>
+  public abstract getCanvasFile(track: string): Promise<string | undefined>
+  public abstract getCanvases(tracks: string[]): Promise<CanvazBatchResponse>
   public abstract getLyrics(track: string): Promise<LyricsResponse | undefined>
+  public abstract getMetadata(uri: string): Promise<Metadata | undefined>
 }
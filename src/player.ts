@@ -1,10 +1,22 @@
-import { TokenScope } from "./types"
+import {
+  CanvazBatchResponse,
+  Capture,
+  ConstructorConfig,
+  LyricsResponse,
+  Metadata,
+  PlayerEvent,
+  RepeatMode,
+  TokenScope,
+} from "./types"
 import { DEFAULT_SCOPES, _librespotModule } from "./utils"
 import { GenericPlayer, safe_execution } from "./genericPlayer"
 
 export class SpotifyPlayer extends GenericPlayer {
   protected onPlayerInitialized() {
     this.device_id = _librespotModule.get_device_id.call(this.playerInstance)
+    this.instance_id = _librespotModule.get_instance_id.call(
+      this.playerInstance
+    )
   }
 
   /**
@@ -33,21 +45,90 @@ export class SpotifyPlayer extends GenericPlayer {
   }
 
   /**
-   * Clear all listeners and close player
+   * Clear all listeners and close player.
+   * Resolves once the session is shut down and the audio device is released
    */
   @safe_execution
   public async close() {
-    this._positionHolder.clearListener()
     this.eventEmitter.removeAllListeners()
     await _librespotModule.close_player.call(this.playerInstance)
+    this.pcmStream?.push(null)
+  }
+
+  /**
+   * Applies playback settings at runtime: bitrate, gapless, passThrough, normalizationConfig,
+   * ditherer, backend, device and format. Resolves once the player was rebuilt with them,
+   * carrying over the current track, position and volume, and emits ConfigChanged
+   * Switching to or from the js and capture backends is not supported
+   * @param update settings to change, the others keep their current value
+   */
+  @safe_execution
+  public async updateConfig(update: Partial<ConstructorConfig>) {
+    const config = this.mergeConfig(update)
+    await _librespotModule.update_config.call(this.playerInstance, config)
+    this.config = config
+  }
+
+  /**
+   * Resumes delivery of decoded audio after pcm_callback returned false
+   */
+  public resumePcm() {
+    // The stream may ask for data before the player is initialized
+    if (this.playerInstance) {
+      _librespotModule.resume_pcm.call(this.playerInstance)
+    }
+  }
+  /**
+   * Audio recorded by the capture backend
+   * @returns segments played since the player was created or the capture was cleared
+   */
+  @safe_execution
+  public getCapture(): Capture {
+    return _librespotModule.get_capture.call(this.playerInstance)
+  }
+
+  /**
+   * Discards the recorded audio and restarts the capture clock
+   */
+  @safe_execution
+  public clearCapture() {
+    _librespotModule.clear_capture.call(this.playerInstance)
   }
 
+  /**
+   * Registers a callback receiving every event of this player natively,
+   * alongside the listeners added with on/addListener
+   * @param callback called with each event, which carries this player's instance_id
+   * @returns id to pass to removeEventListener
+   */
+  @safe_execution
+  public addEventListener(callback: (event: PlayerEvent) => void): number {
+    return _librespotModule.add_event_listener.call(
+      this.playerInstance,
+      callback
+    )
+  }
+
+  /**
+   * Unregisters a callback added with addEventListener
+   * @param listenerId id returned by addEventListener
+   * @returns false if no callback was registered with that id
+   */
+  @safe_execution
+  public removeEventListener(listenerId: number): boolean {
+    return _librespotModule.remove_event_listener.call(
+      this.playerInstance,
+      listenerId
+    )
+  }
+
+
   /**
    * Get current position of player
    * @returns current position in milliseconds
    */
   public getCurrentPosition() {
-    return this._positionHolder.position
+    return _librespotModule.get_position.call(this.playerInstance)
   }
 
   /**
@@ -68,27 +149,23 @@ export class SpotifyPlayer extends GenericPlayer {
   /**
    * Returns volume in percentage or uint16
    * @param raw If true, returns volume in uint16. Otherwise percentage
+   * Read back from the mixer, so hardware volume changed outside the player is reflected
    * @returns volume in percentage or uint16
    */
   public getVolume(raw = false) {
+    const volume =
+      (this.playerInstance &&
+        _librespotModule.get_volume.call(this.playerInstance)) ??
+      this._volume
+
     if (raw) {
-      return this._volume
+      return volume
     }
 
-    return (this._volume / 65535) * 100
+    return (volume / 65535) * 100
   }
 
-  /**
-   * Loads a track by Spotify URI or URL
-   * @param trackURI spotify URI or URL of track to be loaded. (Eg. spotify:track:4PTG3Z6ehGkBFwjybzWkR8)
-   * @param autoplay if true, track will start playing immediately after being loaded. (Default: false)
-   */
-  @safe_execution
-  public async load(
-    trackURIs: string | string[],
-    autoPlay = false,
-    startPosition = 0
-  ) {
+  private parseTrackUris(trackURIs: string | string[]) {
     const regex = new RegExp(
       /^(?<urlType>(?:spotify:|(?:https?:\/\/(?:open|play)\.spotify\.com\/)))(?:embed)?\/?(?<type>album|track|playlist|artist)(?::|\/)((?:[0-9a-zA-Z]){22})/
     )
@@ -97,7 +174,7 @@ export class SpotifyPlayer extends GenericPlayer {
       trackURIs = [trackURIs]
     }
 
-    const parsedTrackUris = trackURIs.map(trackURI => {
+    return trackURIs.map((trackURI) => {
       const match = trackURI.match(regex)
 
       if (match?.groups?.type) {
@@ -111,44 +188,160 @@ export class SpotifyPlayer extends GenericPlayer {
 
       return trackURI
     })
+  }
 
-    for (const t of parsedTrackUris) {
-      await _librespotModule.load_track.call(
-        this.playerInstance,
-        t,
-        autoPlay,
-        startPosition
-      )
-    }
+  /**
+   * Replaces the queue with tracks by Spotify URI or URL and loads the first one
+   * @param trackURIs spotify URIs or URLs of tracks to be loaded. (Eg. spotify:track:4PTG3Z6ehGkBFwjybzWkR8)
+   * @param autoplay if true, track will start playing immediately after being loaded. (Default: false)
+   * @param startPosition position in milliseconds to start the first track from. (Default: 0)
+   */
+  @safe_execution
+  public async load(
+    trackURIs: string | string[],
+    autoPlay = false,
+    startPosition = 0
+  ) {
+    await _librespotModule.queue_clear.call(this.playerInstance)
+    await _librespotModule.queue_add.call(
+      this.playerInstance,
+      this.parseTrackUris(trackURIs)
+    )
+    await _librespotModule.queue_play.call(
+      this.playerInstance,
+      0,
+      autoPlay,
+      startPosition
+    )
   }
 
   /**
-   * Get spotify access token for logged in account
-   * @param scopes scopes to get token for. (https://developer.spotify.com/documentation/general/guides/authorization/scopes/)
-   * @returns token
+   * Adds tracks to the end of the queue
+   * @param trackURIs spotify URIs or URLs of tracks to be added
    */
   @safe_execution
-  public async getToken(...scopes: TokenScope[]) {
-    scopes = scopes && scopes.length > 0 ? scopes : DEFAULT_SCOPES
+  public async addToQueue(trackURIs: string | string[]) {
+    await _librespotModule.queue_add.call(
+      this.playerInstance,
+      this.parseTrackUris(trackURIs)
+    )
+  }
 
-    const cachedToken = await this.tokenHandler.getToken(scopes)
-    if (cachedToken) {
-      return cachedToken
-    }
+  /**
+   * Inserts tracks into the queue
+   * @param index position in the queue to insert tracks at
+   * @param trackURIs spotify URIs or URLs of tracks to be inserted
+   */
+  @safe_execution
+  public async insertInQueue(index: number, trackURIs: string | string[]) {
+    await _librespotModule.queue_insert.call(
+      this.playerInstance,
+      index,
+      this.parseTrackUris(trackURIs)
+    )
+  }
+
+  /**
+   * Removes a track from the queue
+   * @param index position of the track in the queue
+   */
+  @safe_execution
+  public async removeFromQueue(index: number) {
+    await _librespotModule.queue_remove.call(this.playerInstance, index)
+  }
+
+  /**
+   * Moves a track to another position in the queue
+   * @param from current position of the track in the queue
+   * @param to new position of the track in the queue
+   */
+  @safe_execution
+  public async moveInQueue(from: number, to: number) {
+    await _librespotModule.queue_move.call(this.playerInstance, from, to)
+  }
+
+  /**
+   * Removes all tracks from the queue
+   */
+  @safe_execution
+  public async clearQueue() {
+    await _librespotModule.queue_clear.call(this.playerInstance)
+  }
 
-    const res = await _librespotModule.get_token.call(
+  /**
+   * Plays a track from the queue
+   * @param index position of the track in the queue
+   * @param autoplay if true, track will start playing immediately after being loaded. (Default: true)
+   * @param startPosition position in milliseconds to start the track from. (Default: 0)
+   */
+  @safe_execution
+  public async playFromQueue(
+    index: number,
+    autoPlay = true,
+    startPosition = 0
+  ) {
+    await _librespotModule.queue_play.call(
       this.playerInstance,
-      scopes.join(",")
+      index,
+      autoPlay,
+      startPosition
     )
+  }
+
+  /**
+   * Skips to the next track in the queue
+   */
+  @safe_execution
+  public async next() {
+    await _librespotModule.queue_next.call(this.playerInstance)
+  }
 
-    if (res) {
-      res.scopes = (res.scopes as unknown as string).split(",") as TokenScope[]
-      res.expiry_from_epoch = Date.now() + res.expires_in
+  /**
+   * Goes back to the previous track in the queue
+   */
+  @safe_execution
+  public async previous() {
+    await _librespotModule.queue_previous.call(this.playerInstance)
+  }
 
-      await this.tokenHandler.addToken(res)
-    }
+  /**
+   * Set repeat mode of the queue
+   * @param mode "off", "one" to repeat the current track or "all" to repeat the whole queue
+   */
+  @safe_execution
+  public async setRepeat(mode: RepeatMode) {
+    await _librespotModule.queue_set_repeat.call(this.playerInstance, mode)
+  }
+
+  /**
+   * Shuffle or unshuffle the queue
+   * @param shuffle if true, remaining tracks are played in random order
+   */
+  @safe_execution
+  public async setShuffle(shuffle: boolean) {
+    await _librespotModule.queue_set_shuffle.call(this.playerInstance, shuffle)
+  }
 
-    return res
+  /**
+   * Get tracks in the queue along with repeat and shuffle state
+   * @returns queue
+   */
+  @safe_execution
+  public async getQueue() {
+    return _librespotModule.get_queue.call(this.playerInstance)
+  }
+
+  /**
+   * Get spotify access token for logged in account
+   * Tokens are cached natively and refreshed before they expire, see the TokenRefreshed event
+   * @param scopes scopes to get token for. (https://developer.spotify.com/documentation/general/guides/authorization/scopes/)
+   * @returns token holding every requested scope, rejects with a NativeError on failure
+   */
+  @safe_execution
+  public async getToken(...scopes: TokenScope[]) {
+    scopes = scopes && scopes.length > 0 ? scopes : DEFAULT_SCOPES
+
+    return _librespotModule.get_token.call(this.playerInstance, scopes)
   }
 
   /**
@@ -170,26 +363,60 @@ export class SpotifyPlayer extends GenericPlayer {
     }
   }
 
+  /**
+   * Returns spotify canvases for many tracks at once
+   * @param tracks track URIs or URLs to get canvases for
+   * @returns Canvases by track URI, tracks without a canvas are listed in misses
+   */
+  @safe_execution
+  public async getCanvases(tracks: string[]): Promise<CanvazBatchResponse> {
+    return _librespotModule.get_canvases.call(
+      this.playerInstance,
+      this.validateTrackUris(tracks)
+    )
+  }
+
+  /**
+   * Downloads the canvas of a track into the canvas cache.
+   * Cached files are reused until their TTL expires and kept while the canvas is unchanged
+   * @param track track URI or URL to get canvas for
+   * @returns Local path to the canvas media or undefined if the track has none
+   */
+  @safe_execution
+  public async getCanvasFile(track: string): Promise<string | undefined> {
+    const [uri, type] = this.validateUri(track)
+
+    if (uri && type === "track") {
+      return _librespotModule.get_canvas_file.call(this.playerInstance, uri)
+    }
+  }
+
+  /**
+   * Returns metadata of a track, album, artist, playlist, episode or show
+   * @param uri Spotify URI or URL
+   * @returns Metadata object, its type field tells which kind it is
+   */
+  @safe_execution
+  public async getMetadata(uri: string): Promise<Metadata | undefined> {
+    const [parsedUri] = this.validateUri(uri)
+
+    if (parsedUri) {
+      return _librespotModule.get_metadata.call(this.playerInstance, parsedUri)
+    }
+  }
+
   /**
    * Get lyrics for spotify track
    * @param track Spotify track URI or URL
-   * @returns
+   * @returns Parsed lyrics, or undefined if the track has none.
+   * Rejects with code PARSE_ERROR if the response could not be parsed
    */
   @safe_execution
-  public async getLyrics(track: string) {
+  public async getLyrics(track: string): Promise<LyricsResponse | undefined> {
     const [uri, type] = this.validateUri(track)
 
     if (uri && type === "track") {
-      const metadata = await _librespotModule.get_lyrics.call(
-        this.playerInstance,
-        uri
-      )
-
-      try {
-        return JSON.parse(metadata)
-      } catch {
-        return metadata
-      }
+      return _librespotModule.get_lyrics.call(this.playerInstance, uri)
     }
   }
 }
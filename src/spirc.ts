@@ -1,4 +1,13 @@
-import { ConstructorConfig, LyricsResponse, Token } from "./types"
+import {
+  CanvazBatchResponse,
+  Capture,
+  ConstructorConfig,
+  ContextLoadOptions,
+  LyricsResponse,
+  Metadata,
+  PlayerEvent,
+  Token,
+} from "./types"
 import { TokenScope } from "./types"
 import { request, DEFAULT_SCOPES, _librespotModule } from "./utils"
 import { GenericPlayer, safe_execution } from "./genericPlayer"
@@ -11,10 +20,21 @@ export class SpotifyPlayerSpirc extends GenericPlayer {
     this.device_id = _librespotModule.get_device_id_spirc.call(
       this.playerInstance
     )
+    this.instance_id = _librespotModule.get_instance_id_spirc.call(
+      this.playerInstance
+    )
   }
 
-  constructor(config: ConstructorConfig) {
-    super(config, "create_player_spirc")
+  /**
+   * @param config player config
+   * @param discovery if true, the device is advertised over zeroconf and waits for a
+   * Spotify Connect client to hand over credentials instead of using auth. (Default: false)
+   */
+  constructor(config: ConstructorConfig, discovery = false) {
+    super(
+      config,
+      discovery ? "create_player_spirc_discovery" : "create_player_spirc"
+    )
   }
 
   /**
@@ -33,6 +53,80 @@ export class SpotifyPlayerSpirc extends GenericPlayer {
     await _librespotModule.pause_spirc.call(this.playerInstance)
   }
 
+  /**
+   * Toggle between playing and paused
+   */
+  @safe_execution
+  public async togglePlay() {
+    await _librespotModule.play_pause_spirc.call(this.playerInstance)
+  }
+
+  /**
+   * Skip to the next track
+   */
+  @safe_execution
+  public async next() {
+    await _librespotModule.next_spirc.call(this.playerInstance)
+  }
+
+  /**
+   * Go back to the previous track, or restart the current one
+   */
+  @safe_execution
+  public async previous() {
+    await _librespotModule.prev_spirc.call(this.playerInstance)
+  }
+
+  /**
+   * Increase volume by one step
+   */
+  @safe_execution
+  public async volumeUp() {
+    await _librespotModule.volume_up_spirc.call(this.playerInstance)
+  }
+
+  /**
+   * Decrease volume by one step
+   */
+  @safe_execution
+  public async volumeDown() {
+    await _librespotModule.volume_down_spirc.call(this.playerInstance)
+  }
+
+  /**
+   * Enable or disable shuffle
+   * @param shuffle if true, shuffle is enabled
+   */
+  @safe_execution
+  public async setShuffle(shuffle: boolean) {
+    await _librespotModule.shuffle_spirc.call(this.playerInstance, shuffle)
+  }
+
+  /**
+   * Enable or disable repeat
+   * @param repeat if true, repeat is enabled
+   */
+  @safe_execution
+  public async setRepeat(repeat: boolean) {
+    await _librespotModule.repeat_spirc.call(this.playerInstance, repeat)
+  }
+
+  /**
+   * Make this device the active Spotify Connect device
+   */
+  @safe_execution
+  public async activate() {
+    await _librespotModule.activate_spirc.call(this.playerInstance)
+  }
+
+  /**
+   * Stop playback and let another Spotify Connect device take over
+   */
+  @safe_execution
+  public async disconnect() {
+    await _librespotModule.disconnect_spirc.call(this.playerInstance)
+  }
+
   /**
    * Seek current song to position
    * @param posMs position in milliseconds
@@ -43,21 +137,91 @@ export class SpotifyPlayerSpirc extends GenericPlayer {
   }
 
   /**
-   * Clear all listeners and close player
+   * Clear all listeners and close player.
+   * Resolves once the session is shut down and the audio device is released
    */
   @safe_execution
   public async close() {
-    this._positionHolder.clearListener()
     this.eventEmitter.removeAllListeners()
     await _librespotModule.close_player_spirc.call(this.playerInstance)
+    this.pcmStream?.push(null)
+  }
+
+  /**
+   * Applies playback settings at runtime: bitrate, gapless, passThrough, normalizationConfig,
+   * ditherer, backend, device and format. Resolves once the player was rebuilt with them,
+   * carrying over the current track, position and volume, and emits ConfigChanged
+   * The device leaves Connect, reconnects under the same id and loads the context it was playing again
+   * Switching to or from the js and capture backends is not supported
+   * @param update settings to change, the others keep their current value
+   */
+  @safe_execution
+  public async updateConfig(update: Partial<ConstructorConfig>) {
+    const config = this.mergeConfig(update)
+    await _librespotModule.update_config_spirc.call(this.playerInstance, config)
+    this.config = config
+  }
+
+  /**
+   * Resumes delivery of decoded audio after pcm_callback returned false
+   */
+  public resumePcm() {
+    // The stream may ask for data before the player is initialized
+    if (this.playerInstance) {
+      _librespotModule.resume_pcm_spirc.call(this.playerInstance)
+    }
+  }
+  /**
+   * Audio recorded by the capture backend
+   * @returns segments played since the player was created or the capture was cleared
+   */
+  @safe_execution
+  public getCapture(): Capture {
+    return _librespotModule.get_capture_spirc.call(this.playerInstance)
+  }
+
+  /**
+   * Discards the recorded audio and restarts the capture clock
+   */
+  @safe_execution
+  public clearCapture() {
+    _librespotModule.clear_capture_spirc.call(this.playerInstance)
   }
 
+  /**
+   * Registers a callback receiving every event of this player natively,
+   * alongside the listeners added with on/addListener
+   * @param callback called with each event, which carries this player's instance_id
+   * @returns id to pass to removeEventListener
+   */
+  @safe_execution
+  public addEventListener(callback: (event: PlayerEvent) => void): number {
+    return _librespotModule.add_event_listener_spirc.call(
+      this.playerInstance,
+      callback
+    )
+  }
+
+  /**
+   * Unregisters a callback added with addEventListener
+   * @param listenerId id returned by addEventListener
+   * @returns false if no callback was registered with that id
+   */
+  @safe_execution
+  public removeEventListener(listenerId: number): boolean {
+    return _librespotModule.remove_event_listener_spirc.call(
+      this.playerInstance,
+      listenerId
+    )
+  }
+
+
   /**
    * Get current position of player
    * @returns current position in milliseconds
    */
   public getCurrentPosition() {
-    return this._positionHolder.position
+    return _librespotModule.get_position_spirc.call(this.playerInstance)
   }
 
   /**
@@ -78,14 +242,20 @@ export class SpotifyPlayerSpirc extends GenericPlayer {
   /**
    * Returns volume in percentage or uint16
    * @param raw If true, returns volume in uint16. Otherwise percentage
+   * Read back from the mixer, so hardware volume changed outside the player is reflected
    * @returns volume in percentage or uint16
    */
   public getVolume(raw = false) {
+    const volume =
+      (this.playerInstance &&
+        _librespotModule.get_volume_spirc.call(this.playerInstance)) ??
+      this._volume
+
     if (raw) {
-      return this._volume
+      return volume
     }
 
-    return (this._volume / 65535) * 100
+    return (volume / 65535) * 100
   }
 
   /**
@@ -106,6 +276,39 @@ export class SpotifyPlayerSpirc extends GenericPlayer {
     }
   }
 
+  /**
+   * Loads an album, playlist, artist or show by Spotify URI or URL
+   * @param contextURI spotify URI or URL of the context to be loaded. (Eg. spotify:album:4aawyAB9vmqN3uQ7FjRGTy)
+   * @param options tracks, start track and playback options
+   */
+  @safe_execution
+  public async loadContext(
+    contextURI: string,
+    options: ContextLoadOptions = {}
+  ) {
+    const [uri] = this.validateUri(contextURI)
+
+    if (!uri) {
+      throw new Error("Invalid context URI")
+    }
+
+    options = { ...options }
+
+    if (options.startTrack) {
+      options.startTrack = this.validateUri(options.startTrack)[0]
+    }
+
+    if (options.tracks) {
+      options.tracks = options.tracks.map((t) => this.validateUri(t)[0] ?? t)
+    }
+
+    await _librespotModule.load_context_spirc.call(
+      this.playerInstance,
+      uri,
+      options
+    )
+  }
+
   /**
    * Adds track to queue
    * @param trackURI spotify URI or URL of track to be added to queue. (Eg. spotify:track:4PTG3Z6ehGkBFwjybzWkR8)
@@ -141,31 +344,15 @@ export class SpotifyPlayerSpirc extends GenericPlayer {
 
   /**
    * Get spotify access token for logged in account
+   * Tokens are cached natively and refreshed before they expire, see the TokenRefreshed event
    * @param scopes scopes to get token for. (https://developer.spotify.com/documentation/general/guides/authorization/scopes/)
-   * @returns token
+   * @returns token holding every requested scope, rejects with a NativeError on failure
    */
   @safe_execution
   public async getToken(...scopes: TokenScope[]): Promise<Token> {
     scopes = scopes && scopes.length > 0 ? scopes : DEFAULT_SCOPES
 
-    const cachedToken = await this.tokenHandler.getToken(scopes)
-    if (cachedToken) {
-      return cachedToken
-    }
-
-    const res = await _librespotModule.get_token_spirc.call(
-      this.playerInstance,
-      scopes.join(",")
-    )
-
-    if (res) {
-      res.scopes = (res.scopes as unknown as string).split(",") as TokenScope[]
-      res.expiry_from_epoch = Date.now() + res.expires_in
-
-      await this.tokenHandler.addToken(res)
-    }
-
-    return res
+    return _librespotModule.get_token_spirc.call(this.playerInstance, scopes)
   }
 
   /**
@@ -187,22 +374,60 @@ export class SpotifyPlayerSpirc extends GenericPlayer {
     }
   }
 
+  /**
+   * Returns spotify canvases for many tracks at once
+   * @param tracks track URIs or URLs to get canvases for
+   * @returns Canvases by track URI, tracks without a canvas are listed in misses
+   */
+  @safe_execution
+  public async getCanvases(tracks: string[]): Promise<CanvazBatchResponse> {
+    return _librespotModule.get_canvases_spirc.call(
+      this.playerInstance,
+      this.validateTrackUris(tracks)
+    )
+  }
+
+  /**
+   * Downloads the canvas of a track into the canvas cache.
+   * Cached files are reused until their TTL expires and kept while the canvas is unchanged
+   * @param track track URI or URL to get canvas for
+   * @returns Local path to the canvas media or undefined if the track has none
+   */
+  @safe_execution
+  public async getCanvasFile(track: string): Promise<string | undefined> {
+    const [uri, type] = this.validateUri(track)
+
+    if (uri && type === "track") {
+      return _librespotModule.get_canvas_file_spirc.call(this.playerInstance, uri)
+    }
+  }
+
+  /**
+   * Returns metadata of a track, album, artist, playlist, episode or show
+   * @param uri Spotify URI or URL
+   * @returns Metadata object, its type field tells which kind it is
+   */
+  @safe_execution
+  public async getMetadata(uri: string): Promise<Metadata | undefined> {
+    const [parsedUri] = this.validateUri(uri)
+
+    if (parsedUri) {
+      return _librespotModule.get_metadata_spirc.call(this.playerInstance, parsedUri)
+    }
+  }
+
   /**
    * Get lyrics for spotify track
    * @param track Spotify track URI or URL
-   * @returns
+   * @returns Parsed lyrics, or undefined if the track has none.
+   * Rejects with code PARSE_ERROR if the response could not be parsed
    */
   @safe_execution
   public async getLyrics(track: string): Promise<LyricsResponse | undefined> {
     const [uri, type] = this.validateUri(track)
 
     if (uri && type === "track") {
-      const metadata = await _librespotModule.get_lyrics_spirc.call(
-        this.playerInstance,
-        uri
-      )
-
-      return JSON.parse(metadata)
+      return _librespotModule.get_lyrics_spirc.call(this.playerInstance, uri)
     }
   }
 }
\ No newline at end of file
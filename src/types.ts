@@ -1,16 +1,60 @@
+/**
+ * Every setting is optional and falls back to the default noted next to it
+ */
 export interface NormalizationConfig {
+  /**
+   * (Default false)
+   */
   normalization: boolean
+
+  /**
+   * (dB, Default 0)
+   */
   normalizationPregain: number
+
+  /**
+   * (Default auto)
+   */
   normalizationType: "auto" | "album" | "track"
+
+  /**
+   * (Default basic)
+   */
   normalizationMethod: "dynamic" | "basic"
+
+  /**
+   * Attack coefficient of the dynamic limiter, between 0 and 1 (Default 0)
+   */
   normalizationAttackCF: number
+
+  /**
+   * Knee of the dynamic limiter, at least 0 (dB, Default 0)
+   */
   normalizationKneeDB: number
+
+  /**
+   * Release coefficient of the dynamic limiter, between 0 and 1 (Default 0)
+   */
   normalizationReleaseCF: number
+
+  /**
+   * Threshold of the dynamic limiter, at most 0 (dBFS, Default 0)
+   */
   normalizationThreshold: number
 }
 
+/**
+ * Every setting is optional and falls back to the default noted next to it
+ */
 export interface ConnectConfig {
+  /**
+   * Name shown in Spotify Connect (Default librespot)
+   */
   name: string
+
+  /**
+   * (Default computer)
+   */
   deviceType:
     | "computer"
     | "tablet"
@@ -28,15 +72,104 @@ export interface ConnectConfig {
     | "chromebook"
     | "carthing"
     | "homething"
+
+  /**
+   * Between 0 and 65535 (Default 32768)
+   */
   initialVolume: number
+
+  /**
+   * (Default true)
+   */
   hasVolumeControl: boolean
+
+  /**
+   * Port to advertise the zeroconf discovery server on. (Default 0, random port)
+   */
+  discoveryPort: number
 }
 
+/**
+ * Each cache is disabled unless its location is set
+ */
 export interface CacheConfig {
   credentials_location?: string
   volume_location?: string
   audio_location?: string
+
+  /**
+   * Maximum size of the audio cache in bytes, requires audio_location (Default unlimited)
+   */
   size_limiter?: number
+
+  /**
+   * Directory for downloaded canvas media (Default "canvas" inside audio_location, or inside
+   * credentials_location without an audio cache)
+   */
+  canvas_location?: string
+
+  /**
+   * Maximum size of the canvas cache in bytes, least recently used files are evicted first (Default 200MB)
+   */
+  canvas_size_limit?: number
+}
+
+/**
+ * Every setting is optional and falls back to librespot's default
+ */
+export interface SessionConfig {
+  /**
+   * Client id the session identifies with (Default librespot's client id)
+   */
+  client_id: string
+
+  /**
+   * Id of the device in Spotify Connect. Set it to a fixed value so apps keep
+   * showing the same device across restarts (Default random on every start)
+   */
+  device_id: string
+
+  /**
+   * HTTP(S) proxy all Spotify traffic goes through, e.g. http://proxy.corp:3128
+   */
+  proxy: string
+
+  /**
+   * Port of the access point to connect to, e.g. 443 on networks that only allow https (Default any)
+   */
+  ap_port: number
+
+  /**
+   * Keep playing similar tracks once the context ends (Default the account setting)
+   */
+  autoplay: boolean
+
+  /**
+   * Directory for temporary files (Default the system temp dir)
+   */
+  tmp_dir: string
+}
+
+export interface ReconnectConfig {
+  /**
+   * Re-establish the session when the connection is lost (Default false)
+   */
+  enabled: boolean
+
+  /**
+   * Attempts before giving up with a ReconnectFailed event. 0 retries until the player is closed (Default 10)
+   */
+  max_attempts: number
+
+  /**
+   * Delay before the first attempt, doubled after every failed attempt (milliseconds, Default 1000)
+   */
+  initial_delay: number
+
+  /**
+   * Upper bound of the delay between attempts (milliseconds, Default 60000)
+   */
+  max_delay: number
 }
 
 /**
@@ -44,9 +177,9 @@ export interface CacheConfig {
  */
 export interface ConstructorConfig {
   /**
-   * Authentication config
+   * Authentication config (Not required when using zeroconf discovery)
    */
-  auth: Partial<AuthDetails>
+  auth?: Partial<AuthDetails>
 
   /**
    * Cache config
@@ -54,27 +187,63 @@ export interface ConstructorConfig {
   cache?: CacheConfig
 
   /**
-   * Interval at which player position is updated. (milliseconds)
+   * Interval at which PositionChanged events are emitted while playing. 0 disables them. (milliseconds, Default 500)
    */
   pos_update_interval?: number
 
+  /**
+   * Automatic reconnection config
+   */
+  reconnect?: Partial<ReconnectConfig>
+
   /**
    * Librespot backend to use (Default rodio)
-   * Possible values rodio, pipe, subprocess
+   * Possible values rodio, pipe, subprocess, js, capture
+   * The js backend delivers decoded audio to pcm_callback, or to the player's pcmStream if none is given
+   * The capture backend plays without an audio device and records what was played, see getCapture()
+   * Available backends are listed by getAudioBackends()
    */
   backend?: string
 
+  /**
+   * Output device of the backend, e.g. a sound card for rodio or a file path for pipe (Default the backend's default)
+   * Only rodio devices are checked, creating the player fails with INVALID_CONFIG if it isn't listed by getAudioBackends().
+   * pipe takes a file path and subprocess the command to run, the other backends pass the device to librespot unchecked
+   * and fail once playback starts if it doesn't exist. js and capture don't take a device
+   */
+  device?: string
+
+  /**
+   * Sample format the backend is opened with (Default F32)
+   * rodio only supports F32 and S16, js supports everything but S24_3
+   */
+  format?: "F64" | "F32" | "S32" | "S24" | "S24_3" | "S16"
+
+  /**
+   * Ditherer applied when converting to an integer format (Default tpdf)
+   */
+  ditherer?: "none" | "gpdf" | "tpdf" | "tpdf_hp"
+
+  /**
+   * Receives decoded audio when using the js backend.
+   * Returning false pauses playback until resumePcm() is called on the player
+   */
+  pcm_callback?: (frame: PcmFrame) => boolean | void
+
   /**
    * Enable gapless playback (Default false)
    */
   gapless?: boolean
 
   /**
-   * Bitrate to use.
+   * Bitrate to use (Default 320)
    * Possible values 96, 160, 320
    */
   bitrate?: "96" | "160" | "320"
 
+  /**
+   * Pass the encoded audio through to the backend without decoding it (Default false)
+   */
   passThrough?: boolean
 
   /**
@@ -88,19 +257,177 @@ export interface ConstructorConfig {
   connectConfig?: Partial<ConnectConfig>
 
   /**
-   * Mixer volume control type
+   * Mixer volume control type (Default log)
+   * Creating the player fails with INVALID_CONFIG for unknown values
    */
   volumeCtrl?: "cubic" | "fixed" | "linear" | "log",
 
+  /**
+   * Mixer config, software volume by default
+   */
+  mixer?: Partial<MixerConfig>
+
+  /**
+   * Session config, device identity and network settings
+   */
+  session?: Partial<SessionConfig>
+
   logLevel?: 'debug' | 'info' | 'trace' | 'warn' | 'error'
 }
 
+export interface PcmFrame {
+  /**
+   * Interleaved samples in native byte order
+   */
+  data: Buffer
+  sample_rate: number
+  channels: number
+  /**
+   * Sample format, e.g. F32 or S16
+   */
+  format: string
+}
+
+export interface MixerConfig {
+  /**
+   * softvol, or alsa for hardware volume when built with the alsa-backend feature (Default softvol)
+   */
+  name: "softvol" | "alsa"
+
+  /**
+   * Alsa mixer device (Default "default")
+   */
+  device: string
+
+  /**
+   * Alsa mixer control (Default "PCM")
+   */
+  control: string
+
+  /**
+   * Alsa mixer control index (Default 0)
+   */
+  index: number
+
+  /**
+   * Range of the cubic and log volume curves, between 0 and 100 (dB, Default 60)
+   */
+  volume_range: number
+}
+
+export interface AudioDevice {
+  name: string
+  is_default: boolean
+}
+
+export interface AudioBackend {
+  name: string
+  is_default: boolean
+  /**
+   * Devices to choose from, only listed for rodio. Empty for the other backends, whose device is free-form and not checked
+   */
+  devices: AudioDevice[]
+}
+
+export interface CapturedSegment {
+  /**
+   * Track being played, missing for audio written before any track was loaded
+   */
+  track_id?: string
+  /**
+   * Position the track was loaded or seeked to when the segment started
+   */
+  start_position_ms: number
+  /**
+   * Interleaved samples written, across all channels
+   */
+  samples: number
+  /**
+   * Length of the captured audio
+   */
+  duration_ms: number
+  /**
+   * Highest absolute sample value, 1.0 is full scale
+   */
+  peak: number
+  /**
+   * Time of the first and last write, in milliseconds since the capture was created or cleared
+   */
+  first_write_ms?: number
+  last_write_ms?: number
+  /**
+   * Number of times the sink was started and stopped, e.g. by pausing
+   */
+  starts: number
+  stops: number
+}
+
+export interface Capture {
+  sample_rate: number
+  channels: number
+  /**
+   * A new segment starts whenever a track is loaded, seeked or changes gaplessly
+   */
+  segments: CapturedSegment[]
+}
+
+export interface OAuthConfig {
+  /**
+   * Client id of the Spotify application (Default librespot's client id)
+   */
+  client_id: string
+
+  /**
+   * Port of the loopback listener receiving the redirect, 0 picks a free port (Default 8898)
+   * The redirect uri is http://127.0.0.1:<port>/login
+   */
+  redirect_port: number
+
+  /**
+   * Scopes to request (Default ["streaming"])
+   */
+  scopes: TokenScope[]
+
+  /**
+   * Authorization endpoint (Default https://accounts.spotify.com/authorize)
+   */
+  auth_url: string
+
+  /**
+   * Token endpoint (Default https://accounts.spotify.com/api/token)
+   */
+  token_url: string
+
+  /**
+   * How long to wait for the login to be completed in the browser (milliseconds, Default 300000)
+   */
+  timeout: number
+}
+
 export interface AuthDetails {
   username: string
   password: string
+
+  /**
+   * Base64 encoded reusable credentials, as emitted by the CredentialsStored event.
+   * Used with authType AUTHENTICATION_STORED_SPOTIFY_CREDENTIALS
+   */
+  authData?: string
+
+  /**
+   * Login with the credentials saved under cache.credentials_location, without a password
+   */
+  fromCache?: boolean
+
+  /**
+   * Login through the browser with OAuth instead of a password.
+   * The URL to open is emitted with an AuthorizationUrl event
+   */
+  oauth?: Partial<OAuthConfig>
+
   authType?:
     | "AUTHENTICATION_USER_PASS"
-    | "AUTHENTICATION_USER_PASS"
+    | "AUTHENTICATION_STORED_SPOTIFY_CREDENTIALS"
     | "AUTHENTICATION_STORED_FACEBOOK_CREDENTIALS"
     | "AUTHENTICATION_SPOTIFY_TOKEN"
     | "AUTHENTICATION_FACEBOOK_TOKEN"
@@ -131,10 +458,26 @@ export type PlayerEventTypes =
   | "AutoPlayChanged"
   | "PlayerInitialized"
   | "TimeUpdated"
+  | "PositionChanged"
   | "InitializationError"
+  | "CredentialsDiscovered"
+  | "CredentialsStored"
+  | "AuthorizationUrl"
+  | "TokenRefreshed"
+  | "QueueChanged"
+  | "Reconnecting"
+  | "Reconnected"
+  | "ReconnectFailed"
+  | "ConfigChanged"
+  | "ConfigWarning"
 
 export type PlayerEvent<T extends PlayerEventTypes = "InitializationError"> = {
   event: T
+
+  /**
+   * Identifies the native player instance which emitted the event
+   */
+  instance_id?: number
 } & (T extends "Stopped"
   ? {
       play_request_id: bigint
@@ -195,7 +538,7 @@ export type PlayerEvent<T extends PlayerEventTypes = "InitializationError"> = {
     }
   : T extends "TrackChanged"
   ? {
-      audio_item: string
+      audio_item: AudioItem
     }
   : T extends "SessionConnected"
   ? {
@@ -234,12 +577,106 @@ export type PlayerEvent<T extends PlayerEventTypes = "InitializationError"> = {
   ? {
       position_ms: number
     }
+  : T extends "PositionChanged"
+  ? {
+      position_ms: number
+      track_id?: string
+    }
   : T extends "PlayerInitialized"
   ? undefined
   : T extends "InitializationError"
-  ? { error: Error }
+  ? { error: NativeError }
+  : T extends "QueueChanged"
+  ? Queue
+  : T extends "Reconnecting"
+  ? {
+      attempt: number
+      delay_ms: number
+    }
+  : T extends "Reconnected"
+  ? {
+      attempts: number
+    }
+  : T extends "ReconnectFailed"
+  ? {
+      attempts: number
+      error: NativeError
+    }
+  : T extends "ConfigChanged"
+  ? {
+      bitrate: "96" | "160" | "320"
+      gapless: boolean
+      passThrough: boolean
+      normalization: boolean
+      backend: string
+      format: string
+      device?: string
+    }
+  : T extends "ConfigWarning"
+  ? {
+      /**
+       * Dotted paths of the config keys which were ignored, most likely typos
+       */
+      unknown_keys: string[]
+    }
+  : T extends "TokenRefreshed"
+  ? {
+      token: Token
+    }
+  : T extends "AuthorizationUrl"
+  ? {
+      url: string
+    }
+  : T extends "CredentialsDiscovered" | "CredentialsStored"
+  ? {
+      username: string
+      auth_type: string
+      auth_data: string
+    }
   : unknown)
 
+/**
+ * Options for loading a context (album, playlist, artist or show) through SPIRC
+ */
+export interface ContextLoadOptions {
+  /**
+   * Tracks of the context. Resolved from the context URI if not provided
+   */
+  tracks?: string[]
+
+  /**
+   * Index of the track to start playing from (Default 0)
+   */
+  startIndex?: number
+
+  /**
+   * URI of the track to start playing from. Takes precedence over startIndex
+   */
+  startTrack?: string
+
+  /**
+   * Position in milliseconds to start the first track from (Default 0)
+   */
+  positionMs?: number
+
+  /**
+   * Start playing immediately after loading (Default false)
+   */
+  autoPlay?: boolean
+
+  shuffle?: boolean
+  repeat?: boolean
+}
+
+export type RepeatMode = "off" | "one" | "all"
+
+export interface Queue {
+  tracks: string[]
+  current_index?: number
+  repeat: RepeatMode
+  shuffle: boolean
+}
+
 export type TokenScope =
   | "ugc-image-upload"
   | "user-read-playback-state"
@@ -264,7 +701,15 @@ export type TokenScope =
 export type Token = {
   access_token: string
   token_type: "Bearer"
+
+  /**
+   * Milliseconds until the token expires, at the time it was returned
+   */
   expires_in: number
+
+  /**
+   * Unix timestamp in milliseconds at which the token expires
+   */
   expiry_from_epoch: number
   scopes: TokenScope[]
 }
@@ -274,6 +719,12 @@ export interface CanvazResponse {
   ttl_in_seconds: number
 }
 
+export interface CanvazBatchResponse {
+  canvases: Record<string, Canvaz[]>
+  misses: string[]
+  ttl_in_seconds: number
+}
+
 export interface Canvaz {
   id: string
   url: string
@@ -294,35 +745,239 @@ export interface Artist {
   avatar: string
 }
 
-export interface LyricsResponse {
-  lyrics: Lyrics
-  colors: Colors
-  hasVocalRemoval: boolean
-}
+export type LyricsSyncType = "line" | "syllable" | "unsynced"
 
-export interface Lyrics {
-  syncType: string
-  lines: Line[]
-  provider: string
-  providerLyricsId: string
-  providerDisplayName: string
-  syncLyricsUri: string
-  isDenseTypeface: boolean
-  alternatives: any[]
+export interface LyricsResponse {
+  sync_type: LyricsSyncType
+  lines: LyricsLine[]
   language: string
-  isRtlLanguage: boolean
-  fullscreenAction: string
+  is_rtl_language: boolean
+  provider: string
+  provider_lyrics_id: string
+  provider_display_name: string
+  has_vocal_removal: boolean
+  colors?: LyricsColors
 }
 
-export interface Line {
-  startTimeMs: string
+export interface LyricsLine {
+  start_time_ms: number
+  end_time_ms: number
   words: string
-  syllables: any[]
-  endTimeMs: string
+  syllables: LyricsSyllable[]
+}
+
+export interface LyricsSyllable {
+  start_time_ms: number
+  num_chars: number
 }
 
-export interface Colors {
+/**
+ * Colors are signed 32 bit ARGB integers
+ */
+export interface LyricsColors {
   background: number
   text: number
-  highlightText: number
+  highlight_text: number
+}
+
+export interface MetadataImage {
+  url: string
+  width: number
+  height: number
+  size: string
+}
+
+export interface MetadataArtistRef {
+  uri: string
+  name: string
+}
+
+export interface MetadataRestriction {
+  catalogues: string[]
+  countries_allowed?: string[]
+  countries_forbidden?: string[]
+}
+
+export interface TrackMetadata {
+  type: "track"
+  uri: string
+  name: string
+  duration_ms: number
+  artists: MetadataArtistRef[]
+  album: AlbumMetadata
+  covers: MetadataImage[]
+  track_number: number
+  disc_number: number
+  explicit: boolean
+  popularity: number
+  has_lyrics: boolean
+  restrictions: MetadataRestriction[]
+  alternatives: string[]
+}
+
+export interface AlbumMetadata {
+  type: "album"
+  uri: string
+  name: string
+  artists: MetadataArtistRef[]
+  album_type: string
+  label: string
+  /**
+   * Unix timestamp in milliseconds
+   */
+  release_date: number
+  popularity: number
+  genres: string[]
+  covers: MetadataImage[]
+  tracks: string[]
+  restrictions: MetadataRestriction[]
+}
+
+export interface ArtistMetadata {
+  type: "artist"
+  uri: string
+  name: string
+  popularity: number
+  genres: string[]
+  portraits: MetadataImage[]
+  top_tracks: string[]
+  albums: string[]
+  singles: string[]
+  related: MetadataArtistRef[]
+  restrictions: MetadataRestriction[]
+}
+
+export interface PlaylistMetadata {
+  type: "playlist"
+  uri: string
+  name: string
+  description: string
+  collaborative: boolean
+  length: number
+  tracks: string[]
+}
+
+export interface EpisodeMetadata {
+  type: "episode"
+  uri: string
+  name: string
+  show_name: string
+  description: string
+  duration_ms: number
+  /**
+   * Unix timestamp in milliseconds
+   */
+  publish_time: number
+  language: string
+  explicit: boolean
+  covers: MetadataImage[]
+  restrictions: MetadataRestriction[]
+}
+
+export interface ShowMetadata {
+  type: "show"
+  uri: string
+  name: string
+  description: string
+  publisher: string
+  language: string
+  explicit: boolean
+  covers: MetadataImage[]
+  episodes: string[]
+  restrictions: MetadataRestriction[]
+}
+
+export type Metadata =
+  | TrackMetadata
+  | AlbumMetadata
+  | ArtistMetadata
+  | PlaylistMetadata
+  | EpisodeMetadata
+  | ShowMetadata
+
+export interface AudioItemCover {
+  url: string
+  width: number
+  height: number
+  size: string
+}
+
+export interface AudioItemTrackFields {
+  type: "track"
+  artists: (MetadataArtistRef & { role: string })[]
+  album: string
+  album_artists: string[]
+  popularity: number
+  number: number
+  disc_number: number
+}
+
+export interface AudioItemEpisodeFields {
+  type: "episode"
+  description: string
+  /**
+   * Unix timestamp in milliseconds
+   */
+  publish_time: number
+  show_name: string
+}
+
+export interface AudioItem {
+  track_id: string
+  uri: string
+  name: string
+  duration_ms: number
+  covers: AudioItemCover[]
+  language: string[]
+  explicit: boolean
+  available: boolean
+  unavailable_reason?: string
+  alternatives?: string[]
+  unique_fields: AudioItemTrackFields | AudioItemEpisodeFields
+}
+
+export type NativeErrorCode =
+  | "AUTH_FAILED"
+  | "PREMIUM_REQUIRED"
+  | "INVALID_URI"
+  | "INVALID_ARGUMENT"
+  | "INVALID_CONFIG"
+  | "NOT_FOUND"
+  | "NETWORK"
+  | "UNAVAILABLE"
+  | "RATE_LIMITED"
+  | "PARSE_ERROR"
+  | "IO_ERROR"
+  | "PLAYER_CLOSED"
+  | "INTERNAL"
+
+/**
+ * Error thrown by the native module
+ */
+export interface NativeError extends Error {
+  code: NativeErrorCode
+
+  /**
+   * Underlying error reported by librespot, if any
+   */
+  cause?: Error
+
+  /**
+   * Extra details such as the URI the call failed for
+   */
+  context: Record<string, string>
+
+  /**
+   * Every problem found in the config, set on INVALID_CONFIG errors raised while reading it
+   */
+  issues?: ConfigIssue[]
+}
+
+export interface ConfigIssue {
+  /**
+   * Dotted path of the value, e.g. connectConfig.initialVolume
+   */
+  path: string
+  kind: "missing" | "wrong_type" | "unknown_value" | "out_of_range"
+  message: string
 }
@@ -2,7 +2,7 @@ import https, { RequestOptions } from "https"
 import { TokenScope } from "./types"
 
 export const TRACK_REGEX = new RegExp(
-  /^(?<urlType>(?:spotify:|(?:https?:\/\/(?:open|play)\.spotify\.com\/)))(?:embed)?\/?(?<type>album|track|playlist|artist)(?::|\/)((?:[0-9a-zA-Z]){22})/
+  /^(?<urlType>(?:spotify:|(?:https?:\/\/(?:open|play)\.spotify\.com\/)))(?:embed)?\/?(?<type>album|track|playlist|artist|show|episode)(?::|\/)((?:[0-9a-zA-Z]){22})/
 )
 
 export function request<T>(url: string, config: FetchConfig): Promise<T> {
@@ -9,7 +9,7 @@ interface FetchConfig {
 interface LibrespotModule {
   // Non spirc player
   create_player: (
-    config: FullConstructorConfig,
+    config: NativeConfig,
     callback: (event: PlayerEvent) => void
   ) => Promise<PlayerNativeObject>
 
@@ -17,9 +17,18 @@ interface LibrespotModule {
   pause: () => Promise<void>
   seek: (timeMs: number) => Promise<void>
   set_volume: (volume: number) => Promise<void>
+  get_volume: () => number | undefined
   close_player: () => Promise<void>
+  update_config: (config: NativeConfig) => Promise<void>
   get_device_id: () => string
-  get_token: (scopes: string) => Promise<Token | undefined>
+  get_token: (scopes: string[]) => Promise<Token>
+  resume_pcm: () => void
+  get_capture: () => import("../src/types").Capture
+  clear_capture: () => void
+  add_event_listener: (callback: (event: PlayerEvent) => void) => number
+  remove_event_listener: (listenerId: number) => boolean
+  get_instance_id: () => number
+  get_position: () => number
   load_track: (
     trackUri: string,
     autoPlay: boolean,
@@ -28,11 +37,38 @@ interface LibrespotModule {
   get_canvas: (
     trackUri: string
   ) => Promise<import("../src/types").CanvazResponse>
-  get_lyrics: (trackUri: string) => Promise<string>
+  get_canvas_file: (trackUri: string) => Promise<string | undefined>
+  get_canvases: (
+    trackUris: string[]
+  ) => Promise<import("../src/types").CanvazBatchResponse>
+  get_lyrics: (
+    trackUri: string
+  ) => Promise<import("../src/types").LyricsResponse | undefined>
+  get_metadata: (uri: string) => Promise<import("../src/types").Metadata>
+  queue_add: (trackUris: string[]) => Promise<void>
+  queue_insert: (index: number, trackUris: string[]) => Promise<void>
+  queue_remove: (index: number) => Promise<void>
+  queue_move: (from: number, to: number) => Promise<void>
+  queue_clear: () => Promise<void>
+  queue_play: (
+    index: number,
+    autoPlay: boolean,
+    start_pos: number
+  ) => Promise<void>
+  queue_next: () => Promise<void>
+  queue_previous: () => Promise<void>
+  queue_set_repeat: (mode: import("../src/types").RepeatMode) => Promise<void>
+  queue_set_shuffle: (shuffle: boolean) => Promise<void>
+  get_queue: () => Promise<import("../src/types").Queue>
 
   // Spirc player
   create_player_spirc: (
-    config: FullConstructorConfig,
+    config: NativeConfig,
+    callback: (event: PlayerEvent) => void
+  ) => Promise<PlayerNativeObject>
+
+  create_player_spirc_discovery: (
+    config: NativeConfig,
     callback: (event: PlayerEvent) => void
   ) => Promise<PlayerNativeObject>
 
@@ -40,27 +76,50 @@ interface LibrespotModule {
   pause_spirc: () => Promise<void>
   seek_spirc: (timeMs: number) => Promise<void>
   set_volume_spirc: (volume: number) => Promise<void>
+  get_volume_spirc: () => number | undefined
+  play_pause_spirc: () => Promise<void>
+  next_spirc: () => Promise<void>
+  prev_spirc: () => Promise<void>
+  volume_up_spirc: () => Promise<void>
+  volume_down_spirc: () => Promise<void>
+  shuffle_spirc: (shuffle: boolean) => Promise<void>
+  repeat_spirc: (repeat: boolean) => Promise<void>
+  activate_spirc: () => Promise<void>
+  disconnect_spirc: () => Promise<void>
   close_player_spirc: () => Promise<void>
+  update_config_spirc: (config: NativeConfig) => Promise<void>
   get_device_id_spirc: () => string
-  get_token_spirc: (scopes: string) => Promise<Token | undefined>
+  get_token_spirc: (scopes: string[]) => Promise<Token>
+  resume_pcm_spirc: () => void
+  get_capture_spirc: () => import("../src/types").Capture
+  clear_capture_spirc: () => void
+  add_event_listener_spirc: (callback: (event: PlayerEvent) => void) => number
+  remove_event_listener_spirc: (listenerId: number) => boolean
+  get_instance_id_spirc: () => number
+  get_position_spirc: () => number
   get_canvas_spirc: (
     trackUri: string
   ) => Promise<import("../src/types").CanvazResponse>
-  get_lyrics_spirc: (trackUri: string) => Promise<string>
+  get_canvas_file_spirc: (trackUri: string) => Promise<string | undefined>
+  get_canvases_spirc: (
+    trackUris: string[]
+  ) => Promise<import("../src/types").CanvazBatchResponse>
+  get_lyrics_spirc: (
+    trackUri: string
+  ) => Promise<import("../src/types").LyricsResponse | undefined>
+  get_metadata_spirc: (uri: string) => Promise<import("../src/types").Metadata>
   load_track_spirc: (trackUri: string, autoPlay: boolean) => Promise<void>
+  load_context_spirc: (
+    contextUri: string,
+    options: import("../src/types").ContextLoadOptions
+  ) => Promise<void>
   init_logger: (logLevel: string) => void
+  get_audio_backends: () => Promise<import("../src/types").AudioBackend[]>
 }
 
-interface FullConstructorConfig {
-  auth: AuthDetails
-  pos_update_interval: number
-  backend: string
-  gapless: boolean
-  bitrate: "96" | "160" | "320"
-  passThrough: boolean
-  normalizationConfig: NormalizationConfig
-  connectConfig: ConnectConfig
-  cache: CacheConfig
-}
+/**
+ * Config passed to the native module, which validates it and applies the defaults
+ */
+type NativeConfig = import("../src/types").ConstructorConfig
 
 type PlayerNativeObject = never